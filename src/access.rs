@@ -0,0 +1,455 @@
+//! API keys for the data routes, with an audit trail of what each key accessed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::http::{header, HeaderValue, Request, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::admin;
+use crate::cluster::Cluster;
+use crate::error::Error;
+use crate::quota::{Quota, Quotas};
+
+/// How often the audit log is flushed and the daily counts saved.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The requests of each key to each dataset in a day, keyed by the key name.
+pub type Rollup = BTreeMap<String, BTreeMap<String, u64>>;
+
+/// The tiles in a response, for the routes serving more than one.
+#[derive(Clone, Copy, Debug)]
+pub struct TileCount(pub u64);
+
+/// An entry of the keys file, either the name of the key or its settings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyConfig {
+    Name(String),
+    Key { name: String, quota: Option<Quota> },
+}
+
+struct KeyInfo {
+    name: String,
+    quota: Option<Quota>,
+}
+
+impl From<KeyConfig> for KeyInfo {
+    fn from(config: KeyConfig) -> Self {
+        match config {
+            KeyConfig::Name(name) => Self { name, quota: None },
+            KeyConfig::Key { name, quota } => Self { name, quota },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyQuery {
+    api_key: Option<String>,
+}
+
+/// A line of the audit log.
+#[derive(Serialize)]
+struct Record {
+    time: String,
+    key: String,
+    path: String,
+    dataset: Option<String>,
+    tile: Option<String>,
+    status: u16,
+    #[serde(skip)]
+    day: String,
+}
+
+/// The API keys and the audit trail of their requests.
+pub struct Access {
+    keys: BTreeMap<String, KeyInfo>,
+    quotas: Arc<Quotas>,
+    records: Sender<Record>,
+    rollups: Arc<Mutex<BTreeMap<String, Rollup>>>,
+    dir: PathBuf,
+}
+
+/// Converts a number of days since 1970-01-01 to a year, month and day.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Returns the UTC date and RFC 3339 timestamp of a time.
+fn format_time(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_secs() as i64);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let secs = secs.rem_euclid(86400);
+    let date = format!("{:04}-{:02}-{:02}", year, month, day);
+    let timestamp = format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        date,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    (date, timestamp)
+}
+
+/// Returns the dataset and tile a request is for, from paths like `/tile/file.tif/3/4/2`.
+pub fn request_target(path: &str) -> (Option<&str>, Option<String>) {
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["admin", ..] => (None, None),
+        ["tile" | "composite", dataset, z, x, y] => {
+            (Some(dataset), Some(format!("{}/{}/{}", z, x, y)))
+        }
+        [_, dataset, ..] => (Some(dataset), None),
+        _ => (None, None),
+    }
+}
+
+/// Hides the value of the `api_key` parameters of a query string, to log it.
+pub fn redact_query(query: &str) -> String {
+    let is_key = |pair: &str| {
+        // the name is decoded, like when reading the key
+        let name = pair.split('=').next().unwrap_or_default();
+        serde_urlencoded::from_str::<Vec<(String, String)>>(name)
+            .is_ok_and(|pairs| pairs.iter().any(|(name, _)| name == "api_key"))
+    };
+    query
+        .split('&')
+        .map(|pair| {
+            if is_key(pair) {
+                "api_key=redacted"
+            } else {
+                pair
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Returns the path and query of a request, without the API key passed in the query.
+pub fn redact_uri(uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) => format!("{}?{}", uri.path(), redact_query(query)),
+        None => uri.path().to_string(),
+    }
+}
+
+fn rollup_path(dir: &Path, day: &str) -> PathBuf {
+    dir.join(format!("rollup-{}.json", day))
+}
+
+fn read_rollup(dir: &Path, day: &str) -> Rollup {
+    std::fs::read(rollup_path(dir, day))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_rollup(dir: &Path, day: &str, rollup: &Rollup) -> io::Result<()> {
+    let path = rollup_path(dir, day);
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, serde_json::to_vec_pretty(rollup)?)?;
+    std::fs::rename(temp, path)
+}
+
+/// Appends the records to a log file per day, like `access-2024-05-01.jsonl`, and keeps the
+/// daily counts of each key and the quota usage up to date.
+fn write_audit(
+    dir: PathBuf,
+    records: Receiver<Record>,
+    rollups: Arc<Mutex<BTreeMap<String, Rollup>>>,
+    quotas: Arc<Quotas>,
+) {
+    let mut log: Option<(String, BufWriter<File>)> = None;
+    let mut changed = BTreeSet::new();
+    let mut flushed = Instant::now();
+    loop {
+        let record = records.recv_timeout(FLUSH_INTERVAL);
+        if let Ok(record) = &record {
+            if log.as_ref().map(|(day, _)| day) != Some(&record.day) {
+                let path = dir.join(format!("access-{}.jsonl", record.day));
+                match OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => log = Some((record.day.clone(), BufWriter::new(file))),
+                    Err(e) => tracing::warn!("cannot open {}: {}", path.display(), e),
+                }
+            }
+            if let Some((_, file)) = &mut log {
+                let line = serde_json::to_string(record).map_err(io::Error::from);
+                if let Err(e) = line.and_then(|line| writeln!(file, "{}", line)) {
+                    tracing::warn!("cannot write to the audit log: {}", e);
+                }
+            }
+            let mut rollups = rollups.lock().unwrap();
+            let rollup = rollups
+                .entry(record.day.clone())
+                .or_insert_with(|| read_rollup(&dir, &record.day));
+            let dataset = record.dataset.clone().unwrap_or_default();
+            *rollup
+                .entry(record.key.clone())
+                .or_default()
+                .entry(dataset)
+                .or_default() += 1;
+            changed.insert(record.day.clone());
+        }
+        let disconnected = matches!(record, Err(RecvTimeoutError::Disconnected));
+        if flushed.elapsed() >= FLUSH_INTERVAL || disconnected {
+            if let Some((_, file)) = &mut log {
+                if let Err(e) = file.flush() {
+                    tracing::warn!("cannot write to the audit log: {}", e);
+                }
+            }
+            if let Err(e) = quotas.save() {
+                tracing::warn!("cannot save the quota usage: {}", e);
+            }
+            let mut rollups = rollups.lock().unwrap();
+            for day in std::mem::take(&mut changed) {
+                if let Err(e) = save_rollup(&dir, &day, &rollups[&day]) {
+                    tracing::warn!("cannot save the audit counts of {}: {}", day, e);
+                }
+            }
+            // only today's counts are still changing
+            let (today, _) = format_time(SystemTime::now());
+            rollups.retain(|day, _| *day >= today);
+            flushed = Instant::now();
+        }
+        if disconnected {
+            return;
+        }
+    }
+}
+
+impl Access {
+    /// Reads the keys from a JSON file mapping them to their names, like
+    /// `{"3f9a...": "partner-a"}`, or to their name and quota, and starts writing the audit
+    /// trail and the quota usage to a directory.
+    pub fn open(keys_path: &Path, dir: PathBuf) -> Result<Self, Error> {
+        let keys: BTreeMap<String, KeyConfig> =
+            serde_json::from_slice(&std::fs::read(keys_path)?).map_err(io::Error::from)?;
+        let keys = keys
+            .into_iter()
+            .map(|(key, config)| (key, config.into()))
+            .collect();
+        std::fs::create_dir_all(&dir)?;
+        let quotas = Arc::new(Quotas::load(dir.join("usage.json")));
+        let (records, receiver) = mpsc::channel();
+        let rollups = Arc::new(Mutex::new(BTreeMap::new()));
+        {
+            let (dir, rollups, quotas) = (dir.clone(), rollups.clone(), quotas.clone());
+            thread::Builder::new()
+                .name("audit".to_string())
+                .spawn(move || write_audit(dir, receiver, rollups, quotas))?;
+        }
+        Ok(Self {
+            keys,
+            quotas,
+            records,
+            rollups,
+            dir,
+        })
+    }
+
+    fn key_info(&self, key: &str) -> Option<&KeyInfo> {
+        self.keys
+            .iter()
+            .find(|(k, _)| admin::tokens_match(k.as_bytes(), key.as_bytes()))
+            .map(|(_, info)| info)
+    }
+
+    fn record(&self, key: &str, path: &str, status: StatusCode) {
+        let (day, time) = format_time(SystemTime::now());
+        let (dataset, tile) = request_target(path);
+        let record = Record {
+            time,
+            key: key.to_string(),
+            path: path.to_string(),
+            dataset: dataset.map(str::to_string),
+            tile,
+            status: status.as_u16(),
+            day,
+        };
+        // the writer only stops with the process
+        let _ = self.records.send(record);
+    }
+
+    /// Returns the requests of each key to each dataset on a day, like `2024-05-01`, or today.
+    pub fn rollup(&self, day: Option<&str>) -> Result<Rollup, Error> {
+        let (today, _) = format_time(SystemTime::now());
+        let day = day.unwrap_or(&today);
+        let valid = day.len() == 10
+            && day.bytes().enumerate().all(|(i, b)| {
+                if i == 4 || i == 7 {
+                    b == b'-'
+                } else {
+                    b.is_ascii_digit()
+                }
+            });
+        if !valid {
+            return Err(Error::BadRequest(format!("invalid date: {}", day)));
+        }
+        let rollups = self.rollups.lock().unwrap();
+        Ok(match rollups.get(day) {
+            Some(rollup) => rollup.clone(),
+            None => read_rollup(&self.dir, day),
+        })
+    }
+}
+
+/// Rejects the requests without a valid key, passed in the `X-Api-Key` header or the `api_key`
+/// query parameter, or over its hard quota, and records the others, when API keys are
+/// configured.
+///
+/// The responses of the keys with quotas carry the tiles left in `X-Quota-Remaining`, and
+/// `X-Quota-Warning` once they are over the soft limit.
+pub async fn authenticate<B>(request: Request<B>, next: Next<B>) -> Response {
+    let access = match request.extensions().get::<Arc<Access>>() {
+        Some(access) => access.clone(),
+        None => return next.run(request).await,
+    };
+    // already checked and counted by the peer forwarding it
+    let forwarded = request
+        .extensions()
+        .get::<Arc<Cluster>>()
+        .is_some_and(|cluster| cluster.is_trusted(&request));
+    if forwarded {
+        return next.run(request).await;
+    }
+    let provided = request
+        .headers()
+        .get("x-api-key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            let query = request.uri().query()?;
+            serde_urlencoded::from_str::<KeyQuery>(query).ok()?.api_key
+        });
+    let key = match provided.as_deref().and_then(|key| access.key_info(key)) {
+        Some(key) => key,
+        None => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "ApiKey")],
+            )
+                .into_response()
+        }
+    };
+    let path = request.uri().path().to_string();
+    if let Some(retry_after) = key
+        .quota
+        .as_ref()
+        .and_then(|quota| access.quotas.check(&key.name, quota))
+    {
+        access.record(&key.name, &path, StatusCode::TOO_MANY_REQUESTS);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "the quota of the API key is exhausted",
+        )
+            .into_response();
+    }
+    let mut response = next.run(request).await;
+    access.record(&key.name, &path, response.status());
+    if let Some(quota) = &key.quota {
+        let tiles = match response.extensions().get::<TileCount>() {
+            Some(&TileCount(tiles)) => tiles,
+            None => {
+                let (_, tile) = request_target(&path);
+                (tile.is_some() && response.status().is_success()) as u64
+            }
+        };
+        let remaining = access.quotas.add(&key.name, quota, tiles);
+        let headers = response.headers_mut();
+        if let Some(tiles) = remaining.tiles {
+            headers.insert("x-quota-remaining", HeaderValue::from(tiles));
+        }
+        if remaining.soft_exceeded {
+            headers.insert(
+                "x-quota-warning",
+                HeaderValue::from_static("over the soft quota"),
+            );
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Uri;
+
+    use super::{civil_from_days, redact_query, redact_uri, request_target};
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+        // each day follows the previous one
+        let mut previous = civil_from_days(-800);
+        for days in -799..800 {
+            let (year, month, day) = civil_from_days(days);
+            let next_day = (previous.0, previous.1, previous.2 + 1);
+            let next_month = (previous.0, previous.1 + 1, 1);
+            let next_year = (previous.0 + 1, 1, 1);
+            assert!([next_day, next_month, next_year].contains(&(year, month, day)));
+            previous = (year, month, day);
+        }
+    }
+
+    #[test]
+    fn request_targets() {
+        assert_eq!(
+            request_target("/tile/a.tif/3/4/2"),
+            (Some("a.tif"), Some("3/4/2".to_string()))
+        );
+        assert_eq!(
+            request_target("/composite/a,b/1/0/1"),
+            (Some("a,b"), Some("1/0/1".to_string()))
+        );
+        assert_eq!(request_target("/tile/a.tif/3/4"), (Some("a.tif"), None));
+        assert_eq!(request_target("/tilejson/a.tif"), (Some("a.tif"), None));
+        assert_eq!(request_target("/batch/a.tif"), (Some("a.tif"), None));
+        assert_eq!(request_target("/admin/datasets/a.tif"), (None, None));
+        assert_eq!(request_target("/catalog"), (None, None));
+        assert_eq!(request_target("/"), (None, None));
+    }
+
+    #[test]
+    fn redacts_api_keys() {
+        assert_eq!(
+            redact_query("api_key=secret&scale=db"),
+            "api_key=redacted&scale=db"
+        );
+        assert_eq!(
+            redact_query("a=1&api%5Fkey=secret&api_key"),
+            "a=1&api_key=redacted&api_key=redacted"
+        );
+        assert_eq!(redact_query("my_api_key=1&b"), "my_api_key=1&b");
+        let uri = Uri::from_static("/tile/a.tif/1/2/3?api_key=secret");
+        assert_eq!(redact_uri(&uri), "/tile/a.tif/1/2/3?api_key=redacted");
+        let uri = Uri::from_static("/tile/a.tif/1/2/3");
+        assert_eq!(redact_uri(&uri), "/tile/a.tif/1/2/3");
+    }
+}
@@ -0,0 +1,468 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use axum::extract::{self, Extension};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task;
+
+use crate::access::{Access, Rollup};
+use crate::archive;
+use crate::cache;
+use crate::config::{Config, DatasetConfig, DatasetInfo, PoolConfig};
+use crate::dataset;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::jobs::{JobInfo, JobSpec, Jobs};
+use crate::memory_cache;
+use crate::prefetch;
+use crate::prune;
+use crate::raster_info;
+use crate::registry::{self, Kind, Registry};
+use crate::render;
+use crate::workers;
+
+#[derive(Deserialize)]
+pub struct PurgeQuery {
+    dataset: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    date: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DatasetEntry {
+    name: String,
+    path: PathBuf,
+    /// The file a variable was found in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    #[serde(flatten)]
+    info: DatasetInfo,
+}
+
+/// Limits that can be changed while the server runs. Omitted fields are left as they are.
+#[derive(Deserialize, Serialize)]
+pub struct Tuning {
+    /// The size of the GDAL block cache, in bytes.
+    gdal_cache_size: Option<u64>,
+    worker_threads: Option<usize>,
+    worker_queue_depth: Option<usize>,
+    pool_size: Option<usize>,
+    /// In seconds.
+    pool_idle_timeout: Option<f64>,
+    pool_max_datasets: Option<usize>,
+}
+
+impl Tuning {
+    fn current(pool: &DatasetPool) -> Self {
+        let workers = workers::stats();
+        let pool = pool.config();
+        Self {
+            gdal_cache_size: Some(unsafe { gdal_sys::GDALGetCacheMax64() } as u64),
+            worker_threads: Some(workers.threads),
+            worker_queue_depth: Some(workers.queue_depth),
+            pool_size: Some(pool.size),
+            pool_idle_timeout: Some(pool.idle_timeout.as_secs_f64()),
+            pool_max_datasets: Some(pool.max_datasets),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.worker_threads == Some(0) {
+            return Err(Error::BadRequest(
+                "worker_threads must be at least 1".to_string(),
+            ));
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            if Duration::try_from_secs_f64(timeout).is_err() {
+                return Err(Error::BadRequest(format!(
+                    "invalid pool_idle_timeout: {}",
+                    timeout
+                )));
+            }
+        }
+        if let Some(size) = self.gdal_cache_size {
+            if size > i64::MAX as u64 {
+                return Err(Error::BadRequest(format!(
+                    "invalid gdal_cache_size: {}",
+                    size
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, Serialize)]
+pub struct CacheUsage {
+    files: usize,
+    bytes: u64,
+}
+
+/// When the router was built.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Compares two secrets in constant time.
+pub fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Rejects requests without the configured bearer token, hiding the admin API when none is set.
+async fn authorize<B>(request: Request<B>, next: Next<B>) -> Response {
+    let token = request
+        .extensions()
+        .get::<Config>()
+        .and_then(|config| config.admin_token.clone());
+    let token = match token {
+        Some(token) => token,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if tokens_match(provided.as_bytes(), token.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+fn cache_dirs() -> [PathBuf; 2] {
+    [cache::dir(), cache::dir().join("thumbnails")]
+}
+
+/// Removes the cached images of a dataset, or of all datasets. Returns the number of files removed.
+///
+/// Only the finished PNGs are removed, leaving the temporary and lock files of the tiles being
+/// rendered to their writers.
+pub fn purge_cache(dataset: Option<&str>) -> io::Result<usize> {
+    let prefix = dataset.map(cache::entry_prefix);
+    memory_cache::purge(prefix.as_deref());
+    let mut removed = 0;
+    for dir in cache_dirs().iter() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let matches = match &prefix {
+                Some(prefix) => name.starts_with(prefix.as_str()),
+                None => !name.starts_with('.'),
+            };
+            if matches && name.ends_with(".png") && entry.file_type()?.is_file() {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
+
+fn dir_usage(dir: &Path, usage: &mut CacheUsage) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            usage.files += 1;
+            usage.bytes += metadata.len();
+        }
+    }
+    Ok(())
+}
+
+fn cache_usage() -> io::Result<CacheUsage> {
+    let mut usage = CacheUsage::default();
+    for dir in cache_dirs().iter() {
+        dir_usage(dir, &mut usage)?;
+    }
+    Ok(usage)
+}
+
+fn entries(registry: &Registry) -> Vec<DatasetEntry> {
+    registry
+        .entries()
+        .into_iter()
+        .map(|(name, entry)| DatasetEntry {
+            name,
+            path: entry.path,
+            parent: entry.parent,
+            info: entry.info,
+        })
+        .collect()
+}
+
+async fn reload(
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+) -> Result<Json<Value>, Error> {
+    let before = registry.entries();
+    let count = {
+        let registry = registry.0.clone();
+        workers::run(move || Ok(registry.reload()?)).await?
+    };
+    pool.clear();
+    raster_info::clear();
+    let removed = before
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| registry.get(name).is_err())
+        .collect();
+    prune::schedule(registry.0.clone(), removed);
+    Ok(Json(json!({ "datasets": count })))
+}
+
+async fn list_datasets(registry: Extension<Arc<Registry>>) -> Json<Vec<DatasetEntry>> {
+    Json(entries(&registry))
+}
+
+async fn add_dataset(
+    extract::Path(name): extract::Path<String>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+    Json(request): Json<DatasetConfig>,
+) -> Result<StatusCode, Error> {
+    registry::validate_name(&name)?;
+    let entry = {
+        let registry = registry.clone();
+        let name = name.clone();
+        workers::run(move || {
+            let entry = registry.entry(&name, request)?;
+            match entry.kind {
+                Kind::Raster | Kind::GeoPackage => drop(dataset::open(&entry.path)?),
+                Kind::MbTiles => drop(archive::open_sqlite(&entry.path)?),
+                Kind::PmTiles => drop(entry.pmtiles()?),
+                Kind::Stac => {
+                    if let Some(mosaic) = &entry.mosaic {
+                        mosaic.validate()?;
+                    }
+                }
+                Kind::Wms => {
+                    if let Some(wms) = &entry.wms {
+                        wms.validate(&config.remote)?;
+                    }
+                }
+            }
+            purge_cache(Some(&name))?;
+            Ok(entry)
+        })
+        .await?
+    };
+    pool.clear();
+    raster_info::clear();
+    let status = match registry.insert(name, entry) {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::CREATED,
+    };
+    Ok(status)
+}
+
+async fn remove_dataset(
+    extract::Path(name): extract::Path<String>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+) -> Result<StatusCode, Error> {
+    registry
+        .remove(&name)
+        .ok_or_else(|| Error::UnknownDataset(name.clone()))?;
+    pool.clear();
+    raster_info::clear();
+    prune::schedule(registry.0.clone(), vec![name]);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn purge(extract::Query(query): extract::Query<PurgeQuery>) -> Result<Json<Value>, Error> {
+    if let Some(dataset) = &query.dataset {
+        registry::validate_name(dataset)?;
+    }
+    let removed = task::spawn_blocking(move || purge_cache(query.dataset.as_deref())).await??;
+    Ok(Json(json!({ "removed": removed })))
+}
+
+async fn state(
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+) -> Result<Json<Value>, Error> {
+    let cache = task::spawn_blocking(cache_usage).await??;
+    let workers = workers::stats();
+    let pool = pool.config();
+    Ok(Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "datasets": entries(&registry),
+        "cache": cache,
+        "config": {
+            "reverse_y": config.reverse_y,
+            "tile_width": config.tile_width,
+            "tile_height": config.tile_height,
+            "canary_dataset": config.canary_dataset,
+            "pool_size": pool.size,
+            "pool_idle_timeout": pool.idle_timeout.as_secs_f64(),
+            "worker_threads": workers.threads,
+            "worker_queue_depth": workers.queue_depth,
+            "prefetch_budget": config.prefetch_budget,
+        },
+    })))
+}
+
+/// Reports the runtime state of the server, for dashboards that don't scrape `/metrics`.
+async fn stats(pool: Extension<Arc<DatasetPool>>) -> Result<Json<Value>, Error> {
+    let [tiles_dir, thumbnails_dir] = cache_dirs();
+    let (tiles, thumbnails) = task::spawn_blocking(move || -> io::Result<_> {
+        let (mut tiles, mut thumbnails) = (CacheUsage::default(), CacheUsage::default());
+        dir_usage(&tiles_dir, &mut tiles)?;
+        dir_usage(&thumbnails_dir, &mut thumbnails)?;
+        Ok((tiles, thumbnails))
+    })
+    .await??;
+    let workers = workers::stats();
+    let handles = pool.stats();
+    let uptime = STARTED
+        .get()
+        .map_or(0.0, |started| started.elapsed().as_secs_f64());
+    Ok(Json(json!({
+        "uptime": uptime,
+        "pool": {
+            "open": handles.in_use + handles.idle,
+            "in_use": handles.in_use,
+            "idle": handles.idle,
+            "idle_datasets": handles.datasets,
+            "size": pool.config().size,
+        },
+        "workers": {
+            "threads": workers.threads,
+            "running": workers.running,
+            "queued": workers.queued,
+            "queue_depth": workers.queue_depth,
+            "rejected": workers.rejected,
+            "timed_out": workers.timed_out,
+        },
+        "renders": {
+            "in_flight": render::in_flight(),
+            "prefetching": prefetch::in_flight(),
+        },
+        "cache": {
+            "tiles": tiles,
+            "thumbnails": thumbnails,
+            "gdal_blocks": {
+                "bytes": unsafe { gdal_sys::GDALGetCacheUsed64() },
+                "max_bytes": unsafe { gdal_sys::GDALGetCacheMax64() },
+            },
+        },
+    })))
+}
+
+/// Returns how many requests each API key made to each dataset on a day, today by default.
+async fn audit(
+    extract::Query(query): extract::Query<AuditQuery>,
+    access: Option<Extension<Arc<Access>>>,
+) -> Result<Json<Rollup>, Error> {
+    let access =
+        access.ok_or_else(|| Error::BadRequest("API keys are not configured".to_string()))?;
+    Ok(Json(access.rollup(query.date.as_deref())?))
+}
+
+/// Queues a seeding or purge job, returning it with its id.
+async fn start_job(
+    jobs: Extension<Arc<Jobs>>,
+    Json(spec): Json<JobSpec>,
+) -> Result<(StatusCode, Json<JobInfo>), Error> {
+    let job = task::spawn_blocking(move || jobs.start(spec)).await??;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn list_jobs(jobs: Extension<Arc<Jobs>>) -> Json<Vec<JobInfo>> {
+    Json(jobs.list())
+}
+
+async fn job(
+    extract::Path(id): extract::Path<u64>,
+    jobs: Extension<Arc<Jobs>>,
+) -> Result<Json<JobInfo>, StatusCode> {
+    jobs.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Streams the progress of a job as Server-Sent Events with its state, named after its status.
+async fn job_events(
+    extract::Path(id): extract::Path<u64>,
+    jobs: Extension<Arc<Jobs>>,
+) -> Result<Response, StatusCode> {
+    let events = jobs.events(id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+async fn cancel_job(
+    extract::Path(id): extract::Path<u64>,
+    jobs: Extension<Arc<Jobs>>,
+) -> Result<Json<JobInfo>, StatusCode> {
+    jobs.cancel(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn tuning(pool: Extension<Arc<DatasetPool>>) -> Json<Tuning> {
+    Json(Tuning::current(&pool))
+}
+
+/// Applies new limits without restarting, returning the ones in effect afterwards.
+///
+/// Lowering `worker_threads` waits for enough of the running jobs to finish.
+async fn update_tuning(
+    pool: Extension<Arc<DatasetPool>>,
+    Json(tuning): Json<Tuning>,
+) -> Result<Json<Tuning>, Error> {
+    tuning.validate()?;
+    if let Some(size) = tuning.gdal_cache_size {
+        unsafe { gdal_sys::GDALSetCacheMax64(size as i64) };
+    }
+    if let Some(queue_depth) = tuning.worker_queue_depth {
+        workers::set_queue_depth(queue_depth);
+    }
+    if let Some(threads) = tuning.worker_threads {
+        workers::set_threads(threads).await;
+    }
+    if tuning.pool_size.is_some()
+        || tuning.pool_idle_timeout.is_some()
+        || tuning.pool_max_datasets.is_some()
+    {
+        let current = pool.config();
+        pool.set_config(PoolConfig {
+            size: tuning.pool_size.unwrap_or(current.size),
+            idle_timeout: tuning
+                .pool_idle_timeout
+                .map_or(current.idle_timeout, Duration::from_secs_f64),
+            max_datasets: tuning.pool_max_datasets.unwrap_or(current.max_datasets),
+        });
+    }
+    Ok(Json(Tuning::current(&pool)))
+}
+
+pub fn router() -> Router {
+    STARTED.get_or_init(Instant::now);
+    Router::new()
+        .route("/reload", post(reload))
+        .route("/datasets", get(list_datasets))
+        .route("/datasets/:name", put(add_dataset).delete(remove_dataset))
+        .route("/purge", post(purge))
+        .route("/state", get(state))
+        .route("/stats", get(stats))
+        .route("/audit", get(audit))
+        .route("/jobs", get(list_jobs).post(start_job))
+        .route("/jobs/:id", get(job).delete(cancel_job))
+        .route("/jobs/:id/events", get(job_events))
+        .route("/tuning", get(tuning).put(update_tuning))
+        .route_layer(middleware::from_fn(authorize))
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use axum::body::{self, Full};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use hyper::StatusCode;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::error::Error;
+
+/// A pre-rendered tile read from an archive, served without decoding it.
+pub struct RawTile {
+    pub data: Bytes,
+    /// The tile format, as named in the MBTiles metadata (`png`, `jpg`, `pbf`...).
+    pub format: String,
+    /// The `Content-Encoding` of the stored data, if compressed.
+    pub encoding: Option<&'static str>,
+}
+
+impl RawTile {
+    fn content_type(&self) -> &'static str {
+        match self.format.as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "webp" => "image/webp",
+            "avif" => "image/avif",
+            "pbf" | "mvt" => "application/vnd.mapbox-vector-tile",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+impl IntoResponse for RawTile {
+    fn into_response(self) -> Response {
+        let mut builder = Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, self.content_type())
+            .header(header::CONTENT_LENGTH, self.data.len());
+        if let Some(encoding) = self.encoding {
+            builder = builder.header(header::CONTENT_ENCODING, encoding);
+        }
+        builder.body(body::boxed(Full::from(self.data))).unwrap()
+    }
+}
+
+/// Opens an SQLite-based archive for reading.
+pub fn open_sqlite(path: &Path) -> Result<Connection, Error> {
+    let connection = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    Ok(connection)
+}
+
+/// Guesses the format of an image from its first bytes.
+pub fn sniff_format(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        "jpg"
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "webp"
+    } else {
+        ""
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::Deserialize;
+
+use crate::access::TileCount;
+use crate::config::Config;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::registry::Registry;
+use crate::style::StyleQuery;
+use crate::tile_grid::Extent;
+use crate::workers;
+
+const MAX_TILES: u64 = 1000;
+const MAX_ZOOM: u8 = 30;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BatchRequest {
+    /// An explicit list of `[z, x, y]` tiles.
+    Tiles { tiles: Vec<(u8, u32, u32)> },
+    /// The tiles covering a `[xmin, ymin, xmax, ymax]` extent in the tile grid CRS.
+    Area {
+        bbox: [f64; 4],
+        minzoom: u8,
+        maxzoom: u8,
+    },
+}
+
+/// Converts a grid row to the `z/x/y` scheme of the tile endpoint, and back.
+pub fn flip_y(z: u8, y: u32, config: &Config) -> u32 {
+    if config.reverse_y {
+        (1 << z) - 1 - y
+    } else {
+        y
+    }
+}
+
+fn too_many_tiles() -> Error {
+    Error::BadRequest(format!("at most {} tiles can be requested", MAX_TILES))
+}
+
+/// Lists the requested tiles, in the same `z/x/y` scheme as the tile endpoint.
+fn list_tiles(request: BatchRequest, config: &Config) -> Result<Vec<(u8, u32, u32)>, Error> {
+    match request {
+        BatchRequest::Tiles { tiles } => {
+            if tiles.len() as u64 > MAX_TILES {
+                return Err(too_many_tiles());
+            }
+            for &(z, x, y) in &tiles {
+                if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+                    return Err(Error::BadRequest(format!(
+                        "invalid tile: {}/{}/{}",
+                        z, x, y
+                    )));
+                }
+            }
+            Ok(tiles)
+        }
+        BatchRequest::Area {
+            bbox,
+            minzoom,
+            maxzoom,
+        } => {
+            if minzoom > maxzoom || maxzoom > MAX_ZOOM {
+                return Err(Error::BadRequest(format!(
+                    "invalid zoom range: {}-{}",
+                    minzoom, maxzoom
+                )));
+            }
+            let [xmin, ymin, xmax, ymax] = bbox;
+            let extent = Extent {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            };
+            let mut ranges = Vec::new();
+            let mut count = 0;
+            for z in minzoom..=maxzoom {
+                if let Some((xs, ys)) = config.tile_grid.tile_range(&extent, z) {
+                    count +=
+                        (xs.end() - xs.start() + 1) as u64 * (ys.end() - ys.start() + 1) as u64;
+                    if count > MAX_TILES {
+                        return Err(too_many_tiles());
+                    }
+                    ranges.push((z, xs, ys));
+                }
+            }
+            let mut tiles = Vec::new();
+            for (z, xs, ys) in ranges {
+                for x in xs {
+                    for y in ys.clone() {
+                        tiles.push((z, x, flip_y(z, y, config)));
+                    }
+                }
+            }
+            Ok(tiles)
+        }
+    }
+}
+
+/// Renders a batch of tiles into a tar archive with `z/x/y.png` entries, skipping empty ones.
+pub async fn batch(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(style): extract::Query<StyleQuery>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+    Json(request): Json<BatchRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let entry = registry.get(&file)?;
+    let style = style.with_defaults(&entry.style);
+    let tiles = list_tiles(request, &config)?;
+    let disposition = format!("attachment; filename=\"{}.tar\"", file);
+    let file = registry.cache_name(&file);
+    let (archive, count) = workers::run(move || {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut count = 0;
+        for (z, x, y) in tiles {
+            let png = match crate::cached_tile(&entry, &file, (z, x, y), &style, &config, &pool) {
+                Ok((png, _)) => png,
+                Err(Error::OutsideBounds) => continue,
+                Err(e) => return Err(e),
+            };
+            let mut header = tar::Header::new_gnu();
+            header.set_size(png.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, format!("{}/{}/{}.png", z, x, y), &png[..])?;
+            count += 1;
+        }
+        Ok((archive.into_inner()?, count))
+    })
+    .await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Extension(TileCount(count)),
+        archive,
+    ))
+}
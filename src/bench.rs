@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+
+use crate::cache;
+use crate::config::Config;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::raster_info;
+use crate::registry::{Kind, Registry};
+use crate::remote;
+use crate::style::StyleQuery;
+
+/// Whether the stage timings are being collected.
+static RECORDING: AtomicBool = AtomicBool::new(false);
+/// The time taken by each run of each rendering stage.
+static TIMINGS: Mutex<BTreeMap<&'static str, Vec<Duration>>> = Mutex::new(BTreeMap::new());
+
+/// Records how long a rendering stage took, while a benchmark runs.
+pub fn record(stage: &'static str, elapsed: Duration) {
+    if RECORDING.load(Ordering::Relaxed) {
+        TIMINGS
+            .lock()
+            .unwrap()
+            .entry(stage)
+            .or_default()
+            .push(elapsed);
+    }
+}
+
+#[derive(Args)]
+pub struct Options {
+    /// The dataset to render
+    #[arg(long)]
+    dataset: String,
+    /// The zoom levels, like 12 or 8..14
+    #[arg(long, default_value = "8..14", value_parser = parse_zoom)]
+    zoom: RangeInclusive<u8>,
+    /// The tiles rendered at once
+    #[arg(long, default_value_t = 16, value_parser = crate::parse_count)]
+    concurrency: usize,
+    /// The tiles rendered at each zoom level, at most
+    #[arg(long, default_value_t = 1000, value_parser = crate::parse_count)]
+    tiles: usize,
+}
+
+fn parse_zoom(zoom: &str) -> Result<RangeInclusive<u8>, String> {
+    let invalid = || format!("invalid zoom range: {}", zoom);
+    let level = |level: &str| level.parse::<u8>().map_err(|_| invalid());
+    let (min, max) = match zoom.split_once("..") {
+        Some((min, max)) => (level(min)?, level(max.trim_start_matches('='))?),
+        None => (level(zoom)?, level(zoom)?),
+    };
+    Some(min..=max)
+        .filter(|zoom| !zoom.is_empty() && max <= 30)
+        .ok_or_else(invalid)
+}
+
+/// Picks up to `max` tiles from each zoom level, spread over the ones covering the dataset.
+fn workload(tiles_by_zoom: Vec<Vec<(u8, u32, u32)>>, max: usize) -> Vec<(u8, u32, u32)> {
+    let mut workload = Vec::new();
+    for tiles in tiles_by_zoom {
+        let step = (tiles.len() as f64 / max as f64).max(1.0);
+        let mut i = 0.0;
+        while (i as usize) < tiles.len() {
+            workload.push(tiles[i as usize]);
+            i += step;
+        }
+    }
+    workload
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn report(name: &str, mut timings: Vec<Duration>) {
+    if timings.is_empty() {
+        return;
+    }
+    timings.sort();
+    println!(
+        "{:<12} {:>8} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+        name,
+        timings.len(),
+        millis(percentile(&timings, 0.5)),
+        millis(percentile(&timings, 0.9)),
+        millis(percentile(&timings, 0.99)),
+        millis(timings[timings.len() - 1]),
+    );
+}
+
+/// Renders the tiles of a dataset over a range of zoom levels, bypassing the cache, and reports
+/// the throughput and the latency of each rendering stage.
+pub fn run(options: Options) -> Result<(), Error> {
+    let mut config = Config::from_env();
+    config.tile_grid.resolve_crs()?;
+    // keep a handle for each thread
+    config.pool.size = config.pool.size.max(options.concurrency);
+    remote::configure(&config.remote)?;
+    cache::set_dir(&config.cache_dir);
+    let registry = Registry::new(PathBuf::from("."), config.remote.clone())?;
+    let entry = registry.get(&options.dataset)?;
+    if !matches!(entry.kind, Kind::Raster | Kind::GeoPackage) {
+        return Err(Error::BadRequest(format!(
+            "{} is not a raster dataset",
+            options.dataset
+        )));
+    }
+    let pool = DatasetPool::new(config.pool.clone());
+    let extent = {
+        let dataset = pool.get(&entry.path)?;
+        raster_info::get(&entry.path, &dataset)?.extent.clone()
+    };
+    let tiles_by_zoom = options
+        .zoom
+        .clone()
+        .filter_map(|z| {
+            let (xs, ys) = config.tile_grid.tile_range(&extent, z)?;
+            Some(
+                xs.flat_map(|x| ys.clone().map(move |y| (z, x, y)))
+                    .collect(),
+            )
+        })
+        .collect();
+    let tiles = workload(tiles_by_zoom, options.tiles);
+    println!(
+        "rendering {} tiles of {} at zoom {}-{} with {} threads",
+        tiles.len(),
+        options.dataset,
+        options.zoom.start(),
+        options.zoom.end(),
+        options.concurrency
+    );
+
+    let style = StyleQuery::default().with_defaults(&entry.style);
+    let next = AtomicUsize::new(0);
+    let empty = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let latencies = Mutex::new(Vec::with_capacity(tiles.len()));
+    RECORDING.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency {
+            scope.spawn(|| {
+                while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let tile_start = Instant::now();
+                    match crate::render_tile(&entry, tile, &style, &config, &pool) {
+                        Ok(_) => latencies.lock().unwrap().push(tile_start.elapsed()),
+                        Err(Error::OutsideBounds) => {
+                            empty.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            let (z, x, y) = tile;
+                            tracing::warn!("cannot render {}/{}/{}: {}", z, x, y, e);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+    RECORDING.store(false, Ordering::Relaxed);
+
+    let latencies = latencies.into_inner().unwrap();
+    println!(
+        "rendered {} tiles ({} empty, {} failed) in {:.2} s, {:.1} tiles/s",
+        latencies.len(),
+        empty.into_inner(),
+        failed.into_inner(),
+        elapsed.as_secs_f64(),
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "{:<12} {:>8} {:>9} {:>9} {:>9} {:>9}",
+        "stage", "count", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    report("tile", latencies);
+    let timings = std::mem::take(&mut *TIMINGS.lock().unwrap());
+    for (stage, timings) in timings {
+        report(stage, timings);
+    }
+    Ok(())
+}
@@ -0,0 +1,295 @@
+//! Reads and writes of the PNGs in the tile and thumbnail caches.
+//!
+//! The entries are checked when read, so that the ones left truncated by a crash or a full disk,
+//! or damaged later, are removed and rendered again instead of being served broken.
+//!
+//! Several server instances can share a cache directory. The entries being rendered are marked
+//! with lock files, so that the other instances wait for them instead of rendering them again.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use flate2::Crc;
+
+use crate::cluster;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// How often to look for an entry being rendered elsewhere.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The directory of the cached tiles and thumbnails and of the generated VRTs, if not the
+/// default one.
+static DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Sets the cache directory, which is `cache` in the current one by default.
+pub fn set_dir(dir: &Path) {
+    *DIR.write().unwrap() = Some(dir.to_path_buf());
+}
+
+pub fn dir() -> PathBuf {
+    DIR.read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("cache"))
+}
+
+/// Returns the start of the names of the cache entries of a dataset, a hash of its name.
+///
+/// Dataset names can start with each other, like `s2` and `s2_2023`, and contain characters
+/// like `/` or `~` that don't belong in file names, so their fixed-length hashes are used
+/// instead, keeping the entries of each dataset apart when purging them.
+pub fn entry_prefix(name: &str) -> String {
+    format!("{:016x}_", cluster::hash(name.as_bytes()))
+}
+
+/// Cache entries found corrupt since the server started.
+static CORRUPT: AtomicU64 = AtomicU64::new(0);
+
+pub fn corrupt_entries() -> u64 {
+    CORRUPT.load(Ordering::Relaxed)
+}
+
+/// Checks that a PNG starts with the signature and an `IHDR` chunk, and that its chunks end with
+/// an `IEND` one at the end of the data. With `verify_crc`, the CRCs of the chunks are checked
+/// too.
+pub fn is_valid_png(data: &[u8], verify_crc: bool) -> bool {
+    let mut rest = match data.strip_prefix(SIGNATURE) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut first = true;
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+        if length > rest.len() - 12 || (first && kind != b"IHDR") {
+            return false;
+        }
+        let (chunk, crc) = rest[4..].split_at(4 + length);
+        if verify_crc {
+            let mut expected = Crc::new();
+            expected.update(chunk);
+            if expected.sum().to_be_bytes() != crc[..4] {
+                return false;
+            }
+        }
+        rest = &crc[4..];
+        if kind == b"IEND" {
+            return rest.is_empty();
+        }
+        first = false;
+    }
+    false
+}
+
+fn report_corrupt(path: &Path) {
+    CORRUPT.fetch_add(1, Ordering::Relaxed);
+    tracing::warn!(path = %path.display(), "removing a corrupt cache entry");
+}
+
+fn report_removed(path: &Path, result: io::Result<()>) {
+    if let Err(e) = result {
+        if e.kind() != io::ErrorKind::NotFound {
+            tracing::warn!("cannot remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Removes a corrupt entry, so that it's rendered again.
+fn discard(path: &Path) {
+    report_corrupt(path);
+    report_removed(path, std::fs::remove_file(path));
+}
+
+/// Like `discard`, but without blocking the runtime.
+async fn discard_async(path: &Path) {
+    report_corrupt(path);
+    report_removed(path, tokio::fs::remove_file(path).await);
+}
+
+/// Reads a cached PNG, returning `None` if it's missing or was corrupt and got removed.
+pub fn read(path: &Path, verify_crc: bool) -> io::Result<Option<Vec<u8>>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !is_valid_png(&data, verify_crc) {
+        discard(path);
+        return Ok(None);
+    }
+    Ok(Some(data))
+}
+
+/// Like `read`, but without blocking the runtime.
+pub async fn read_async(path: &Path, verify_crc: bool) -> io::Result<Option<Vec<u8>>> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !is_valid_png(&data, verify_crc) {
+        discard_async(path).await;
+        return Ok(None);
+    }
+    Ok(Some(data))
+}
+
+/// Writes an entry through a temporary file, so that it's never read half-written.
+pub fn write(path: &Path, data: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut temp_path = path.as_os_str().to_owned();
+    // unique, since a tile can be rendered by more than one request at once
+    temp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = PathBuf::from(temp_path);
+    if let Err(e) = std::fs::write(&temp_path, data) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    std::fs::rename(&temp_path, path)
+}
+
+/// Marks an entry as being rendered, until dropped.
+pub struct Lock(PathBuf);
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            if e.kind() != io::ErrorKind::NotFound {
+                tracing::warn!("cannot remove {}: {}", self.0.display(), e);
+            }
+        }
+    }
+}
+
+/// What `claim` found.
+pub enum Claim {
+    /// The entry is to be rendered, holding its lock if it could be taken.
+    Render(Option<Lock>),
+    /// The entry was written elsewhere in the meantime.
+    Cached(Vec<u8>),
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+/// Takes the lock of a missing entry before rendering it. If another process or thread holds it,
+/// this waits for up to `wait` for the entry to be written, returning it.
+///
+/// Locks older than `wait` are assumed to be left by an instance that crashed, and are taken
+/// over. If the lock is still held after `wait`, or can't be created, the entry is rendered
+/// without it, which is harmless since the writes are atomic.
+pub fn claim(path: &Path, verify_crc: bool, wait: Duration) -> io::Result<Claim> {
+    let lock_path = lock_path(path);
+    let start = SystemTime::now();
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(mut file) => {
+                // only for debugging, the lock is the file existing
+                let _ = write!(file, "{}", std::process::id());
+                let lock = Lock(lock_path);
+                // written between the first read and taking the lock
+                if let Some(data) = read(path, verify_crc)? {
+                    return Ok(Claim::Cached(data));
+                }
+                return Ok(Claim::Render(Some(lock)));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                tracing::warn!("cannot lock {}: {}", path.display(), e);
+                return Ok(Claim::Render(None));
+            }
+        }
+        let locked = lock_path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let stale = locked
+            .and_then(|locked| SystemTime::now().duration_since(locked).ok())
+            .is_some_and(|age| age >= wait);
+        if stale {
+            tracing::warn!(path = %path.display(), "taking over a stale cache lock");
+            let _ = std::fs::remove_file(&lock_path);
+            continue;
+        }
+        if start.elapsed().map_or(true, |waited| waited >= wait) {
+            return Ok(Claim::Render(None));
+        }
+        thread::sleep(LOCK_POLL_INTERVAL);
+        if let Some(data) = read(path, verify_crc)? {
+            return Ok(Claim::Cached(data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::Crc;
+
+    use super::{is_valid_png, SIGNATURE};
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        let mut crc = Crc::new();
+        crc.update(&chunk[4..]);
+        chunk.extend_from_slice(&crc.sum().to_be_bytes());
+        chunk
+    }
+
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut png = SIGNATURE.to_vec();
+        for chunk in chunks {
+            png.extend_from_slice(chunk);
+        }
+        png
+    }
+
+    #[test]
+    fn validates_pngs() {
+        let ihdr = chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+        let idat = chunk(b"IDAT", &[0x78, 0x9c, 0x63, 0, 0, 0, 0, 1]);
+        let iend = chunk(b"IEND", &[]);
+        let valid = png(&[ihdr.clone(), idat.clone(), iend.clone()]);
+        assert!(is_valid_png(&valid, false));
+        assert!(is_valid_png(&valid, true));
+
+        // truncated, or with trailing data
+        assert!(!is_valid_png(&valid[..valid.len() - 1], false));
+        let mut trailing = valid.clone();
+        trailing.push(0);
+        assert!(!is_valid_png(&trailing, false));
+        // without the signature, the header or the end
+        assert!(!is_valid_png(&valid[8..], false));
+        assert!(!is_valid_png(&png(&[idat.clone(), iend.clone()]), false));
+        assert!(!is_valid_png(&png(&[ihdr.clone(), idat.clone()]), false));
+        assert!(!is_valid_png(&[], false));
+        // a chunk longer than the file
+        let mut long = valid.clone();
+        long[8 + ihdr.len() + 3] = 0xff;
+        assert!(!is_valid_png(&long, false));
+
+        // a flipped bit is only noticed with the CRCs
+        let mut corrupt = valid.clone();
+        corrupt[8 + ihdr.len() + 8] ^= 1;
+        assert!(is_valid_png(&corrupt, false));
+        assert!(!is_valid_png(&corrupt, true));
+    }
+}
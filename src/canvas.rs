@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+use gdal::{Dataset, Driver};
+
+use crate::error::Error;
+
+/// Idle datasets or buffers kept for each size.
+const MAX_IDLE: usize = 16;
+
+/// Buffers kept between renders, keyed by length, to avoid allocating them for each tile.
+pub struct BufferPool<T>(Mutex<BTreeMap<usize, Vec<Vec<T>>>>);
+
+impl<T: Clone> BufferPool<T> {
+    pub const fn new() -> Self {
+        Self(Mutex::new(BTreeMap::new()))
+    }
+
+    /// Returns a buffer of `len` copies of `value`.
+    pub fn take(&self, len: usize, value: T) -> Vec<T> {
+        let buffer = self
+            .0
+            .lock()
+            .unwrap()
+            .get_mut(&len)
+            .and_then(|buffers| buffers.pop());
+        match buffer {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer.resize(len, value);
+                buffer
+            }
+            None => vec![value; len],
+        }
+    }
+
+    /// Returns an empty buffer that can hold `len` values without reallocating.
+    pub fn take_empty(&self, len: usize) -> Vec<T> {
+        let buffer = self
+            .0
+            .lock()
+            .unwrap()
+            .get_mut(&len)
+            .and_then(|buffers| buffers.pop());
+        match buffer {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer
+            }
+            None => Vec::with_capacity(len),
+        }
+    }
+
+    /// Gives a buffer back to the pool, keyed by its length.
+    pub fn put(&self, buffer: Vec<T>) {
+        let mut buffers = self.0.lock().unwrap();
+        let buffers = buffers.entry(buffer.len()).or_default();
+        if buffers.len() < MAX_IDLE {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Blank RGBA `MEM` datasets, keyed by size.
+static CANVASES: Mutex<BTreeMap<(usize, usize), Vec<Dataset>>> = Mutex::new(BTreeMap::new());
+
+/// A transparent RGBA `MEM` dataset to render into, returned to the pool when dropped.
+pub struct Canvas {
+    size: (usize, usize),
+    dataset: Option<Dataset>,
+}
+
+impl Canvas {
+    /// Returns a transparent `width` by `height` dataset, reusing one from the pool if possible.
+    pub fn new(width: usize, height: usize) -> Result<Self, Error> {
+        let size = (width, height);
+        let idle = CANVASES
+            .lock()
+            .unwrap()
+            .get_mut(&size)
+            .and_then(|canvases| canvases.pop());
+        let dataset = match idle {
+            Some(dataset) => {
+                clear(&dataset)?;
+                dataset
+            }
+            None => Driver::get("MEM")?.create("", width as isize, height as isize, 4)?,
+        };
+        Ok(Self {
+            size,
+            dataset: Some(dataset),
+        })
+    }
+
+    /// Copies an image without a border of `border` pixels around it.
+    pub fn crop(image: &Dataset, border: usize) -> Result<Self, Error> {
+        let (width, height) = image.raster_size();
+        let size = (width - 2 * border, height - 2 * border);
+        let out = Self::new(size.0, size.1)?;
+        for band in 1..=4 {
+            let data = image.rasterband(band)?.read_as::<u8>(
+                (border as isize, border as isize),
+                size,
+                size,
+                None,
+            )?;
+            out.rasterband(band)?.write((0, 0), size, &data)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Fills all the bands of a dataset with zeros.
+fn clear(dataset: &Dataset) -> Result<(), Error> {
+    for band in 1..=dataset.raster_count() {
+        let rv = unsafe {
+            let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _);
+            gdal_sys::GDALFillRaster(c_band, 0.0, 0.0)
+        };
+        if rv != gdal_sys::CPLErr::CE_None {
+            return Err(Error::last_cpl_error(rv));
+        }
+    }
+    Ok(())
+}
+
+impl Deref for Canvas {
+    type Target = Dataset;
+
+    fn deref(&self) -> &Dataset {
+        self.dataset.as_ref().unwrap()
+    }
+}
+
+impl Drop for Canvas {
+    fn drop(&mut self) {
+        if let Some(dataset) = self.dataset.take() {
+            let mut canvases = CANVASES.lock().unwrap();
+            let canvases = canvases.entry(self.size).or_default();
+            if canvases.len() < MAX_IDLE {
+                canvases.push(dataset);
+            }
+        }
+    }
+}
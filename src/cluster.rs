@@ -0,0 +1,170 @@
+//! Cluster mode, where each tile is rendered and cached by one of the instances, picked by
+//! consistent hashing of its cache key, with the others forwarding the requests for it.
+//!
+//! This way a tile is only rendered once across the fleet, and the caches of the instances add
+//! up instead of holding the same tiles.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{self, Body};
+use axum::extract::{OriginalUri, RequestParts};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::client::HttpConnector;
+use hyper::{header, Client};
+
+use crate::config::ClusterConfig;
+use crate::error::Error;
+
+/// Marks the requests forwarded by a peer, which are never forwarded again.
+const FORWARDED_HEADER: &str = "x-tile-server-forwarded";
+/// Points of each instance on the hash ring, so that the tiles are spread evenly.
+const VIRTUAL_NODES: u64 = 128;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+static FORWARDED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+/// Tile requests forwarded to a peer.
+pub fn forwarded() -> u64 {
+    FORWARDED.load(Ordering::Relaxed)
+}
+
+/// Tile requests rendered locally because their peer couldn't be reached.
+pub fn failed() -> u64 {
+    FAILED.load(Ordering::Relaxed)
+}
+
+/// FNV-1a, with the bits mixed like in SplitMix64, since it must give the same results on every
+/// instance, unlike the `std` hasher.
+pub fn hash(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in data {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+pub struct Cluster {
+    config: ClusterConfig,
+    /// The points of the instances on the ring, sorted, with the index of their instance.
+    ring: Vec<(u64, usize)>,
+    client: Client<HttpConnector>,
+}
+
+impl Cluster {
+    pub fn new(config: ClusterConfig) -> Result<Self, Error> {
+        if !config.peers.contains(&config.self_url) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the cluster peers don't include this instance, {}",
+                    config.self_url
+                ),
+            )));
+        }
+        let mut ring = config
+            .peers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, peer)| {
+                (0..VIRTUAL_NODES)
+                    .map(move |node| (hash(format!("{}#{}", peer, node).as_bytes()), i))
+            })
+            .collect::<Vec<_>>();
+        ring.sort_unstable();
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(CONNECT_TIMEOUT));
+        Ok(Self {
+            config,
+            ring,
+            client: Client::builder().build(connector),
+        })
+    }
+
+    /// Returns the URL of the instance rendering the tiles with a cache key.
+    pub fn owner(&self, key: &str) -> &str {
+        let hash = hash(key.as_bytes());
+        let point = self.ring.partition_point(|&(point, _)| point < hash);
+        let (_, peer) = self.ring[point % self.ring.len()];
+        &self.config.peers[peer]
+    }
+
+    /// Checks whether a request was forwarded by a peer knowing the cluster secret.
+    pub fn is_trusted<B>(&self, request: &Request<B>) -> bool {
+        let secret = match &self.config.secret {
+            Some(secret) => secret,
+            None => return false,
+        };
+        request
+            .headers()
+            .get(FORWARDED_HEADER)
+            .is_some_and(|value| value.as_bytes() == secret.as_bytes())
+    }
+}
+
+/// Forwards the requests for the tiles rendered by another instance to it, rendering them locally
+/// if it can't be reached.
+pub async fn forward(request: Request<Body>, next: Next<Body>) -> Response {
+    let cluster = match request.extensions().get::<Arc<Cluster>>() {
+        Some(cluster) => cluster.clone(),
+        None => return next.run(request).await,
+    };
+    if request.headers().contains_key(FORWARDED_HEADER) {
+        return next.run(request).await;
+    }
+    let mut parts = RequestParts::new(request);
+    let owner = crate::tile_cache_key(&mut parts)
+        .await
+        .map(|key| cluster.owner(&key).to_string())
+        .filter(|owner| *owner != cluster.config.self_url);
+    let request = match parts.try_into_request() {
+        Ok(request) => request,
+        Err(e) => return e.into_response(),
+    };
+    let owner = match owner {
+        Some(owner) => owner,
+        None => return next.run(request).await,
+    };
+
+    // with the `/t/<tenant>` prefix stripped by the nested router
+    let uri = match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri,
+        None => request.uri(),
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    let mut forwarded = Request::builder()
+        .method(request.method())
+        .uri(format!("{}{}", owner, path));
+    for (name, value) in request.headers() {
+        if name != header::HOST && name != header::CONNECTION {
+            forwarded = forwarded.header(name, value);
+        }
+    }
+    let marker = cluster.config.secret.as_deref().unwrap_or("1");
+    let forwarded = forwarded
+        .header(FORWARDED_HEADER, marker)
+        .body(Body::empty());
+    let result = match forwarded {
+        Ok(forwarded) => cluster.client.request(forwarded).await.map_err(Error::from),
+        Err(e) => Err(Error::BadRequest(e.to_string())),
+    };
+    match result {
+        Ok(response) => {
+            FORWARDED.fetch_add(1, Ordering::Relaxed);
+            response.map(body::boxed)
+        }
+        Err(e) => {
+            FAILED.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(peer = %owner, "cannot forward the tile request: {}", e);
+            next.run(request).await
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use gdal::raster::Buffer;
+
+use crate::canvas::Canvas;
+use crate::config::Config;
+use crate::dataset;
+use crate::error::Error;
+use crate::raster_info;
+use crate::registry::Registry;
+use crate::render;
+use crate::renderer::RgbRenderer;
+use crate::style::Style;
+use crate::tile_grid::{self, Extent};
+use crate::workers;
+use crate::Png;
+
+const MAX_LAYERS: usize = 8;
+
+/// Parses a comma-separated list of `name` or `name:opacity` layers, from bottom to top.
+fn parse_layers(layers: &str) -> Result<Vec<(String, f64)>, Error> {
+    let layers = layers
+        .split(',')
+        .map(|layer| match layer.rsplit_once(':') {
+            Some((name, opacity)) => match opacity.parse::<f64>() {
+                Ok(opacity) if (0.0..=1.0).contains(&opacity) => Ok((name.to_string(), opacity)),
+                _ => Err(Error::BadRequest(format!("invalid opacity: {}", opacity))),
+            },
+            None => Ok((layer.to_string(), 1.0)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if layers.len() > MAX_LAYERS {
+        return Err(Error::BadRequest(format!(
+            "at most {} layers can be composited",
+            MAX_LAYERS
+        )));
+    }
+    Ok(layers)
+}
+
+/// Blends the rendered layers over each other and encodes the result as PNG.
+fn render_composite(
+    layers: &[(PathBuf, f64)],
+    extent: &Extent,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u8>, Error> {
+    let pixels = width * height;
+    let mut colors = vec![[0.0; 3]; pixels];
+    let mut alphas = vec![0.0; pixels];
+    let mut rendered = false;
+    for (path, opacity) in layers {
+        let dataset = dataset::open(path)?;
+        let style = Style::default_for(dataset.raster_count());
+        let info = raster_info::get(path, &dataset)?;
+        let out = match render::render(&dataset, &info, extent, width, height, &style, &RgbRenderer)
+        {
+            Ok(out) => out,
+            Err(Error::OutsideBounds) => continue,
+            Err(e) => return Err(e),
+        };
+        rendered = true;
+        let bands = (1..=4)
+            .map(|band| Ok(out.rasterband(band)?.read_band_as::<u8>()?.data))
+            .collect::<Result<Vec<_>, Error>>()?;
+        for i in 0..pixels {
+            let alpha = bands[3][i] as f64 / 255.0 * opacity;
+            if alpha == 0.0 {
+                continue;
+            }
+            let below = alphas[i] * (1.0 - alpha);
+            let blended = alpha + below;
+            for (c, color) in colors[i].iter_mut().enumerate() {
+                *color = (bands[c][i] as f64 * alpha + *color * below) / blended;
+            }
+            alphas[i] = blended;
+        }
+    }
+    if !rendered {
+        return Err(Error::OutsideBounds);
+    }
+
+    let out = Canvas::new(width, height)?;
+    for c in 0..3 {
+        let channel = colors.iter().map(|color| color[c].round() as u8).collect();
+        out.rasterband(c as isize + 1)?.write(
+            (0, 0),
+            (width, height),
+            &Buffer::new((width, height), channel),
+        )?;
+    }
+    let alpha = alphas.iter().map(|a| (a * 255.0).round() as u8).collect();
+    out.rasterband(4)?.write(
+        (0, 0),
+        (width, height),
+        &Buffer::new((width, height), alpha),
+    )?;
+    render::encode_png(&out)
+}
+
+pub async fn composite(
+    extract::Path((layers, z, x, mut y)): extract::Path<(String, u8, u32, u32)>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Png, Error> {
+    tile_grid::check_tile(z, x, y)?;
+    let layers = parse_layers(&layers)?
+        .into_iter()
+        .map(|(name, opacity)| Ok((registry.resolve(&name)?, opacity)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    if config.reverse_y {
+        y = (1 << z) - 1 - y;
+    }
+    let extent = config.tile_grid.tile_extent(x, y, z);
+    let png = workers::run(move || {
+        render_composite(&layers, &extent, config.tile_width, config.tile_height)
+    })
+    .await?;
+    Ok(Png(png.into()))
+}
@@ -0,0 +1,100 @@
+//! Responses for the large JSON documents, like the catalogue, the OpenAPI description or the
+//! footprints, which keep their gzip-encoded bytes between requests, so that an unchanged
+//! document isn't compressed again each time it's served.
+//!
+//! Only gzip is offered, since the server doesn't depend on a brotli encoder.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use axum::body::{Bytes, Full};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// The size under which the documents are sent as they are.
+const MIN_SIZE: usize = 1024;
+const MAX_DOCUMENTS: usize = 256;
+
+/// A serialized document, with its gzip encoding when it's large enough.
+pub struct Document {
+    identity: Bytes,
+    gzip: Option<Bytes>,
+}
+
+/// The last version of each document, by the key passed to `encode`.
+static DOCUMENTS: Mutex<BTreeMap<String, Arc<Document>>> = Mutex::new(BTreeMap::new());
+
+/// Checks whether the client accepts gzip-encoded responses.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parameters = coding.split(';');
+            let name = parameters.next().unwrap_or_default().trim();
+            let quality = parameters
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map_or(1.0, |quality| quality.trim().parse().unwrap_or(0.0));
+            (name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip"))
+                && quality > 0.0
+        })
+}
+
+/// Serializes a document, reusing its gzip encoding if it's the same as the last time it was
+/// encoded under that key. This compresses it otherwise, so it should run on the workers.
+pub fn encode(key: String, value: &impl Serialize) -> Result<Arc<Document>, Error> {
+    let identity = serde_json::to_vec(value).map_err(io::Error::from)?;
+    if identity.len() < MIN_SIZE {
+        return Ok(Arc::new(Document {
+            identity: identity.into(),
+            gzip: None,
+        }));
+    }
+    if let Some(document) = DOCUMENTS.lock().unwrap().get(&key) {
+        if document.identity[..] == identity[..] {
+            return Ok(document.clone());
+        }
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&identity)?;
+    let document = Arc::new(Document {
+        identity: identity.into(),
+        gzip: Some(encoder.finish()?.into()),
+    });
+    let mut documents = DOCUMENTS.lock().unwrap();
+    if documents.len() >= MAX_DOCUMENTS && !documents.contains_key(&key) {
+        documents.pop_first();
+    }
+    documents.insert(key, document.clone());
+    Ok(document)
+}
+
+impl Document {
+    /// Returns the encoding of the document picked by the `Accept-Encoding` of a request.
+    pub fn respond(&self, headers: &HeaderMap) -> Response {
+        let content_type = (
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let gzip = match &self.gzip {
+            Some(gzip) => gzip,
+            None => return ([content_type], Full::new(self.identity.clone())).into_response(),
+        };
+        let vary = (header::VARY, HeaderValue::from_static("accept-encoding"));
+        if accepts_gzip(headers) {
+            let encoding = (header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            ([content_type, vary, encoding], Full::new(gzip.clone())).into_response()
+        } else {
+            ([content_type, vary], Full::new(self.identity.clone())).into_response()
+        }
+    }
+}
@@ -1,4 +1,22 @@
-use crate::tile_grid::TileGrid;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+pub use crate::dataset::Resampling;
+
+use crate::crs;
+use crate::layers::Layers;
+use crate::renderer::Renderers;
+use crate::sentinel2::Sentinel2;
+use crate::stac::StacSearch;
+use crate::style::StyleQuery;
+use crate::tile_grid::{Extent, TileGrid};
+use crate::wms::WmsSource;
 
 #[derive(Clone)]
 pub struct Config {
@@ -6,4 +24,637 @@ pub struct Config {
     pub reverse_y: bool,
     pub tile_width: usize,
     pub tile_height: usize,
+    /// A dataset that must open for the server to report itself as ready.
+    pub canary_dataset: Option<String>,
+    /// Bearer token required by the admin API, which is disabled when unset.
+    pub admin_token: Option<String>,
+    pub remote: RemoteConfig,
+    /// How often to scan the data directory for added or removed datasets, if at all.
+    pub watch_interval: Option<Duration>,
+    pub pool: PoolConfig,
+    pub workers: WorkerConfig,
+    pub output_limits: OutputLimits,
+    /// Whether to check the CRCs of the cached images when reading them, besides their
+    /// structure.
+    pub cache_verify_crc: bool,
+    /// Whether the PNGs are marked as sRGB, the colour space the rasters with an ICC profile are
+    /// converted to.
+    pub png_srgb: bool,
+    /// How long to wait for a tile being rendered by another instance sharing the cache, with the
+    /// cache not locked if unset.
+    pub cache_lock_wait: Option<Duration>,
+    /// The size of the recently used tiles kept in memory, in bytes, with 0 disabling it.
+    pub memory_cache_size: usize,
+    /// Neighbours of rendered tiles that can be prefetched at once, with 0 disabling it.
+    pub prefetch_budget: usize,
+    /// Custom renderers the datasets can select by name.
+    pub renderers: Renderers,
+    /// Requests taking longer than this are logged as warnings, with the timings of their stages.
+    pub slow_request_threshold: Option<Duration>,
+    /// A JSON file mapping the API keys required by the data routes to their names, if any.
+    pub api_keys: Option<PathBuf>,
+    /// Where the audit trail and the quota usage of the API keys are kept, `audit` in the cache
+    /// directory by default.
+    pub audit_dir: Option<PathBuf>,
+    /// Where to report the internal errors and panics, when built with the `sentry` feature.
+    #[cfg(feature = "sentry")]
+    pub sentry_dsn: Option<String>,
+    /// Admin jobs running at once, with the others waiting for their turn.
+    pub max_jobs: usize,
+    /// The other instances the tiles are spread across, if any.
+    pub cluster: Option<ClusterConfig>,
+    /// A JSON file describing the tenants by name, whose datasets are served under `/t/<name>`.
+    pub tenants: Option<PathBuf>,
+    /// Where the tiles, thumbnails and generated VRTs are cached.
+    pub cache_dir: PathBuf,
+    /// How the rasters in another CRS than the tile grid are resampled when reprojected.
+    pub resampling: Resampling,
+    /// Extra middleware inserted into the router.
+    pub layers: Layers,
+}
+
+impl Config {
+    /// Reads the settings from the `TILE_SERVER_ADMIN_TOKEN`, `TILE_SERVER_WATCH_INTERVAL`,
+    /// `TILE_SERVER_PREFETCH_BUDGET`, `TILE_SERVER_SLOW_REQUEST_THRESHOLD`,
+    /// `TILE_SERVER_API_KEYS`, `TILE_SERVER_AUDIT_DIR`, `TILE_SERVER_MAX_JOBS`,
+    /// `TILE_SERVER_CACHE_VERIFY_CRC`, `TILE_SERVER_CACHE_LOCK_WAIT`, `TILE_SERVER_PNG_SRGB`,
+    /// `TILE_SERVER_TENANTS`, `TILE_SERVER_CACHE_DIR`, `TILE_SERVER_RESAMPLING` and
+    /// `TILE_SERVER_MEMORY_CACHE_SIZE` environment variables, with the durations in seconds, along
+    /// with the ones of the remote, pool, worker, output limit and cluster settings. With the
+    /// `sentry` feature, the DSN is read from `TILE_SERVER_SENTRY_DSN`.
+    pub fn from_env() -> Self {
+        Self {
+            tile_grid: TileGrid::web_mercator(),
+            reverse_y: false,
+            tile_width: 256,
+            tile_height: 256,
+            canary_dataset: None,
+            admin_token: std::env::var("TILE_SERVER_ADMIN_TOKEN").ok(),
+            remote: RemoteConfig::from_env(),
+            watch_interval: env_var("TILE_SERVER_WATCH_INTERVAL").map(Duration::from_secs_f64),
+            pool: PoolConfig::from_env(),
+            workers: WorkerConfig::from_env(),
+            output_limits: OutputLimits::from_env(),
+            cache_verify_crc: env_var("TILE_SERVER_CACHE_VERIFY_CRC").unwrap_or(false),
+            png_srgb: env_var("TILE_SERVER_PNG_SRGB").unwrap_or(false),
+            cache_lock_wait: env_var("TILE_SERVER_CACHE_LOCK_WAIT").map(Duration::from_secs_f64),
+            memory_cache_size: env_var("TILE_SERVER_MEMORY_CACHE_SIZE").unwrap_or(64 << 20),
+            prefetch_budget: env_var("TILE_SERVER_PREFETCH_BUDGET").unwrap_or(0),
+            renderers: Renderers::new(),
+            slow_request_threshold: env_var("TILE_SERVER_SLOW_REQUEST_THRESHOLD")
+                .map(Duration::from_secs_f64),
+            api_keys: std::env::var_os("TILE_SERVER_API_KEYS").map(PathBuf::from),
+            audit_dir: std::env::var_os("TILE_SERVER_AUDIT_DIR").map(PathBuf::from),
+            #[cfg(feature = "sentry")]
+            sentry_dsn: std::env::var("TILE_SERVER_SENTRY_DSN").ok(),
+            max_jobs: env_var("TILE_SERVER_MAX_JOBS")
+                .filter(|&n| n > 0)
+                .unwrap_or(1),
+            cluster: ClusterConfig::from_env(),
+            tenants: std::env::var_os("TILE_SERVER_TENANTS").map(PathBuf::from),
+            cache_dir: std::env::var_os("TILE_SERVER_CACHE_DIR")
+                .map_or_else(|| PathBuf::from("cache"), PathBuf::from),
+            resampling: env_var("TILE_SERVER_RESAMPLING").unwrap_or_default(),
+            layers: Layers::default(),
+        }
+    }
+
+    pub fn audit_dir(&self) -> PathBuf {
+        self.audit_dir
+            .clone()
+            .unwrap_or_else(|| self.cache_dir.join("audit"))
+    }
+}
+
+/// The largest tiles, in pixels.
+const MAX_TILE_SIZE: usize = 4096;
+
+/// A tile grid in a configuration file, either `"web_mercator"` or a table with the extent it
+/// covers, in the CRS of the datasets.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum GridConfig {
+    Named(String),
+    Extent { extent: [f64; 4] },
+}
+
+impl GridConfig {
+    fn tile_grid(&self) -> Result<TileGrid, String> {
+        match self {
+            GridConfig::Named(name) if name == "web_mercator" => Ok(TileGrid::web_mercator()),
+            GridConfig::Named(name) => Err(format!("unknown tile grid: {}", name)),
+            &GridConfig::Extent {
+                extent: [xmin, ymin, xmax, ymax],
+            } => {
+                if !(xmin < xmax && ymin < ymax) {
+                    return Err("the grid extent must be [xmin, ymin, xmax, ymax]".to_string());
+                }
+                Ok(TileGrid::new(Extent {
+                    xmin,
+                    ymin,
+                    xmax,
+                    ymax,
+                }))
+            }
+        }
+    }
+}
+
+impl FromStr for GridConfig {
+    type Err = String;
+
+    /// Parses `web_mercator` or an extent, like `xmin,ymin,xmax,ymax`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(',') {
+            return Ok(GridConfig::Named(s.to_string()));
+        }
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("invalid tile grid: {}", s))?;
+        match values[..] {
+            [xmin, ymin, xmax, ymax] => Ok(GridConfig::Extent {
+                extent: [xmin, ymin, xmax, ymax],
+            }),
+            _ => Err(format!("invalid tile grid: {}", s)),
+        }
+    }
+}
+
+/// The settings of the server read from the TOML file passed with `--config` and from the
+/// command line, which take precedence over the environment. The ones left out keep their
+/// defaults.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// The address to listen on, `127.0.0.1` by default.
+    pub listen: Option<IpAddr>,
+    /// The port to listen on, 3011 by default.
+    pub port: Option<u16>,
+    /// The width and height of the tiles, in pixels.
+    pub tile_size: Option<usize>,
+    pub grid: Option<GridConfig>,
+    /// The CRS of the grid, like `EPSG:32633`, which the rasters in other ones are reprojected
+    /// to. The Web Mercator grid is in `EPSG:3857`, and the rasters are assumed to be in the CRS
+    /// of the other grids if it's not given.
+    pub grid_crs: Option<String>,
+    pub resampling: Option<Resampling>,
+    /// The directory of the datasets, the current one by default.
+    pub data_dir: Option<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
+    /// The directory of the audit trail, `audit` in the cache directory by default.
+    pub audit_dir: Option<PathBuf>,
+    pub reverse_y: Option<bool>,
+    #[serde(skip)]
+    tile_grid: Option<TileGrid>,
+}
+
+impl FileConfig {
+    /// Reads and checks a configuration file.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid configuration file {}: {}", path.display(), e),
+            )
+        };
+        let document = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let mut config: Self = toml::from_str(&document).map_err(|e| invalid(e.to_string()))?;
+        config.check().map_err(invalid)?;
+        Ok(config)
+    }
+
+    /// Reads the configuration file at `path`, if any, and checks the settings, with the ones
+    /// given here, e.g. on the command line, overriding those in the file.
+    pub fn merge_file(self, path: Option<&Path>) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let mut config = match path {
+            Some(path) => Self::read(path)?,
+            None => Self::default(),
+        };
+        config.listen = self.listen.or(config.listen);
+        config.port = self.port.or(config.port);
+        config.tile_size = self.tile_size.or(config.tile_size);
+        config.grid = self.grid.or(config.grid);
+        config.grid_crs = self.grid_crs.or(config.grid_crs);
+        config.resampling = self.resampling.or(config.resampling);
+        config.data_dir = self.data_dir.or(config.data_dir);
+        config.cache_dir = self.cache_dir.or(config.cache_dir);
+        config.audit_dir = self.audit_dir.or(config.audit_dir);
+        config.reverse_y = self.reverse_y.or(config.reverse_y);
+        config.check().map_err(invalid)?;
+        Ok(config)
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        if let Some(size) = self.tile_size {
+            if !(1..=MAX_TILE_SIZE).contains(&size) {
+                return Err(format!(
+                    "the tile size must be between 1 and {} pixels",
+                    MAX_TILE_SIZE
+                ));
+            }
+        }
+        self.tile_grid = self.grid.as_ref().map(GridConfig::tile_grid).transpose()?;
+        if let Some(grid_crs) = &self.grid_crs {
+            let srs = crs::parse_srs(grid_crs)
+                .map_err(|e| format!("invalid grid CRS {}: {}", grid_crs, e))?;
+            let web_mercator = crs::parse_srs("EPSG:3857").map_err(|e| e.to_string())?;
+            // the Web Mercator grid is already in it, and its extent makes no sense in others
+            let tile_grid = match (&self.grid, self.tile_grid.take()) {
+                (Some(GridConfig::Extent { .. }), Some(tile_grid)) if srs != web_mercator => {
+                    tile_grid
+                }
+                _ => {
+                    return Err(
+                        "the grid CRS needs a grid extent, and can't be EPSG:3857".to_string()
+                    )
+                }
+            };
+            self.tile_grid = Some(tile_grid.with_crs(grid_crs.clone()));
+        }
+        Ok(())
+    }
+
+    /// Overrides the settings given in the file.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(size) = self.tile_size {
+            config.tile_width = size;
+            config.tile_height = size;
+        }
+        if let Some(tile_grid) = &self.tile_grid {
+            config.tile_grid = tile_grid.clone();
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            config.cache_dir = cache_dir.clone();
+        }
+        if let Some(audit_dir) = &self.audit_dir {
+            config.audit_dir = Some(audit_dir.clone());
+        }
+        if let Some(reverse_y) = self.reverse_y {
+            config.reverse_y = reverse_y;
+        }
+        if let Some(resampling) = self.resampling {
+            config.resampling = resampling;
+        }
+    }
+}
+
+/// Settings for the handles of open datasets kept between requests.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Idle handles kept open for each dataset.
+    pub size: usize,
+    /// How long idle handles are kept open.
+    pub idle_timeout: Duration,
+    /// Datasets with idle handles kept open, after which the least recently used ones are
+    /// closed.
+    pub max_datasets: usize,
+}
+
+impl PoolConfig {
+    /// Reads the settings from the `TILE_SERVER_POOL_SIZE`, `TILE_SERVER_POOL_IDLE_TIMEOUT` (in
+    /// seconds) and `TILE_SERVER_POOL_MAX_DATASETS` environment variables.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            size: env_var("TILE_SERVER_POOL_SIZE").unwrap_or(default.size),
+            idle_timeout: env_var("TILE_SERVER_POOL_IDLE_TIMEOUT")
+                .map(Duration::from_secs_f64)
+                .unwrap_or(default.idle_timeout),
+            max_datasets: env_var("TILE_SERVER_POOL_MAX_DATASETS").unwrap_or(default.max_datasets),
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            idle_timeout: Duration::from_secs(60),
+            max_datasets: 64,
+        }
+    }
+}
+
+/// Limits on the images rendered at a size chosen by the request, like previews and exports, so
+/// that a single request can't run the server out of memory or keep it busy for long.
+#[derive(Clone, Debug)]
+pub struct OutputLimits {
+    pub max_width: usize,
+    pub max_height: usize,
+    /// Output pixels, at most.
+    pub max_pixels: usize,
+    /// Source pixels read, summed over the bands and counted at the overview level used.
+    pub max_read_pixels: usize,
+}
+
+impl OutputLimits {
+    /// Reads the settings from the `TILE_SERVER_MAX_OUTPUT_WIDTH`,
+    /// `TILE_SERVER_MAX_OUTPUT_HEIGHT`, `TILE_SERVER_MAX_OUTPUT_PIXELS` and
+    /// `TILE_SERVER_MAX_READ_PIXELS` environment variables.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            max_width: env_var("TILE_SERVER_MAX_OUTPUT_WIDTH").unwrap_or(default.max_width),
+            max_height: env_var("TILE_SERVER_MAX_OUTPUT_HEIGHT").unwrap_or(default.max_height),
+            max_pixels: env_var("TILE_SERVER_MAX_OUTPUT_PIXELS").unwrap_or(default.max_pixels),
+            max_read_pixels: env_var("TILE_SERVER_MAX_READ_PIXELS")
+                .unwrap_or(default.max_read_pixels),
+        }
+    }
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 4096,
+            max_height: 4096,
+            max_pixels: 4096 * 4096,
+            max_read_pixels: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings for the threads reading and rendering datasets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerConfig {
+    /// GDAL jobs that can run at once.
+    pub threads: usize,
+    /// GDAL jobs that can wait for a thread, with the requests over it rejected.
+    pub queue_depth: usize,
+    /// Sent in the `Retry-After` header of the rejected requests, in seconds.
+    pub retry_after: u64,
+    /// How long a GDAL job can run before its request fails with a 504, with no limit by default.
+    pub deadline: Option<Duration>,
+}
+
+impl WorkerConfig {
+    /// Reads the settings from the `TILE_SERVER_WORKER_THREADS`, `TILE_SERVER_WORKER_QUEUE_DEPTH`,
+    /// `TILE_SERVER_RETRY_AFTER` and `TILE_SERVER_READ_DEADLINE` (both in seconds) environment
+    /// variables.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            threads: env_var("TILE_SERVER_WORKER_THREADS")
+                .filter(|&threads| threads > 0)
+                .unwrap_or(default.threads),
+            queue_depth: env_var("TILE_SERVER_WORKER_QUEUE_DEPTH").unwrap_or(default.queue_depth),
+            retry_after: env_var("TILE_SERVER_RETRY_AFTER").unwrap_or(default.retry_after),
+            deadline: env_var("TILE_SERVER_READ_DEADLINE")
+                .filter(|&secs: &f64| secs > 0.0)
+                .map(Duration::from_secs_f64),
+        }
+    }
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            threads: std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(4),
+            queue_depth: 256,
+            retry_after: 1,
+            deadline: None,
+        }
+    }
+}
+
+/// Settings for spreading the tiles across several instances, each rendering and caching its
+/// share of them.
+#[derive(Clone, Debug)]
+pub struct ClusterConfig {
+    /// The base URLs of all the instances, including this one, like `http://10.0.0.1:3000`.
+    pub peers: Vec<String>,
+    /// The URL of this instance, as found in `peers`.
+    pub self_url: String,
+    /// Sent with the forwarded requests, so that the peers don't check their API keys again.
+    pub secret: Option<String>,
+}
+
+impl ClusterConfig {
+    /// Reads the settings from the `TILE_SERVER_CLUSTER_PEERS` (comma-separated),
+    /// `TILE_SERVER_CLUSTER_SELF` and `TILE_SERVER_CLUSTER_SECRET` environment variables, if
+    /// the first two are set.
+    pub fn from_env() -> Option<Self> {
+        let peers = std::env::var("TILE_SERVER_CLUSTER_PEERS")
+            .ok()?
+            .split(',')
+            .map(|peer| peer.trim().trim_end_matches('/').to_string())
+            .filter(|peer| !peer.is_empty())
+            .collect();
+        let self_url = std::env::var("TILE_SERVER_CLUSTER_SELF").ok()?;
+        Some(Self {
+            peers,
+            self_url: self_url.trim().trim_end_matches('/').to_string(),
+            secret: std::env::var("TILE_SERVER_CLUSTER_SECRET").ok(),
+        })
+    }
+}
+
+/// A customer catalogue, served under `/t/<name>` apart from the others.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TenantConfig {
+    /// The directory the datasets of the tenant are found in, with its own `datasets.json` and
+    /// `profiles.json`.
+    pub root: PathBuf,
+    /// A JSON file with the API keys of the tenant, like `TILE_SERVER_API_KEYS`. The keys of
+    /// the server are required instead when unset.
+    pub api_keys: Option<PathBuf>,
+    /// Host names the tenant is also served on, without the prefix.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+/// Settings for datasets read over HTTP.
+#[derive(Clone, Debug)]
+pub struct RemoteConfig {
+    /// Hosts that datasets can be read from, either exact names or `*.example.com` patterns.
+    pub allowed_hosts: Vec<String>,
+    /// Size of the GDAL cache of downloaded blocks, in bytes.
+    pub cache_size: Option<u64>,
+    pub max_retry: u32,
+    /// Delay before the first retry in seconds, doubled after each attempt.
+    pub retry_delay: f64,
+}
+
+/// How the log lines are formatted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the fields of the spans it was logged in, like the request
+    /// id or the dataset and coordinates of a tile.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Reads the format from the `TILE_SERVER_LOG_FORMAT` environment variable, either `text`
+    /// (the default) or `json`.
+    pub fn from_env() -> Self {
+        env_var("TILE_SERVER_LOG_FORMAT").unwrap_or(Self::Text)
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.parse().ok()
+}
+
+impl RemoteConfig {
+    /// Reads the settings from the `TILE_SERVER_ALLOWED_HOSTS` (comma-separated),
+    /// `TILE_SERVER_HTTP_CACHE_SIZE`, `TILE_SERVER_HTTP_MAX_RETRY` and
+    /// `TILE_SERVER_HTTP_RETRY_DELAY` environment variables.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            allowed_hosts: std::env::var("TILE_SERVER_ALLOWED_HOSTS")
+                .map(|hosts| {
+                    hosts
+                        .split(',')
+                        .map(|host| host.trim().to_string())
+                        .filter(|host| !host.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            cache_size: env_var("TILE_SERVER_HTTP_CACHE_SIZE"),
+            max_retry: env_var("TILE_SERVER_HTTP_MAX_RETRY").unwrap_or(default.max_retry),
+            retry_delay: env_var("TILE_SERVER_HTTP_RETRY_DELAY").unwrap_or(default.retry_delay),
+        }
+    }
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            cache_size: None,
+            max_retry: 3,
+            retry_delay: 1.0,
+        }
+    }
+}
+
+/// Descriptive metadata of a dataset, shown in the generated documents and the viewer.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DatasetInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+/// Selects a 2D slice of an array in a multidimensional dataset like a Zarr store.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ArraySelection {
+    /// The name of the array, like `/temperature`.
+    pub variable: Option<String>,
+    /// Indices along the non-spatial dimensions of the array, like time or depth, in order.
+    #[serde(default)]
+    pub indices: Vec<u64>,
+}
+
+/// Describes one of the variables of a multidimensional file, registered as its own dataset.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct VariableConfig {
+    /// Styling parameters used when requests don't pass them.
+    pub style: Option<StyleQuery>,
+    #[serde(flatten)]
+    pub info: DatasetInfo,
+}
+
+/// Pairs the bands of a multispectral raster with a higher-resolution panchromatic band, for the
+/// pan-sharpened rendering of a dataset.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pansharpen {
+    /// The raster with the panchromatic band, relative to the data directory.
+    pub panchromatic: PathBuf,
+    /// The panchromatic band, the first one by default.
+    #[serde(default = "default_panchromatic_band")]
+    pub band: isize,
+    /// The multispectral bands to sharpen, all of them by default.
+    pub bands: Option<Vec<isize>>,
+    /// The weights of the bands in the intensity matched to the panchromatic band, equal by
+    /// default.
+    pub weights: Option<Vec<f64>>,
+}
+
+fn default_panchromatic_band() -> isize {
+    1
+}
+
+/// A dataset entry in `datasets.json`, also used to add datasets through the admin API.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DatasetConfig {
+    /// Where to read the dataset from, for datasets not found in the data directory, like
+    /// `PG:` connection strings.
+    pub path: Option<PathBuf>,
+    /// A glob pattern like `scenes/*.tif`, matching the rasters to mosaic into a VRT instead of
+    /// a `path`. A `path` to a directory mosaics all the rasters in it.
+    pub glob: Option<String>,
+    /// A STAC API search to mosaic the items of, instead of a `path`.
+    pub stac: Option<StacSearch>,
+    /// A remote WMS layer to serve as tiles, instead of a `path`.
+    pub wms: Option<WmsSource>,
+    /// Names of raster datasets to mosaic into a single one, with the later ones on top.
+    pub group: Option<Vec<String>>,
+    /// For mosaics and groups, blends the overlapping rasters over this many of their pixels
+    /// from their edges, instead of the later ones covering the others.
+    pub feather: Option<f64>,
+    /// The CRS of the dataset, like `EPSG:32635`, replacing the one in its metadata.
+    pub srs_override: Option<String>,
+    /// Serves a raster without a geotransform, like a scanned map or a photo, in pixel space,
+    /// with its longer side spanning the tile grid at zoom 0.
+    #[serde(default)]
+    pub flat: bool,
+    /// Opens the `path` as a Sentinel-2 product.
+    pub sentinel2: Option<Sentinel2>,
+    /// A panchromatic band to sharpen the bands of the dataset with, rendered when the style has
+    /// `pansharpen=true`.
+    pub pansharpen: Option<Pansharpen>,
+    /// Styling parameters used when requests don't pass them.
+    pub style: Option<StyleQuery>,
+    /// The name of a custom renderer registered by the application embedding the server, used
+    /// instead of the default RGB one.
+    pub renderer: Option<String>,
+    /// An expression transforming the values read from the dataset before styling them, like
+    /// `v * 0.0001`.
+    pub script: Option<String>,
+    /// The credentials profile to use for object storage paths, from `profiles.json`.
+    pub profile: Option<String>,
+    /// Descriptions of the variables of NetCDF or HDF files with more than one, keyed by name.
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableConfig>,
+    #[serde(flatten)]
+    pub array: ArraySelection,
+    #[serde(flatten)]
+    pub info: DatasetInfo,
+}
+
+impl DatasetConfig {
+    /// Checks whether the entry says where to read the dataset from.
+    pub fn has_source(&self) -> bool {
+        self.path.is_some()
+            || self.glob.is_some()
+            || self.stac.is_some()
+            || self.wms.is_some()
+            || self.group.is_some()
+    }
 }
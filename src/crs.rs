@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal_sys::OSRAxisMappingStrategy;
+
+use crate::error::Error;
+
+/// Transforms kept on each thread.
+const MAX_TRANSFORMS: usize = 64;
+
+/// Identifies a CRS by its WKT and axis order.
+type SrsKey = (String, OSRAxisMappingStrategy::Type);
+
+thread_local! {
+    /// The transforms created on this thread, since building the PROJ pipelines is expensive.
+    ///
+    /// They're not shared between threads because transforms can't be used concurrently.
+    static TRANSFORMS: RefCell<HashMap<(SrsKey, SrsKey), Rc<CoordTransform>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Parses a user-supplied CRS definition (`EPSG:3857`, WKT, PROJ strings etc.).
+///
+/// The returned `SpatialRef` always uses the traditional GIS axis order (x/lon, y/lat).
+pub fn parse_srs(definition: &str) -> Result<SpatialRef, Error> {
+    let spatial_ref = SpatialRef::from_definition(definition)
+        .map_err(|_| Error::BadRequest(format!("invalid CRS: {}", definition)))?;
+    spatial_ref.set_axis_mapping_strategy(OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(spatial_ref)
+}
+
+pub fn wgs84() -> Result<SpatialRef, Error> {
+    let spatial_ref = SpatialRef::from_epsg(4326)?;
+    spatial_ref.set_axis_mapping_strategy(OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(spatial_ref)
+}
+
+fn srs_key(spatial_ref: &SpatialRef) -> Result<SrsKey, Error> {
+    Ok((spatial_ref.to_wkt()?, spatial_ref.axis_mapping_strategy()))
+}
+
+/// Returns a transform between two CRSs, reusing the one created before on this thread.
+pub fn transform(source: &SpatialRef, target: &SpatialRef) -> Result<Rc<CoordTransform>, Error> {
+    let key = (srs_key(source)?, srs_key(target)?);
+    if let Some(transform) = TRANSFORMS.with(|transforms| transforms.borrow().get(&key).cloned()) {
+        return Ok(transform);
+    }
+    let transform = Rc::new(CoordTransform::new(source, target)?);
+    TRANSFORMS.with(|transforms| {
+        let mut transforms = transforms.borrow_mut();
+        if transforms.len() >= MAX_TRANSFORMS {
+            transforms.clear();
+        }
+        transforms.insert(key, transform.clone());
+    });
+    Ok(transform)
+}
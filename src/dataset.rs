@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use gdal::spatial_ref::{CoordTransform, SpatialRef};
+use gdal::{Dataset, DatasetOptions, GeoTransform, Metadata};
+use gdal_sys::{GDALResampleAlg, OSRAxisMappingStrategy};
+use serde::Deserialize;
+
+use crate::crs;
+use crate::error::Error;
+use crate::remote;
+use crate::tile_grid::{Extent, TileGrid};
+
+/// CRS definitions replacing the ones of datasets with missing or wrong projection metadata.
+static SRS_OVERRIDES: RwLock<BTreeMap<PathBuf, String>> = RwLock::new(BTreeMap::new());
+
+/// Sets or clears the CRS to assign to a dataset when opening it.
+pub fn set_srs_override(path: &Path, srs: Option<String>) {
+    let mut overrides = SRS_OVERRIDES.write().unwrap();
+    match srs {
+        Some(srs) => overrides.insert(path.to_path_buf(), srs),
+        None => overrides.remove(path),
+    };
+}
+
+/// Rasters without a geotransform to serve in pixel space, like scanned maps or photos.
+static FLAT: RwLock<BTreeSet<PathBuf>> = RwLock::new(BTreeSet::new());
+
+/// Sets whether to place a raster without a geotransform on the tile grid when opening it.
+pub fn set_flat(path: &Path, flat: bool) {
+    let mut paths = FLAT.write().unwrap();
+    if flat {
+        paths.insert(path.to_path_buf());
+    } else {
+        paths.remove(path);
+    }
+}
+
+/// Opens a dataset, applying the configuration options of remote ones and the CRS overrides.
+pub fn open(path: &Path) -> Result<Dataset, Error> {
+    open_with_options(path, &[])
+}
+
+/// Opens a dataset with driver-specific open options.
+///
+/// The open options are ignored for datasets with a CRS override or served in pixel space.
+pub fn open_with_options(path: &Path, open_options: &[&str]) -> Result<Dataset, Error> {
+    let srs = SRS_OVERRIDES.read().unwrap().get(path).cloned();
+    let flat = FLAT.read().unwrap().contains(path);
+    remote::with_path_options(path, || match srs {
+        Some(srs) => assign_srs(path, &srs),
+        None if flat => place_flat(path),
+        None => {
+            let options = DatasetOptions {
+                open_options: Some(open_options).filter(|options| !options.is_empty()),
+                ..Default::default()
+            };
+            Ok(Dataset::open_ex(path, options)?)
+        }
+    })
+}
+
+/// Wraps a dataset in an in-memory VRT with another CRS, like `gdal_translate -a_srs`.
+fn assign_srs(path: &Path, srs: &str) -> Result<Dataset, Error> {
+    let args = [CString::new("-a_srs")?, CString::new(srs)?];
+    let mut arg_ptrs = args
+        .iter()
+        .map(|arg| arg.as_ptr() as *mut _)
+        .collect::<Vec<_>>();
+    arg_ptrs.push(ptr::null_mut());
+    let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+    let names = [c_path.as_ptr(), ptr::null()];
+    let empty = CString::new("")?;
+    let c_dataset = unsafe {
+        let options = gdal_sys::GDALBuildVRTOptionsNew(arg_ptrs.as_mut_ptr(), ptr::null_mut());
+        if options.is_null() {
+            return Err(Error::BadRequest(format!("invalid CRS override: {}", srs)));
+        }
+        let c_dataset = gdal_sys::GDALBuildVRT(
+            empty.as_ptr(),
+            1,
+            ptr::null_mut(),
+            names.as_ptr(),
+            options,
+            ptr::null_mut(),
+        );
+        gdal_sys::GDALBuildVRTOptionsFree(options);
+        c_dataset
+    };
+    if c_dataset.is_null() {
+        return Err(Error::last_cpl_error(gdal_sys::CPLErr::CE_Failure));
+    }
+    Ok(unsafe { Dataset::from_c_dataset(c_dataset) })
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Wraps a raster without a geotransform in a VRT placing it at the top left of the Web Mercator
+/// grid, with its longer side spanning the grid, so that it's tiled in pixel space.
+fn place_flat(path: &Path) -> Result<Dataset, Error> {
+    let source = Dataset::open(path)?;
+    let (width, height) = source.raster_size();
+    let extent = TileGrid::web_mercator().extent().clone();
+    let pixel_size = (extent.xmax - extent.xmin) / width.max(height) as f64;
+    let srs = crs::parse_srs("EPSG:3857")?.to_wkt()?;
+    let mut vrt = format!(
+        r#"<VRTDataset rasterXSize="{}" rasterYSize="{}"><SRS>{}</SRS><GeoTransform>{}, {}, 0, {}, 0, {}</GeoTransform>"#,
+        width,
+        height,
+        escape_xml(&srs),
+        extent.xmin,
+        pixel_size,
+        extent.ymax,
+        -pixel_size
+    );
+    let source_path = escape_xml(&path.to_string_lossy());
+    for band in 1..=source.raster_count() {
+        let rasterband = source.rasterband(band)?;
+        let (data_type, color) = unsafe {
+            let data_type = gdal_sys::GDALGetDataTypeName(rasterband.band_type());
+            let color =
+                gdal_sys::GDALGetColorInterpretationName(rasterband.color_interpretation().c_int());
+            (
+                CStr::from_ptr(data_type).to_string_lossy().into_owned(),
+                CStr::from_ptr(color).to_string_lossy().into_owned(),
+            )
+        };
+        let _ = write!(
+            vrt,
+            r#"<VRTRasterBand dataType="{}" band="{}"><ColorInterp>{}</ColorInterp>"#,
+            data_type, band, color
+        );
+        if let Some(no_data) = rasterband.no_data_value() {
+            let _ = write!(vrt, "<NoDataValue>{}</NoDataValue>", no_data);
+        }
+        let _ = write!(
+            vrt,
+            r#"<SimpleSource><SourceFilename relativeToVRT="0">{}</SourceFilename><SourceBand>{}</SourceBand></SimpleSource></VRTRasterBand>"#,
+            source_path, band
+        );
+    }
+    vrt.push_str("</VRTDataset>");
+    Ok(Dataset::open(Path::new(&vrt))?)
+}
+
+/// Returns the geotransform of a raster, failing with `Error::NotGeoreferenced` for the ones
+/// without one, for which GDAL reports an error or the identity transform.
+pub fn geo_transform(dataset: &Dataset) -> Result<GeoTransform, Error> {
+    match dataset.geo_transform() {
+        Ok(geo_transform) if geo_transform != [0.0, 1.0, 0.0, 0.0, 0.0, 1.0] => Ok(geo_transform),
+        _ => Err(Error::NotGeoreferenced(
+            dataset.description().unwrap_or_default(),
+        )),
+    }
+}
+
+/// Returns the unit of the physical values of a band, like `K` or `m`, if known.
+pub fn band_unit(dataset: &Dataset, band: isize) -> Option<String> {
+    let unit = unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _);
+        if c_band.is_null() {
+            return None;
+        }
+        let c_unit = gdal_sys::GDALGetRasterUnitType(c_band);
+        if c_unit.is_null() {
+            return None;
+        }
+        CStr::from_ptr(c_unit).to_string_lossy().into_owned()
+    };
+    Some(unit).filter(|unit| !unit.is_empty())
+}
+
+/// Lists the subdatasets of a dataset, as their paths and descriptions.
+pub fn subdatasets(dataset: &Dataset) -> Vec<(String, String)> {
+    let items = dataset.metadata_domain("SUBDATASETS").unwrap_or_default();
+    let item = |key: String| {
+        items
+            .iter()
+            .find_map(|item| item.strip_prefix(&key)?.strip_prefix('='))
+            .map(str::to_string)
+    };
+    (1..)
+        .map_while(|i| {
+            let name = item(format!("SUBDATASET_{}_NAME", i))?;
+            let description = item(format!("SUBDATASET_{}_DESC", i)).unwrap_or_default();
+            Some((name, description))
+        })
+        .collect()
+}
+
+pub fn image_extent(geo_transform: &GeoTransform, raster_size: (usize, usize)) -> Extent {
+    let (x_min, x_size, y_max, y_size) = (
+        geo_transform[0],
+        geo_transform[1],
+        geo_transform[3],
+        geo_transform[5],
+    );
+    Extent {
+        xmin: x_min,
+        ymin: y_max + y_size * raster_size.1 as f64,
+        xmax: x_min + x_size * raster_size.0 as f64,
+        ymax: y_max,
+    }
+}
+
+/// Number of points sampled along each edge when reprojecting an extent.
+const EDGE_SAMPLES: usize = 21;
+
+/// Transforms an extent to another spatial reference, returning the bounding box of the result.
+///
+/// The edges are densified, since they might not be straight lines in the target CRS.
+pub fn reproject_extent(extent: &Extent, transform: &CoordTransform) -> Result<Extent, Error> {
+    let mut x = Vec::with_capacity(4 * EDGE_SAMPLES);
+    let mut y = Vec::with_capacity(4 * EDGE_SAMPLES);
+    for i in 0..EDGE_SAMPLES {
+        let t = i as f64 / (EDGE_SAMPLES - 1) as f64;
+        let px = extent.xmin + (extent.xmax - extent.xmin) * t;
+        let py = extent.ymin + (extent.ymax - extent.ymin) * t;
+        x.extend_from_slice(&[px, px, extent.xmin, extent.xmax]);
+        y.extend_from_slice(&[extent.ymin, extent.ymax, py, py]);
+    }
+    let mut z = vec![0.0; x.len()];
+    transform.transform_coords(&mut x, &mut y, &mut z)?;
+    Ok(Extent {
+        xmin: x.iter().copied().fold(f64::INFINITY, f64::min),
+        ymin: y.iter().copied().fold(f64::INFINITY, f64::min),
+        xmax: x.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        ymax: y.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+    })
+}
+
+/// Maps georeferenced coordinates to fractional pixel coordinates (column, row).
+pub fn world_to_pixel(geo_transform: &GeoTransform, x: f64, y: f64) -> (f64, f64) {
+    let [x0, a, b, y0, d, e] = *geo_transform;
+    let det = a * e - b * d;
+    let (dx, dy) = (x - x0, y - y0);
+    ((e * dx - b * dy) / det, (a * dy - d * dx) / det)
+}
+
+/// Returns the pixel containing the given georeferenced coordinates, if it's inside the raster.
+pub fn pixel_at(
+    geo_transform: &GeoTransform,
+    raster_size: (usize, usize),
+    x: f64,
+    y: f64,
+) -> Option<(usize, usize)> {
+    let (col, row) = world_to_pixel(geo_transform, x, y);
+    if col < 0.0 || row < 0.0 || col >= raster_size.0 as f64 || row >= raster_size.1 as f64 {
+        return None;
+    }
+    Some((col.floor() as usize, row.floor() as usize))
+}
+
+pub fn spatial_ref(dataset: &Dataset) -> Result<SpatialRef, Error> {
+    let spatial_ref = dataset.spatial_ref()?;
+    spatial_ref.set_axis_mapping_strategy(OSRAxisMappingStrategy::OAMS_TRADITIONAL_GIS_ORDER);
+    Ok(spatial_ref)
+}
+
+/// How the pixels of reprojected rasters are resampled.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Resampling {
+    #[default]
+    Nearest,
+    Bilinear,
+    Cubic,
+    CubicSpline,
+    Lanczos,
+    Average,
+    Mode,
+}
+
+impl Resampling {
+    fn alg(self) -> GDALResampleAlg::Type {
+        match self {
+            Resampling::Nearest => GDALResampleAlg::GRA_NearestNeighbour,
+            Resampling::Bilinear => GDALResampleAlg::GRA_Bilinear,
+            Resampling::Cubic => GDALResampleAlg::GRA_Cubic,
+            Resampling::CubicSpline => GDALResampleAlg::GRA_CubicSpline,
+            Resampling::Lanczos => GDALResampleAlg::GRA_Lanczos,
+            Resampling::Average => GDALResampleAlg::GRA_Average,
+            Resampling::Mode => GDALResampleAlg::GRA_Mode,
+        }
+    }
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Resampling::Nearest),
+            "bilinear" => Ok(Resampling::Bilinear),
+            "cubic" => Ok(Resampling::Cubic),
+            "cubicspline" => Ok(Resampling::CubicSpline),
+            "lanczos" => Ok(Resampling::Lanczos),
+            "average" => Ok(Resampling::Average),
+            "mode" => Ok(Resampling::Mode),
+            _ => Err(format!("unknown resampling method: {}", s)),
+        }
+    }
+}
+
+/// A warped VRT, along with the dataset it reads from.
+pub struct Warped {
+    // NOTE: the VRT must be closed before its source
+    vrt: Dataset,
+    _source: Dataset,
+}
+
+impl Deref for Warped {
+    type Target = Dataset;
+
+    fn deref(&self) -> &Dataset {
+        &self.vrt
+    }
+}
+
+/// Wraps a dataset in a warped VRT with the given target spatial reference.
+pub fn warp(
+    source: Dataset,
+    spatial_ref: &SpatialRef,
+    resampling: Resampling,
+) -> Result<Warped, Error> {
+    warp_to_wkt(source, &spatial_ref.to_wkt()?, resampling)
+}
+
+/// Wraps a dataset in a warped VRT with the target spatial reference given as WKT.
+pub fn warp_to_wkt(source: Dataset, wkt: &str, resampling: Resampling) -> Result<Warped, Error> {
+    let dst_wkt = CString::new(wkt)?;
+    let c_dataset = unsafe {
+        gdal_sys::GDALAutoCreateWarpedVRT(
+            source.c_dataset(),
+            ptr::null(),
+            dst_wkt.as_ptr(),
+            resampling.alg(),
+            0.125,
+            ptr::null(),
+        )
+    };
+    if c_dataset.is_null() {
+        return Err(Error::BadRequest(
+            "cannot reproject dataset to the requested CRS".to_string(),
+        ));
+    }
+    let vrt = unsafe { Dataset::from_c_dataset(c_dataset) };
+    Ok(Warped {
+        vrt,
+        _source: source,
+    })
+}
+
+/// A dataset, or a warped VRT reading from it.
+pub enum Reprojected {
+    Source(Dataset),
+    Warped(Warped),
+}
+
+impl Deref for Reprojected {
+    type Target = Dataset;
+
+    fn deref(&self) -> &Dataset {
+        match self {
+            Reprojected::Source(dataset) => dataset,
+            Reprojected::Warped(warped) => warped,
+        }
+    }
+}
+
+/// Opens a dataset, reprojected to the given CRS unless it's already in it.
+pub fn open_in_crs(path: &Path, crs: Option<&str>) -> Result<Reprojected, Error> {
+    let source = open(path)?;
+    if let Some(crs) = crs {
+        let srs = crs::parse_srs(crs)?;
+        if srs != spatial_ref(&source)? {
+            return Ok(Reprojected::Warped(warp(
+                source,
+                &srs,
+                Resampling::Nearest,
+            )?));
+        }
+    }
+    Ok(Reprojected::Source(source))
+}
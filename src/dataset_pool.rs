@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+use gdal::Dataset;
+
+use crate::config::PoolConfig;
+use crate::dataset::{self, Reprojected, Resampling};
+use crate::error::Error;
+
+/// The reprojection of the handles of a dataset warped to another CRS.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Warp {
+    /// The WKT of the target CRS.
+    pub wkt: String,
+    pub resampling: Resampling,
+}
+
+/// Identifies the handles of a dataset, as it is or warped to another CRS.
+type Key = (PathBuf, Option<Warp>);
+
+/// Keeps datasets open between requests, since opening them can be expensive, especially for
+/// remote files and PostGIS rasters.
+///
+/// GDAL datasets can't be shared between threads, so each handle is only used by one request
+/// at a time.
+pub struct DatasetPool {
+    config: RwLock<PoolConfig>,
+    idle: Mutex<HashMap<Key, Vec<(Reprojected, Instant)>>>,
+    /// When the handles of a dataset were last evicted, so that the ones checked out before
+    /// aren't kept when returned. Only the last `max_datasets` evictions are remembered.
+    evicted: Mutex<HashMap<PathBuf, Instant>>,
+    /// Handles checked out by requests.
+    in_use: AtomicUsize,
+}
+
+/// A snapshot of the handles of the pool.
+pub struct PoolStats {
+    pub in_use: usize,
+    pub idle: usize,
+    /// Datasets with idle handles.
+    pub datasets: usize,
+}
+
+/// A dataset checked out from the pool, returned to it when dropped.
+pub struct PooledDataset<'a> {
+    pool: &'a DatasetPool,
+    key: Key,
+    dataset: Option<Reprojected>,
+    checked_out: Instant,
+}
+
+impl Deref for PooledDataset<'_> {
+    type Target = Dataset;
+
+    fn deref(&self) -> &Dataset {
+        self.dataset.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledDataset<'_> {
+    fn drop(&mut self) {
+        self.pool.in_use.fetch_sub(1, Ordering::Relaxed);
+        if let Some(dataset) = self.dataset.take() {
+            let key = std::mem::replace(&mut self.key, (PathBuf::new(), None));
+            self.pool.release(key, dataset, self.checked_out);
+        }
+    }
+}
+
+/// Closes the handles of the datasets released the longest ago, keeping at most `max_datasets`
+/// of them open.
+fn close_least_recent(idle: &mut HashMap<Key, Vec<(Reprojected, Instant)>>, max_datasets: usize) {
+    while idle.len() > max_datasets {
+        // the handles of each dataset are in the order they were released
+        let least_recent = idle
+            .iter()
+            .filter_map(|(key, datasets)| Some((datasets.last()?.1, key)))
+            .min_by_key(|&(released, _)| released)
+            .map(|(_, key)| key.clone());
+        match least_recent {
+            Some(key) => idle.remove(&key),
+            None => break,
+        };
+    }
+}
+
+impl DatasetPool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            idle: Mutex::new(HashMap::new()),
+            evicted: Mutex::new(HashMap::new()),
+            in_use: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Result<PooledDataset<'_>, Error> {
+        self.checkout((path.to_path_buf(), None))
+    }
+
+    /// Returns a handle of a dataset warped to another CRS, keeping the warped VRT in the pool
+    /// like the other handles.
+    pub fn get_warped(&self, path: &Path, warp: &Warp) -> Result<PooledDataset<'_>, Error> {
+        self.checkout((path.to_path_buf(), Some(warp.clone())))
+    }
+
+    fn checkout(&self, key: Key) -> Result<PooledDataset<'_>, Error> {
+        let checked_out = Instant::now();
+        let idle = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .and_then(|datasets| datasets.pop())
+            .filter(|(_, released)| released.elapsed() < self.config().idle_timeout)
+            .map(|(dataset, _)| dataset);
+        let dataset = match idle {
+            Some(dataset) => dataset,
+            None => {
+                let source = dataset::open(&key.0)?;
+                match &key.1 {
+                    Some(warp) => Reprojected::Warped(dataset::warp_to_wkt(
+                        source,
+                        &warp.wkt,
+                        warp.resampling,
+                    )?),
+                    None => Reprojected::Source(source),
+                }
+            }
+        };
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        Ok(PooledDataset {
+            pool: self,
+            key,
+            dataset: Some(dataset),
+            checked_out,
+        })
+    }
+
+    fn release(&self, key: Key, dataset: Reprojected, checked_out: Instant) {
+        let config = self.config();
+        if config.size == 0 || config.max_datasets == 0 {
+            return;
+        }
+        let evicted = self.evicted.lock().unwrap().get(&key.0).copied();
+        if evicted.is_some_and(|evicted| evicted >= checked_out) {
+            return;
+        }
+        let now = Instant::now();
+        let mut idle = self.idle.lock().unwrap();
+        // close the handles that weren't used for a while, for all datasets
+        idle.retain(|_, datasets| {
+            datasets.retain(|(_, released)| now - *released < config.idle_timeout);
+            !datasets.is_empty()
+        });
+        let datasets = idle.entry(key).or_default();
+        if datasets.len() >= config.size {
+            // the oldest one is the least likely to be needed
+            datasets.remove(0);
+        }
+        datasets.push((dataset, now));
+        close_least_recent(&mut idle, config.max_datasets);
+    }
+
+    pub fn config(&self) -> PoolConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Changes the handle limits, closing the idle handles that exceed them.
+    pub fn set_config(&self, config: PoolConfig) {
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|_, datasets| {
+            datasets.retain(|(_, released)| released.elapsed() < config.idle_timeout);
+            // the oldest ones are the least likely to be needed
+            let excess = datasets.len().saturating_sub(config.size);
+            datasets.drain(..excess);
+            !datasets.is_empty()
+        });
+        close_least_recent(&mut idle, config.max_datasets);
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let idle = self.idle.lock().unwrap();
+        PoolStats {
+            in_use: self.in_use.load(Ordering::Relaxed),
+            idle: idle.values().map(Vec::len).sum(),
+            datasets: idle.len(),
+        }
+    }
+
+    /// Closes the idle handles, e.g. after the datasets were changed.
+    pub fn clear(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+
+    /// Closes the handles of a dataset whose file changed, including the ones in use once
+    /// they're returned.
+    pub fn evict(&self, path: &Path) {
+        let max_datasets = self.config().max_datasets.max(1);
+        let mut evicted = self.evicted.lock().unwrap();
+        evicted.insert(path.to_path_buf(), Instant::now());
+        // the handles checked out before the oldest evictions were most likely returned since
+        while evicted.len() > max_datasets {
+            let oldest = evicted
+                .iter()
+                .min_by_key(|&(_, evicted)| evicted)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                evicted.remove(&oldest);
+            }
+        }
+        drop(evicted);
+        self.idle.lock().unwrap().retain(|(key, _), _| key != path);
+    }
+}
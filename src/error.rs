@@ -1,10 +1,12 @@
-use std::ffi::NulError;
+use std::ffi::{CStr, NulError};
 use std::fmt::{self, Display, Formatter};
+use std::sync::Once;
 use std::{error, io};
 
 use axum::response::{IntoResponse, Response};
-use gdal::errors::GdalError;
-use hyper::StatusCode;
+use gdal::errors::{CplErrType, GdalError};
+use gdal_sys::CPLErr;
+use hyper::{header, StatusCode};
 use tokio::task::JoinError;
 
 #[derive(Debug)]
@@ -12,12 +14,57 @@ pub enum Error {
     Io(io::Error),
     Nul(NulError),
     Gdal(GdalError),
+    Sqlite(rusqlite::Error),
     Hyper(hyper::Error),
     Join(JoinError),
     OutsideBounds,
+    UnknownDataset(String),
+    BadRequest(String),
+    /// The raster at a path has no geotransform, so it can't be placed on the tile grid.
+    NotGeoreferenced(String),
+    /// Too many requests are waiting for a worker thread, retry after some seconds.
+    Overloaded(u64),
+    /// A GDAL job ran past `WorkerConfig::deadline`, like when the storage is unresponsive.
+    DeadlineExceeded,
+    /// A bug, like a thread that panicked.
+    Internal(String),
     Infallible(std::convert::Infallible),
 }
 
+impl Error {
+    /// Builds an error from the last GDAL error, for calls made through `gdal_sys`.
+    pub fn last_cpl_error(class: CPLErr::Type) -> Self {
+        let (number, msg) = unsafe {
+            (
+                gdal_sys::CPLGetLastErrorNo(),
+                CStr::from_ptr(gdal_sys::CPLGetLastErrorMsg())
+                    .to_string_lossy()
+                    .into_owned(),
+            )
+        };
+        Error::Gdal(GdalError::CplError { class, number, msg })
+    }
+}
+
+/// Routes the GDAL errors and warnings to `tracing` with the `gdal` target, instead of printing
+/// them to stderr. They are logged in the span of the request they happened in, since GDAL calls
+/// the handler on the thread raising them.
+pub fn log_gdal_errors() {
+    static INSTALLED: Once = Once::new();
+
+    INSTALLED.call_once(|| {
+        gdal::config::set_error_handler(|class, number, msg| match class {
+            CplErrType::None | CplErrType::Debug => {
+                tracing::debug!(target: "gdal", number, "{}", msg)
+            }
+            CplErrType::Warning => tracing::warn!(target: "gdal", number, "{}", msg),
+            CplErrType::Failure | CplErrType::Fatal => {
+                tracing::error!(target: "gdal", number, "{}", msg)
+            }
+        })
+    });
+}
+
 impl From<NulError> for Error {
     fn from(v: NulError) -> Self {
         Error::Nul(v)
@@ -30,6 +77,12 @@ impl From<GdalError> for Error {
     }
 }
 
+impl From<rusqlite::Error> for Error {
+    fn from(v: rusqlite::Error) -> Self {
+        Error::Sqlite(v)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(v: io::Error) -> Self {
         Error::Io(v)
@@ -59,9 +112,21 @@ impl Display for Error {
             Error::Io(e) => e.fmt(f),
             Error::Nul(e) => e.fmt(f),
             Error::Gdal(e) => e.fmt(f),
+            Error::Sqlite(e) => e.fmt(f),
             Error::Hyper(e) => e.fmt(f),
             Error::Join(e) => e.fmt(f),
             Error::OutsideBounds => f.write_str("tile is outside image bounds"),
+            Error::UnknownDataset(name) => write!(f, "unknown dataset: {}", name),
+            Error::BadRequest(e) => f.write_str(e),
+            Error::NotGeoreferenced(path) => write!(
+                f,
+                "{} has no geotransform, so it can't be placed on the map; set \"flat\": true \
+                 in its datasets.json entry to serve it as a plain image",
+                path
+            ),
+            Error::Overloaded(_) => f.write_str("server is overloaded"),
+            Error::DeadlineExceeded => f.write_str("reading the dataset took too long"),
+            Error::Internal(e) => f.write_str(e),
             Error::Infallible(e) => e.fmt(f),
         }
     }
@@ -73,9 +138,16 @@ impl error::Error for Error {
             Error::Io(e) => Some(e),
             Error::Nul(e) => Some(e),
             Error::Gdal(e) => Some(e),
+            Error::Sqlite(e) => Some(e),
             Error::Hyper(e) => Some(e),
             Error::Join(e) => Some(e),
             Error::OutsideBounds => None,
+            Error::UnknownDataset(_) => None,
+            Error::BadRequest(_) => None,
+            Error::NotGeoreferenced(_) => None,
+            Error::Overloaded(_) => None,
+            Error::DeadlineExceeded => None,
+            Error::Internal(_) => None,
             Error::Infallible(e) => Some(e),
         }
     }
@@ -85,7 +157,28 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
             Error::OutsideBounds => (StatusCode::NOT_FOUND, ()).into_response(),
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, ()).into_response(),
+            Error::UnknownDataset(_) => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::BadRequest(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+            Error::NotGeoreferenced(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response()
+            }
+            Error::Overloaded(retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response(),
+            Error::DeadlineExceeded => {
+                (StatusCode::GATEWAY_TIMEOUT, self.to_string()).into_response()
+            }
+            _ => {
+                #[allow(unused_mut)]
+                let mut response = (StatusCode::INTERNAL_SERVER_ERROR, ()).into_response();
+                #[cfg(feature = "sentry")]
+                response
+                    .extensions_mut()
+                    .insert(crate::sentry::Reported(self.to_string()));
+                response
+            }
         }
     }
 }
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use gdal::raster::RasterCreationOption;
+use gdal::{Dataset, Driver};
+
+use crate::cache;
+use crate::config::{Config, OutputLimits};
+use crate::dataset;
+use crate::error::Error;
+use crate::preview;
+use crate::raster_info::RasterInfo;
+use crate::registry::{Kind, Registry};
+use crate::remote;
+use crate::render;
+use crate::renderer::RgbRenderer;
+use crate::style::{Style, StyleQuery};
+use crate::tile_grid::Extent;
+
+#[derive(Args)]
+pub struct Options {
+    /// The dataset to render
+    #[arg(long)]
+    dataset: String,
+    /// The GeoTIFF (.tif) or PNG (.png) file to write
+    #[arg(long)]
+    output: PathBuf,
+    /// The extent to render, as xmin,ymin,xmax,ymax in the dataset CRS or --crs [default: all of
+    /// the dataset]
+    #[arg(long, value_parser = crate::parse_bbox)]
+    bbox: Option<Extent>,
+    /// The CRS to render in, instead of the one of the dataset
+    #[arg(long)]
+    crs: Option<String>,
+    /// The size of the pixels, in the units of the CRS
+    #[arg(long, value_parser = crate::parse_positive, conflicts_with_all = ["width", "height"])]
+    resolution: Option<f64>,
+    #[arg(long)]
+    width: Option<usize>,
+    #[arg(long)]
+    height: Option<usize>,
+    /// The tile endpoint parameters, like 'bands=1&rescale=0,3000&colormap=viridis'
+    #[arg(long, default_value = "", value_parser = parse_style)]
+    style: StyleQuery,
+}
+
+fn parse_style(style: &str) -> Result<StyleQuery, String> {
+    serde_urlencoded::from_str(style).map_err(|e| format!("invalid style: {}", e))
+}
+
+fn size_at_resolution(
+    extent: &Extent,
+    resolution: f64,
+    limits: &OutputLimits,
+) -> Result<(usize, usize), Error> {
+    let width = ((extent.xmax - extent.xmin) / resolution).round();
+    let height = ((extent.ymax - extent.ymin) / resolution).round();
+    if !(width >= 1.0 && height >= 1.0) {
+        return Err(Error::BadRequest(format!(
+            "the resolution is too coarse for the extent, giving a {}x{} image",
+            width, height
+        )));
+    }
+    preview::output_size(extent, Some(width as usize), Some(height as usize), limits)
+}
+
+/// Writes a PNG with a world file next to it, named like `image.pgw`.
+fn write_png(image: &Dataset, path: &Path, geo_transform: &[f64; 6]) -> Result<(), Error> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| Error::BadRequest("invalid --output".to_string()))?;
+    render::write_png(image, path)?;
+    let [xmin, x_size, _, ymax, _, y_size] = *geo_transform;
+    // the world file refers to the center of the top-left pixel
+    let world = format!(
+        "{}\n0\n0\n{}\n{}\n{}\n",
+        x_size,
+        y_size,
+        xmin + x_size / 2.0,
+        ymax + y_size / 2.0
+    );
+    std::fs::write(Path::new(path).with_extension("pgw"), world)?;
+    Ok(())
+}
+
+fn write_geotiff(
+    image: &Dataset,
+    path: &Path,
+    geo_transform: &[f64; 6],
+    source: &Dataset,
+) -> Result<(), Error> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| Error::BadRequest("invalid --output".to_string()))?;
+    let options = [
+        RasterCreationOption {
+            key: "COMPRESS",
+            value: "DEFLATE",
+        },
+        RasterCreationOption {
+            key: "PHOTOMETRIC",
+            value: "RGB",
+        },
+        RasterCreationOption {
+            key: "ALPHA",
+            value: "YES",
+        },
+    ];
+    let mut out = image.create_copy(&Driver::get("GTiff")?, path, &options)?;
+    out.set_geo_transform(geo_transform)?;
+    out.set_spatial_ref(&dataset::spatial_ref(source)?)?;
+    Ok(())
+}
+
+/// Renders an extent of a dataset, styled like its tiles, into a georeferenced GeoTIFF or a PNG
+/// with a world file, depending on the extension of `--output`.
+pub fn run(options: Options) -> Result<(), Error> {
+    let config = Config::from_env();
+    remote::configure(&config.remote)?;
+    cache::set_dir(&config.cache_dir);
+    render::set_png_srgb(config.png_srgb);
+    let registry = Registry::new(PathBuf::from("."), config.remote.clone())?;
+    let entry = registry.get(&options.dataset)?;
+    if !matches!(entry.kind, Kind::Raster | Kind::GeoPackage) {
+        return Err(Error::BadRequest(format!(
+            "{} is not a raster dataset",
+            options.dataset
+        )));
+    }
+    let extension = options
+        .output
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let geotiff = match extension.as_deref() {
+        Some("tif") | Some("tiff") => true,
+        Some("png") => false,
+        _ => {
+            return Err(Error::BadRequest(format!(
+                "unsupported output format: {}",
+                options.output.display()
+            )))
+        }
+    };
+
+    let style = options.style.with_defaults(&entry.style);
+    let dataset = &*dataset::open_in_crs(entry.styled_path(&style)?, options.crs.as_deref())?;
+    let info = RasterInfo::read(dataset)?;
+    let extent = options.bbox.clone().unwrap_or_else(|| info.extent.clone());
+    let limits = &config.output_limits;
+    let (width, height) = match options.resolution {
+        Some(resolution) => size_at_resolution(&extent, resolution, limits)?,
+        None => preview::output_size(&extent, options.width, options.height, limits)?,
+    };
+    let style = Style::parse(&style, dataset.raster_count())?;
+    let bands = style.bands.len();
+    preview::check_read_cost(dataset, &info, &extent, (width, height), bands, limits)?;
+    #[cfg(feature = "scripts")]
+    let style = Style {
+        script: entry.script.clone(),
+        ..style
+    };
+    let image = match render::render(dataset, &info, &extent, width, height, &style, &RgbRenderer) {
+        Err(Error::OutsideBounds) => {
+            return Err(Error::BadRequest(
+                "the extent doesn't intersect the dataset".to_string(),
+            ))
+        }
+        image => image?,
+    };
+    let geo_transform = [
+        extent.xmin,
+        (extent.xmax - extent.xmin) / width as f64,
+        0.0,
+        extent.ymax,
+        0.0,
+        (extent.ymin - extent.ymax) / height as f64,
+    ];
+    if geotiff {
+        write_geotiff(&image, &options.output, &geo_transform, dataset)?;
+    } else {
+        write_png(&image, &options.output, &geo_transform)?;
+    }
+    println!(
+        "exported a {}x{} image to {}",
+        width,
+        height,
+        options.output.display()
+    );
+    Ok(())
+}
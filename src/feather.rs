@@ -0,0 +1,132 @@
+//! Feathered blending of the overlapping rasters of mosaics and groups, which fades each raster
+//! in from its edges over the ones below it, instead of the hard seams of drawing the later
+//! ones on top.
+//!
+//! The weights only depend on the distance to the edges of the rasters, not on their nodata
+//! areas, so that they're the same on both sides of the tile edges.
+
+use std::path::PathBuf;
+
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::raster_info::{self, RasterInfo};
+use crate::render::{self, Window};
+use crate::renderer::SourceBand;
+use crate::tile_grid::Extent;
+
+pub struct Feather {
+    /// How far from its edges a raster is fully opaque, in its pixels.
+    width: f64,
+    /// The rasters of the mosaic, from the bottom to the top.
+    sources: Vec<PathBuf>,
+}
+
+fn source_bands(info: &RasterInfo, bands: &[isize]) -> Result<Vec<SourceBand>, Error> {
+    bands
+        .iter()
+        .map(|&band| {
+            let band = info.band(band)?;
+            Ok(SourceBand {
+                no_data: band.no_data,
+                scale: band.scale,
+                offset: band.offset,
+            })
+        })
+        .collect()
+}
+
+impl Feather {
+    pub fn new(width: f64, sources: Vec<PathBuf>) -> Result<Self, Error> {
+        if !width.is_finite() || width <= 0.0 {
+            return Err(Error::BadRequest(
+                "the feather width must be a positive number of pixels".to_string(),
+            ));
+        }
+        Ok(Self { width, sources })
+    }
+
+    /// Reads the window of a mosaic covering a tile from its rasters, like `render::read_bands`
+    /// would from the mosaic, blending each raster over the ones below it.
+    pub fn read(
+        &self,
+        pool: &DatasetPool,
+        info: &RasterInfo,
+        tile_extent: &Extent,
+        (width, height): (usize, usize),
+        bands: &[isize],
+        window: &Window,
+    ) -> Result<Vec<f64>, Error> {
+        let (mosaic_x, mosaic_y) = window.output_position;
+        let (mosaic_width, mosaic_height) = window.output_size;
+        let count = bands.len();
+        let mut data = render::VALUES.take(mosaic_width * mosaic_height * count, 0.0);
+        for (band, mosaic_band) in source_bands(info, bands)?.iter().enumerate() {
+            let fill = mosaic_band.no_data.unwrap_or(0.0);
+            for pixel in 0..mosaic_width * mosaic_height {
+                data[pixel * count + band] = fill;
+            }
+        }
+        let mut covered = vec![false; mosaic_width * mosaic_height];
+
+        for source in &self.sources {
+            let dataset = pool.get(source)?;
+            let source_info = raster_info::get(source, &dataset)?;
+            let source_window = match render::window(&source_info, tile_extent, width, height) {
+                Err(Error::OutsideBounds) => continue,
+                source_window => source_window?,
+            };
+            let source_bands = source_bands(&source_info, bands)?;
+            let values = render::read_bands(&dataset, bands, &source_window)?;
+            let (raster_width, raster_height) = dataset.raster_size();
+            let Window {
+                input_position,
+                input_size,
+                output_position,
+                output_size,
+            } = source_window;
+            let x_scale = input_size.0 as f64 / output_size.0 as f64;
+            let y_scale = input_size.1 as f64 / output_size.1 as f64;
+
+            for y in 0..output_size.1 {
+                let row = output_position.1 + y as isize - mosaic_y;
+                if row < 0 || row >= mosaic_height as isize {
+                    continue;
+                }
+                // the distance from the centre of the pixel to the top or bottom edge
+                let source_row = input_position.1 as f64 + (y as f64 + 0.5) * y_scale;
+                let y_distance = source_row.min(raster_height as f64 - source_row);
+                for x in 0..output_size.0 {
+                    let col = output_position.0 + x as isize - mosaic_x;
+                    if col < 0 || col >= mosaic_width as isize {
+                        continue;
+                    }
+                    let start = (y * output_size.0 + x) * count;
+                    let pixel = &values[start..start + count];
+                    if pixel
+                        .iter()
+                        .zip(&source_bands)
+                        .any(|(&value, band)| band.is_no_data(value))
+                    {
+                        continue;
+                    }
+                    let source_col = input_position.0 as f64 + (x as f64 + 0.5) * x_scale;
+                    let x_distance = source_col.min(raster_width as f64 - source_col);
+                    let target = row as usize * mosaic_width + col as usize;
+                    // the lowest raster with data is opaque
+                    let weight = if covered[target] {
+                        (x_distance.min(y_distance) / self.width).clamp(0.0, 1.0)
+                    } else {
+                        1.0
+                    };
+                    covered[target] = true;
+                    for (band, &value) in pixel.iter().enumerate() {
+                        let out = &mut data[target * count + band];
+                        *out += weight * (value - *out);
+                    }
+                }
+            }
+            render::VALUES.put(values);
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,185 @@
+//! Blurring and unsharp-mask sharpening of rendered tiles.
+//!
+//! The tiles are rendered with a border wide enough for the filters, which is cropped away after
+//! filtering them, so that the filters see the same pixels on both sides of the tile edges and
+//! don't leave seams.
+
+use gdal::raster::Buffer;
+use gdal::Dataset;
+
+use crate::canvas::Canvas;
+use crate::error::Error;
+use crate::style::StyleQuery;
+
+const MAX_SHARPEN: f64 = 5.0;
+/// In pixels.
+const MAX_BLUR: f64 = 10.0;
+/// The standard deviation of the blur subtracted when sharpening, in pixels.
+const SHARPEN_SIGMA: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Filter {
+    /// How much of the detail removed by a blur is added back, with 0 disabling it.
+    sharpen: f64,
+    /// The standard deviation of the Gaussian blur, in pixels, with 0 disabling it.
+    blur: f64,
+}
+
+fn parse_parameter(name: &str, value: Option<&str>, max: f64) -> Result<f64, Error> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(0.0),
+    };
+    value
+        .parse()
+        .ok()
+        .filter(|value| (0.0..=max).contains(value))
+        .ok_or_else(|| {
+            Error::BadRequest(format!("{} must be a number between 0 and {}", name, max))
+        })
+}
+
+/// The radius of a Gaussian kernel, past which its weights are negligible.
+fn radius(sigma: f64) -> usize {
+    (3.0 * sigma).ceil() as usize
+}
+
+/// Blurs a plane with a separable Gaussian kernel, repeating the pixels at its edges.
+fn gaussian(plane: &[f32], (width, height): (usize, usize), sigma: f64) -> Vec<f32> {
+    let radius = radius(sigma) as isize;
+    let kernel = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f64>();
+    let kernel = kernel
+        .iter()
+        .map(|weight| (weight / total) as f32)
+        .collect::<Vec<_>>();
+    let clamp = |i: isize, len: usize| i.clamp(0, len as isize - 1) as usize;
+
+    let mut rows = vec![0.0; plane.len()];
+    for y in 0..height {
+        let row = &plane[y * width..(y + 1) * width];
+        for x in 0..width {
+            rows[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| weight * row[clamp(x as isize + k as isize - radius, width)])
+                .sum();
+        }
+    }
+    let mut out = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            out[y * width + x] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, weight)| {
+                    weight * rows[clamp(y as isize + k as isize - radius, height) * width + x]
+                })
+                .sum();
+        }
+    }
+    out
+}
+
+/// Blurs the colours of an image, leaving out the transparent pixels, which would otherwise
+/// darken the edges of the data.
+fn blur_colours(
+    colours: &[Vec<f32>],
+    alpha: &[f32],
+    size: (usize, usize),
+    sigma: f64,
+) -> Vec<Vec<f32>> {
+    let weights = gaussian(alpha, size, sigma);
+    colours
+        .iter()
+        .map(|colour| {
+            let premultiplied = colour
+                .iter()
+                .zip(alpha)
+                .map(|(colour, alpha)| colour * alpha)
+                .collect::<Vec<_>>();
+            gaussian(&premultiplied, size, sigma)
+                .iter()
+                .zip(&weights)
+                .zip(colour)
+                .map(
+                    |((sum, weight), colour)| {
+                        if *weight > 0.0 {
+                            sum / weight
+                        } else {
+                            *colour
+                        }
+                    },
+                )
+                .collect()
+        })
+        .collect()
+}
+
+impl Filter {
+    /// Reads the `sharpen` and `blur` parameters, if any of them is set.
+    pub fn parse(query: &StyleQuery) -> Result<Option<Self>, Error> {
+        let filter = Filter {
+            sharpen: parse_parameter("sharpen", query.sharpen.as_deref(), MAX_SHARPEN)?,
+            blur: parse_parameter("blur", query.blur.as_deref(), MAX_BLUR)?,
+        };
+        Ok(Some(filter).filter(|filter| filter.sharpen > 0.0 || filter.blur > 0.0))
+    }
+
+    /// Returns the width of the border a tile needs to be rendered with, in pixels.
+    pub fn border(&self) -> usize {
+        let mut border = 0;
+        if self.blur > 0.0 {
+            border += radius(self.blur);
+        }
+        if self.sharpen > 0.0 {
+            border += radius(SHARPEN_SIGMA);
+        }
+        border
+    }
+
+    /// Filters an image rendered with a border, returning it without the border.
+    pub fn apply(&self, image: &Dataset, border: usize) -> Result<Canvas, Error> {
+        let size = image.raster_size();
+        let mut planes = (1..=4)
+            .map(|band| {
+                let data = image
+                    .rasterband(band)?
+                    .read_as::<u8>((0, 0), size, size, None)?
+                    .data;
+                Ok(data.into_iter().map(|value| value as f32 / 255.0).collect())
+            })
+            .collect::<Result<Vec<Vec<f32>>, Error>>()?;
+        let alpha = planes.pop().expect("RGBA image");
+
+        let mut colours = planes;
+        if self.blur > 0.0 {
+            colours = blur_colours(&colours, &alpha, size, self.blur);
+        }
+        if self.sharpen > 0.0 {
+            let blurred = blur_colours(&colours, &alpha, size, SHARPEN_SIGMA);
+            for (colour, blurred) in colours.iter_mut().zip(&blurred) {
+                for (value, blurred) in colour.iter_mut().zip(blurred) {
+                    *value += self.sharpen as f32 * (*value - blurred);
+                }
+            }
+        }
+
+        let (width, height) = (size.0 - 2 * border, size.1 - 2 * border);
+        let out = Canvas::new(width, height)?;
+        for (band, plane) in colours.iter().chain(Some(&alpha)).enumerate() {
+            let data = (border..border + height)
+                .flat_map(|y| &plane[y * size.0 + border..y * size.0 + border + width])
+                .map(|value| (value * 255.0).round().clamp(0.0, 255.0) as u8)
+                .collect();
+            out.rasterband(band as isize + 1)?.write(
+                (0, 0),
+                (width, height),
+                &Buffer::new((width, height), data),
+            )?;
+        }
+        Ok(out)
+    }
+}
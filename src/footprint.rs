@@ -0,0 +1,112 @@
+use std::io;
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use gdal::raster::{Buffer, RasterBand};
+use gdal::vector::{FieldDefn, OGRFieldType, OGRwkbGeometryType};
+use gdal::{Driver, LayerOptions};
+use gdal_sys::CPLErr;
+use serde_json::{json, Value};
+
+use crate::compressed;
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::registry::Registry;
+use crate::workers;
+
+/// Largest dimension of the mask used to trace the footprint.
+const MASK_SIZE: usize = 512;
+
+fn compute_footprint(path: &Path) -> Result<Value, Error> {
+    let dataset = dataset::open(path)?;
+    let spatial_ref = dataset::spatial_ref(&dataset)?;
+    let geo_transform = dataset::geo_transform(&dataset)?;
+    let raster_size = dataset.raster_size();
+    let ratio = (raster_size.0.max(raster_size.1) as f64 / MASK_SIZE as f64).max(1.0);
+    let mask_size = (
+        ((raster_size.0 as f64 / ratio).round() as usize).max(1),
+        ((raster_size.1 as f64 / ratio).round() as usize).max(1),
+    );
+
+    let mask = unsafe {
+        let band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), 1);
+        RasterBand::from_c_rasterband(&dataset, gdal_sys::GDALGetMaskBand(band))
+    };
+    let mask = mask.read_as::<u8>((0, 0), raster_size, mask_size, None)?;
+    let mask = Buffer::new(
+        mask_size,
+        mask.data.into_iter().map(|v| (v != 0) as u8).collect(),
+    );
+
+    let mut raster = Driver::get("MEM")?.create_with_band_type::<u8>(
+        "",
+        mask_size.0 as isize,
+        mask_size.1 as isize,
+        1,
+    )?;
+    let scale_x = raster_size.0 as f64 / mask_size.0 as f64;
+    let scale_y = raster_size.1 as f64 / mask_size.1 as f64;
+    let [x0, a, b, y0, d, e] = geo_transform;
+    raster.set_geo_transform(&[x0, a * scale_x, b * scale_y, y0, d * scale_x, e * scale_y])?;
+    raster.set_spatial_ref(&spatial_ref)?;
+    raster.rasterband(1)?.write((0, 0), mask_size, &mask)?;
+
+    let mut vector = Driver::get("Memory")?.create_vector_only("")?;
+    let mut layer = vector.create_layer(LayerOptions {
+        name: "footprint",
+        srs: Some(&spatial_ref),
+        ty: OGRwkbGeometryType::wkbPolygon,
+        ..Default::default()
+    })?;
+    FieldDefn::new("value", OGRFieldType::OFTInteger)?.add_to_layer(&layer)?;
+    let rv = unsafe {
+        let band = gdal_sys::GDALGetRasterBand(raster.c_dataset(), 1);
+        gdal_sys::GDALPolygonize(
+            band,
+            band,
+            layer.c_layer(),
+            0,
+            ptr::null_mut(),
+            None,
+            ptr::null_mut(),
+        )
+    };
+    if rv != CPLErr::CE_None {
+        return Err(Error::last_cpl_error(rv));
+    }
+
+    let transform = crs::transform(&spatial_ref, &crs::wgs84()?)?;
+    let mut polygons = Vec::new();
+    for feature in layer.features() {
+        let geometry = feature.geometry().transform(&transform)?;
+        let geometry: Value = serde_json::from_str(&geometry.json()?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        polygons.push(geometry["coordinates"].clone());
+    }
+
+    Ok(json!({
+        "type": "Feature",
+        "properties": {},
+        "geometry": {
+            "type": "MultiPolygon",
+            "coordinates": polygons,
+        },
+    }))
+}
+
+pub async fn bounds(
+    extract::Path(file): extract::Path<String>,
+    headers: HeaderMap,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Response, Error> {
+    let path = registry.resolve(&file)?;
+    let key = format!("bounds {}", registry.cache_name(&file));
+    let document =
+        workers::run(move || compressed::encode(key, &compute_footprint(&path)?)).await?;
+    Ok(document.respond(&headers))
+}
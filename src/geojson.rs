@@ -0,0 +1,93 @@
+use serde::Deserialize;
+
+use crate::error::Error;
+
+pub type Position = Vec<f64>;
+
+/// A polygon as a list of rings, the first one being the exterior.
+pub type Polygon = Vec<Vec<(f64, f64)>>;
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    LineString {
+        coordinates: Vec<Position>,
+    },
+    Polygon {
+        coordinates: Vec<Vec<Position>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Position>>>,
+    },
+    Feature {
+        geometry: Box<Geometry>,
+    },
+}
+
+impl Geometry {
+    /// Unwraps `Feature` objects down to their geometry.
+    pub fn into_geometry(self) -> Geometry {
+        match self {
+            Geometry::Feature { geometry } => geometry.into_geometry(),
+            geometry => geometry,
+        }
+    }
+
+    pub fn into_line_string(self) -> Result<Vec<(f64, f64)>, Error> {
+        match self.into_geometry() {
+            Geometry::LineString { coordinates } => positions(coordinates),
+            _ => Err(Error::BadRequest("expected a LineString".to_string())),
+        }
+    }
+
+    /// Returns the polygons making up the geometry, each as a list of rings.
+    pub fn into_polygons(self) -> Result<Vec<Polygon>, Error> {
+        let polygons = match self.into_geometry() {
+            Geometry::Polygon { coordinates } => vec![coordinates],
+            Geometry::MultiPolygon { coordinates } => coordinates,
+            _ => {
+                return Err(Error::BadRequest(
+                    "expected a Polygon or MultiPolygon".to_string(),
+                ))
+            }
+        };
+        polygons
+            .into_iter()
+            .map(|rings| rings.into_iter().map(positions).collect())
+            .collect()
+    }
+}
+
+pub fn multi_polygon_wkt(polygons: &[Polygon]) -> String {
+    let polygons = polygons
+        .iter()
+        .map(|rings| {
+            let rings = rings
+                .iter()
+                .map(|ring| {
+                    let points = ring
+                        .iter()
+                        .map(|(x, y)| format!("{} {}", x, y))
+                        .collect::<Vec<_>>();
+                    format!("({})", points.join(","))
+                })
+                .collect::<Vec<_>>();
+            format!("({})", rings.join(","))
+        })
+        .collect::<Vec<_>>();
+    format!("MULTIPOLYGON({})", polygons.join(","))
+}
+
+pub fn positions(coordinates: Vec<Position>) -> Result<Vec<(f64, f64)>, Error> {
+    coordinates
+        .into_iter()
+        .map(|p| match p[..] {
+            [x, y, ..] => Ok((x, y)),
+            _ => Err(Error::BadRequest("invalid position".to_string())),
+        })
+        .collect()
+}
+
+pub fn parse(s: &str) -> Result<Geometry, Error> {
+    serde_json::from_str(s).map_err(|e| Error::BadRequest(format!("invalid GeoJSON: {}", e)))
+}
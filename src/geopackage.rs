@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::archive::{self, RawTile};
+use crate::error::Error;
+use crate::tile_grid::{Extent, TileGrid};
+
+/// Relative tolerance when comparing the tile matrix set extent to the tile grid.
+const TOLERANCE: f64 = 1e-9;
+
+struct TileMatrix {
+    zoom_level: u32,
+    matrix_width: u64,
+    matrix_height: u64,
+}
+
+/// The tile pyramid of a GeoPackage tile table.
+struct TileMatrixSet {
+    table: String,
+    extent: Extent,
+    matrices: Vec<TileMatrix>,
+}
+
+impl TileMatrixSet {
+    fn read(connection: &Connection) -> Result<Option<Self>, Error> {
+        let set = connection
+            .query_row(
+                "SELECT s.table_name, s.min_x, s.min_y, s.max_x, s.max_y
+                 FROM gpkg_tile_matrix_set s
+                 JOIN gpkg_contents c ON c.table_name = s.table_name
+                 WHERE c.data_type = 'tiles'
+                 ORDER BY s.table_name
+                 LIMIT 1",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        Extent {
+                            xmin: row.get(1)?,
+                            ymin: row.get(2)?,
+                            xmax: row.get(3)?,
+                            ymax: row.get(4)?,
+                        },
+                    ))
+                },
+            )
+            .optional()?;
+        let (table, extent) = match set {
+            Some(set) => set,
+            None => return Ok(None),
+        };
+        let mut statement = connection.prepare(
+            "SELECT zoom_level, matrix_width, matrix_height
+             FROM gpkg_tile_matrix
+             WHERE table_name = ?1",
+        )?;
+        let matrices = statement
+            .query_map([&table], |row| {
+                Ok(TileMatrix {
+                    zoom_level: row.get(0)?,
+                    matrix_width: row.get(1)?,
+                    matrix_height: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Self {
+            table,
+            extent,
+            matrices,
+        }))
+    }
+
+    /// Finds the zoom level whose tiles are the same as the tile grid ones at `z`.
+    fn zoom_level(&self, tile_grid: &TileGrid, z: u8) -> Option<u32> {
+        let grid = tile_grid.extent();
+        let size = (grid.xmax - grid.xmin).max(grid.ymax - grid.ymin);
+        let same = |a: f64, b: f64| (a - b).abs() <= size * TOLERANCE;
+        if !(same(self.extent.xmin, grid.xmin)
+            && same(self.extent.ymin, grid.ymin)
+            && same(self.extent.xmax, grid.xmax)
+            && same(self.extent.ymax, grid.ymax))
+        {
+            return None;
+        }
+        let count = 1u64 << z;
+        self.matrices
+            .iter()
+            .find(|matrix| matrix.matrix_width == count && matrix.matrix_height == count)
+            .map(|matrix| matrix.zoom_level)
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Reads a stored tile, with `row` counted from the top. Returns `None` if the tile matrix set
+/// doesn't line up with the tile grid, in which case the tile needs to be rendered.
+pub fn read_tile(
+    path: &Path,
+    tile_grid: &TileGrid,
+    z: u8,
+    x: u32,
+    row: u32,
+) -> Result<Option<RawTile>, Error> {
+    let connection = archive::open_sqlite(path)?;
+    let set = match TileMatrixSet::read(&connection)? {
+        Some(set) => set,
+        None => return Ok(None),
+    };
+    let zoom_level = match set.zoom_level(tile_grid, z) {
+        Some(zoom_level) => zoom_level,
+        None => return Ok(None),
+    };
+    let query = format!(
+        "SELECT tile_data FROM {} WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+        quote_identifier(&set.table)
+    );
+    let data: Vec<u8> = connection
+        .query_row(&query, [zoom_level, x, row], |row| row.get(0))
+        .optional()?
+        .ok_or(Error::OutsideBounds)?;
+    Ok(Some(RawTile {
+        format: archive::sniff_format(&data).to_string(),
+        data: data.into(),
+        encoding: None,
+    }))
+}
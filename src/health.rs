@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::extract::Extension;
+use axum::response::IntoResponse;
+use axum::Json;
+use hyper::StatusCode;
+use serde::Serialize;
+use tokio::task;
+
+use crate::cache;
+use crate::config::Config;
+use crate::dataset;
+use crate::workers;
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    checks: Vec<Check>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    name: &'static str,
+    ok: bool,
+    error: Option<String>,
+}
+
+impl Check {
+    fn new<E: ToString>(name: &'static str, result: Result<(), E>) -> Self {
+        let error = result.err().map(|e| e.to_string());
+        Self {
+            name,
+            ok: error.is_none(),
+            error,
+        }
+    }
+}
+
+fn check_cache_writable() -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // unique, since the probes can run at once
+    let path = cache::dir().join(format!(
+        ".readyz.{}.{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, b"")?;
+    std::fs::remove_file(&path)
+}
+
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+pub async fn readyz(config: Extension<Config>) -> impl IntoResponse {
+    let mut checks = vec![Check::new("config", Ok::<_, String>(()))];
+    checks.push(Check::new(
+        "cache",
+        task::spawn_blocking(check_cache_writable)
+            .await
+            .unwrap_or_else(|e| Err(e.into())),
+    ));
+    if let Some(canary) = config.canary_dataset.clone() {
+        let result = workers::run(move || dataset::open(Path::new(&canary)).map(|_| ())).await;
+        checks.push(Check::new("canary_dataset", result));
+    }
+
+    let ready = checks.iter().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks }))
+}
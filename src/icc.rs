@@ -0,0 +1,237 @@
+//! Conversion of RGB rasters with an embedded ICC profile to sRGB, so that they keep their colours
+//! when shown by browsers, which assume tiles to be sRGB.
+//!
+//! Only matrix/TRC RGB profiles are supported, like Adobe RGB, Display P3 or ProPhoto, which cover
+//! most imagery. The conversion is relative colorimetric, clipping the colours outside sRGB.
+
+use std::ffi::CString;
+
+use gdal::{Dataset, Metadata};
+
+/// From XYZ relative to D50, like the profile connection space, to linear sRGB, with Bradford
+/// adaptation.
+const XYZ_D50_TO_SRGB: [[f64; 3]; 3] = [
+    [3.1338561, -1.6168667, -0.4906146],
+    [-0.9787684, 1.9161415, 0.0334540],
+    [0.0719453, -0.2289914, 1.4052427],
+];
+
+/// Steps of the table encoding linear values to sRGB.
+const ENCODE_STEPS: usize = 4096;
+
+/// A tone reproduction curve, from the device values to linear light.
+enum Curve {
+    Gamma(f64),
+    Table(Vec<f64>),
+    /// The parameters of a `para` curve, padded to `[g, a, b, c, d, e, f]`.
+    Parametric(u16, [f64; 7]),
+}
+
+impl Curve {
+    fn eval(&self, x: f64) -> f64 {
+        match self {
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Table(table) => {
+                let position = x * (table.len() - 1) as f64;
+                let i = (position.floor() as usize).min(table.len() - 2);
+                let t = position - i as f64;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            &Curve::Parametric(kind, [g, a, b, c, d, e, f]) => match kind {
+                0 => x.powf(g),
+                1 if x >= -b / a => (a * x + b).powf(g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(g) + c,
+                2 => c,
+                3 if x >= d => (a * x + b).powf(g),
+                3 => c * x,
+                _ if x >= d => (a * x + b).powf(g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn s15_fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    Some(be_u32(data, offset)? as i32 as f64 / 65536.0)
+}
+
+/// Returns the data of a tag, from its type signature on.
+fn tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = be_u32(profile, 128)? as usize;
+    (0..count).find_map(|i| {
+        let entry = 132 + i * 12;
+        if profile.get(entry..entry + 4)? != signature {
+            return None;
+        }
+        let offset = be_u32(profile, entry + 4)? as usize;
+        let size = be_u32(profile, entry + 8)? as usize;
+        profile.get(offset..offset.checked_add(size)?)
+    })
+}
+
+fn xyz(profile: &[u8], signature: &[u8; 4]) -> Option<[f64; 3]> {
+    let data = tag(profile, signature)?;
+    if &data[..4] != b"XYZ " {
+        return None;
+    }
+    Some([
+        s15_fixed16(data, 8)?,
+        s15_fixed16(data, 12)?,
+        s15_fixed16(data, 16)?,
+    ])
+}
+
+fn curve(profile: &[u8], signature: &[u8; 4]) -> Option<Curve> {
+    let data = tag(profile, signature)?;
+    match data.get(..4)? {
+        b"curv" => match be_u32(data, 8)? {
+            0 => Some(Curve::Gamma(1.0)),
+            1 => Some(Curve::Gamma(f64::from(be_u16(data, 12)?) / 256.0)),
+            count => (0..count as usize)
+                .map(|i| Some(f64::from(be_u16(data, 12 + i * 2)?) / 65535.0))
+                .collect::<Option<Vec<_>>>()
+                .map(Curve::Table),
+        },
+        b"para" => {
+            let kind = be_u16(data, 8)?;
+            let count = match kind {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut parameters = [1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+            for (i, parameter) in parameters.iter_mut().take(count).enumerate() {
+                *parameter = s15_fixed16(data, 12 + i * 4)?;
+            }
+            Some(Curve::Parametric(kind, parameters))
+        }
+        _ => None,
+    }
+}
+
+fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts 8-bit RGB values from the colour space of a profile to sRGB.
+#[derive(Clone)]
+pub struct ToSrgb {
+    /// The device values of each channel in linear light.
+    linear: [[f32; 256]; 3],
+    matrix: [[f32; 3]; 3],
+    encode: Vec<u8>,
+}
+
+impl ToSrgb {
+    /// Builds the conversion from an ICC profile, failing for unsupported profiles.
+    pub fn new(profile: &[u8]) -> Option<Self> {
+        if profile.get(16..20)? != b"RGB " {
+            return None;
+        }
+        let columns = [
+            xyz(profile, b"rXYZ")?,
+            xyz(profile, b"gXYZ")?,
+            xyz(profile, b"bXYZ")?,
+        ];
+        let curves = [
+            curve(profile, b"rTRC")?,
+            curve(profile, b"gTRC")?,
+            curve(profile, b"bTRC")?,
+        ];
+        let mut linear = [[0.0; 256]; 3];
+        for (channel, curve) in linear.iter_mut().zip(&curves) {
+            for (value, linear) in channel.iter_mut().enumerate() {
+                *linear = curve.eval(value as f64 / 255.0).clamp(0.0, 1.0) as f32;
+            }
+        }
+        let mut matrix = [[0.0; 3]; 3];
+        for (row, to_srgb) in matrix.iter_mut().zip(&XYZ_D50_TO_SRGB) {
+            for (i, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| to_srgb[k] * columns[i][k]).sum::<f64>() as f32;
+            }
+        }
+        let encode = (0..ENCODE_STEPS)
+            .map(|i| {
+                let linear = i as f64 / (ENCODE_STEPS - 1) as f64;
+                (srgb_encode(linear) * 255.0).round() as u8
+            })
+            .collect();
+        Some(Self {
+            linear,
+            matrix,
+            encode,
+        })
+    }
+
+    fn convert(&self, rgb: [u8; 3]) -> [u8; 3] {
+        let linear = [
+            self.linear[0][rgb[0] as usize],
+            self.linear[1][rgb[1] as usize],
+            self.linear[2][rgb[2] as usize],
+        ];
+        let mut out = [0; 3];
+        for (out, row) in out.iter_mut().zip(&self.matrix) {
+            let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+            let step = (value.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round() as usize;
+            *out = self.encode[step];
+        }
+        out
+    }
+
+    /// Checks whether the profile is sRGB or close enough that converting makes no difference.
+    fn is_noop(&self) -> bool {
+        (0..=255u8).all(|value| {
+            [[value, 0, 0], [0, value, 0], [0, 0, value], [value; 3]]
+                .iter()
+                .all(|&rgb| {
+                    let out = self.convert(rgb);
+                    (0..3).all(|i| (i32::from(out[i]) - i32::from(rgb[i])).abs() <= 1)
+                })
+        })
+    }
+
+    /// Converts the channels of a rendered image in place.
+    pub fn apply(&self, red: &mut [u8], green: &mut [u8], blue: &mut [u8]) {
+        for ((r, g), b) in red.iter_mut().zip(green.iter_mut()).zip(blue.iter_mut()) {
+            let [red, green, blue] = self.convert([*r, *g, *b]);
+            *r = red;
+            *g = green;
+            *b = blue;
+        }
+    }
+}
+
+/// Reads the ICC profile embedded in a dataset, returning the conversion to sRGB unless the
+/// profile is missing, unsupported, or already sRGB.
+pub fn read(dataset: &Dataset) -> Option<ToSrgb> {
+    let encoded = dataset.metadata_item("SOURCE_ICC_PROFILE", "COLOR_PROFILE")?;
+    let mut profile = CString::new(encoded).ok()?.into_bytes_with_nul();
+    let length = unsafe { gdal_sys::CPLBase64DecodeInPlace(profile.as_mut_ptr()) };
+    profile.truncate(length.max(0) as usize);
+    match ToSrgb::new(&profile) {
+        Some(to_srgb) if to_srgb.is_noop() => None,
+        Some(to_srgb) => Some(to_srgb),
+        None => {
+            tracing::debug!("ignoring an unsupported ICC profile");
+            None
+        }
+    }
+}
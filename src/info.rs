@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::Dataset;
+use serde::Serialize;
+
+use crate::cache;
+use crate::config::Config;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::raster_info::{self, RasterInfo};
+use crate::registry::Registry;
+use crate::remote;
+use crate::tilejson;
+use crate::ImageInfo;
+use crate::InfoQuery;
+
+/// How many standard deviations around the mean the suggested rescale range covers.
+const RESCALE_STDDEVS: f64 = 2.0;
+
+#[derive(Serialize)]
+struct Suggestions {
+    /// The zoom level where the whole dataset fits in a tile.
+    minzoom: u8,
+    /// The zoom level where the tiles match the resolution of the dataset.
+    maxzoom: u8,
+    /// A `rescale` parameter for each band, in physical values.
+    rescale: Vec<Option<String>>,
+}
+
+#[derive(Serialize)]
+struct DatasetReport {
+    #[serde(flatten)]
+    info: ImageInfo,
+    suggested: Suggestions,
+}
+
+#[derive(Args)]
+pub struct Options {
+    /// The dataset to describe
+    dataset: String,
+    /// The CRS to report the extent in, instead of the one of the dataset
+    #[arg(long)]
+    crs: Option<String>,
+}
+
+/// Suggests a `rescale` range from the approximate statistics of a band, computing them if the
+/// dataset doesn't have them.
+fn rescale(dataset: &Dataset, info: &RasterInfo, band: isize) -> Result<Option<String>, Error> {
+    let (mut min, mut max, mut mean, mut stddev) = (0.0, 0.0, 0.0, 0.0);
+    let rv = unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _);
+        gdal_sys::GDALGetRasterStatistics(c_band, 1, 1, &mut min, &mut max, &mut mean, &mut stddev)
+    };
+    if rv != gdal_sys::CPLErr::CE_None {
+        // e.g. when every pixel is nodata
+        return Ok(None);
+    }
+    let band = info.band(band)?;
+    let physical = |value: f64| value * band.scale + band.offset;
+    let low = physical((mean - RESCALE_STDDEVS * stddev).max(min));
+    let high = physical((mean + RESCALE_STDDEVS * stddev).min(max));
+    // a negative scale swaps them
+    Ok(Some(format!("{},{}", low.min(high), low.max(high))))
+}
+
+/// Prints the `/info` response of a dataset, with suggested zoom levels and rescale ranges.
+pub fn run(options: Options) -> Result<(), Error> {
+    let query = InfoQuery { crs: options.crs };
+
+    let config = Config::from_env();
+    remote::configure(&config.remote)?;
+    cache::set_dir(&config.cache_dir);
+    let registry = Registry::new(PathBuf::from("."), config.remote.clone())?;
+    let path = registry.resolve(&options.dataset)?;
+    let pool = DatasetPool::new(config.pool.clone());
+    let info = crate::read_info(&path, &query, &pool)?;
+    let dataset = pool.get(&path)?;
+    let raster = raster_info::get(&path, &dataset)?;
+    let (minzoom, maxzoom) = tilejson::zoom_range(&raster, &config);
+    let rescale = (1..=dataset.raster_count())
+        .map(|band| rescale(&dataset, &raster, band))
+        .collect::<Result<_, Error>>()?;
+    let report = DatasetReport {
+        info,
+        suggested: Suggestions {
+            minzoom,
+            maxzoom,
+            rescale,
+        },
+    };
+    let json =
+        serde_json::to_string_pretty(&report).map_err(|e| Error::Io(std::io::Error::other(e)))?;
+    println!("{}", json);
+    Ok(())
+}
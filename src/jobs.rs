@@ -0,0 +1,546 @@
+//! Seeding and purging jobs started through the admin API, run in the background a few at a time.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::ffi::{c_void, CString};
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::response::sse::Event;
+use futures_util::stream::{self, Stream};
+use gdal::{Dataset, DatasetOptions, GdalOpenFlags};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::admin;
+use crate::batch;
+use crate::config::Config;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::raster_info;
+use crate::registry::{self, Kind, Registry};
+use crate::seed::Pyramid;
+use crate::statistics;
+use crate::style::StyleQuery;
+use crate::tile_grid::Extent;
+
+/// Finished jobs kept to be inspected, with the oldest ones forgotten first.
+const MAX_FINISHED: usize = 100;
+/// The time between two progress events of a job, at least.
+const EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The resampling methods of `gdaladdo`.
+const RESAMPLING_METHODS: &[&str] = &[
+    "nearest",
+    "average",
+    "rms",
+    "bilinear",
+    "cubic",
+    "cubicspline",
+    "lanczos",
+    "gauss",
+    "mode",
+];
+
+fn default_maxzoom() -> u8 {
+    14
+}
+
+fn default_resampling() -> String {
+    "average".to_string()
+}
+
+/// What a job does.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum JobSpec {
+    /// Renders the tiles of a dataset into the cache, like the `seed` command.
+    Seed {
+        dataset: String,
+        #[serde(default)]
+        minzoom: u8,
+        #[serde(default = "default_maxzoom")]
+        maxzoom: u8,
+        /// `[xmin, ymin, xmax, ymax]` in the coordinates of the tile grid, the extent of the
+        /// dataset by default.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bbox: Option<[f64; 4]>,
+    },
+    /// Removes the cached images of a dataset, or of all datasets.
+    Purge { dataset: Option<String> },
+    /// Builds the overviews of a raster, like `gdaladdo`, then removes its cached images.
+    Overviews {
+        dataset: String,
+        /// The decimation factors, by default halving the size until the raster fits in a tile.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        levels: Option<Vec<u32>>,
+        #[serde(default = "default_resampling")]
+        resampling: String,
+        /// Whether to store them in the raster instead of an `.ovr` file next to it.
+        #[serde(default)]
+        internal: bool,
+    },
+    /// Computes the statistics and histograms of the bands of a raster, like
+    /// `gdalinfo -stats -hist`, saving them in an `.aux.xml` file next to it.
+    Statistics {
+        dataset: String,
+        /// Whether to compute them from an overview or a subset of the pixels.
+        #[serde(default)]
+        approximate: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn name(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// The state of a job, as returned by the admin API.
+#[derive(Clone, Serialize)]
+pub struct JobInfo {
+    id: u64,
+    #[serde(flatten)]
+    spec: JobSpec,
+    status: JobStatus,
+    /// The tiles seeded, the files purged or the percentage of the overviews or statistics
+    /// computed so far.
+    done: usize,
+    /// The tiles to seed, once known, or 100 for the overviews and statistics.
+    total: Option<usize>,
+    /// The tiles that couldn't be rendered.
+    failed: usize,
+    error: Option<String>,
+    /// In seconds since the Unix epoch.
+    created: f64,
+    started: Option<f64>,
+    finished: Option<f64>,
+}
+
+struct Job {
+    /// Watched by the progress feeds.
+    info: watch::Sender<JobInfo>,
+    cancelled: AtomicBool,
+}
+
+impl Job {
+    fn update(&self, f: impl FnOnce(&mut JobInfo)) {
+        self.info.send_modify(f);
+    }
+
+    fn finish(&self, status: JobStatus, error: Option<String>) {
+        self.update(|info| {
+            info.status = status;
+            info.error = error;
+            info.finished = Some(now());
+        });
+    }
+}
+
+#[derive(Default)]
+struct State {
+    jobs: BTreeMap<u64, Arc<Job>>,
+    queue: VecDeque<Arc<Job>>,
+    running: usize,
+    next_id: u64,
+}
+
+/// Runs the jobs in the order they were started, at most `Config::max_jobs` at once.
+pub struct Jobs {
+    state: Mutex<State>,
+    config: Config,
+    registry: Arc<Registry>,
+    pool: Arc<DatasetPool>,
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |t| t.as_secs_f64())
+}
+
+impl Jobs {
+    pub fn new(config: Config, registry: Arc<Registry>, pool: Arc<DatasetPool>) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            config,
+            registry,
+            pool,
+        }
+    }
+
+    /// Queues a job, after checking that its dataset exists.
+    pub fn start(self: &Arc<Self>, spec: JobSpec) -> Result<JobInfo, Error> {
+        match &spec {
+            JobSpec::Seed { dataset, .. } => drop(self.registry.get(dataset)?),
+            JobSpec::Purge {
+                dataset: Some(dataset),
+            } => registry::validate_name(dataset)?,
+            JobSpec::Purge { dataset: None } => {}
+            JobSpec::Overviews {
+                dataset,
+                levels,
+                resampling,
+                ..
+            } => {
+                self.check_local_raster(dataset)?;
+                if !RESAMPLING_METHODS.contains(&resampling.as_str()) {
+                    return Err(Error::BadRequest(format!(
+                        "unknown resampling method: {}",
+                        resampling
+                    )));
+                }
+                if levels
+                    .iter()
+                    .flatten()
+                    .any(|&level| !(2..=i32::MAX as u32).contains(&level))
+                {
+                    return Err(Error::BadRequest(
+                        "overview levels must be at least 2".to_string(),
+                    ));
+                }
+            }
+            JobSpec::Statistics { dataset, .. } => self.check_local_raster(dataset)?,
+        }
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let info = JobInfo {
+            id: state.next_id,
+            spec,
+            status: JobStatus::Queued,
+            done: 0,
+            total: None,
+            failed: 0,
+            error: None,
+            created: now(),
+            started: None,
+            finished: None,
+        };
+        let job = Arc::new(Job {
+            info: watch::channel(info.clone()).0,
+            cancelled: AtomicBool::new(false),
+        });
+        state.jobs.insert(info.id, job.clone());
+        state.queue.push_back(job);
+        let finished = state
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.info.borrow().status.is_finished())
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED))
+        {
+            state.jobs.remove(id);
+        }
+        self.run_queued(&mut state);
+        Ok(info)
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        let state = self.state.lock().unwrap();
+        let job = state.jobs.get(&id)?;
+        let info = job.info.borrow().clone();
+        Some(info)
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let state = self.state.lock().unwrap();
+        state
+            .jobs
+            .values()
+            .map(|job| job.info.borrow().clone())
+            .collect()
+    }
+
+    /// Returns a feed of the state of a job, sent when subscribing and then as it changes, until
+    /// it finishes.
+    pub fn events(&self, id: u64) -> Option<impl Stream<Item = Result<Event, Infallible>>> {
+        let receiver = self.state.lock().unwrap().jobs.get(&id)?.info.subscribe();
+        let events = stream::unfold(Some((receiver, true)), |state| async move {
+            let (mut receiver, first) = state?;
+            if !first {
+                tokio::time::sleep(EVENT_INTERVAL).await;
+                // the job was forgotten
+                receiver.changed().await.ok()?;
+            }
+            let info = receiver.borrow_and_update().clone();
+            let event = Event::default()
+                .event(info.status.name())
+                .json_data(&info)
+                .ok()?;
+            let next = (!info.status.is_finished()).then_some((receiver, false));
+            Some((Ok(event), next))
+        });
+        Some(events)
+    }
+
+    /// Cancels a job. The running ones stop after the tile they are rendering.
+    pub fn cancel(&self, id: u64) -> Option<JobInfo> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.get(&id)?.clone();
+        job.cancelled.store(true, Ordering::Relaxed);
+        if job.info.borrow().status == JobStatus::Queued {
+            state.queue.retain(|queued| !Arc::ptr_eq(queued, &job));
+            job.finish(JobStatus::Cancelled, None);
+        }
+        let info = job.info.borrow().clone();
+        Some(info)
+    }
+
+    /// Starts the queued jobs while there are free slots.
+    fn run_queued(self: &Arc<Self>, state: &mut State) {
+        while state.running < self.config.max_jobs {
+            let job = match state.queue.pop_front() {
+                Some(job) => job,
+                None => break,
+            };
+            // while the lock is held, so that it's not cancelled as a queued job
+            job.update(|info| {
+                info.status = JobStatus::Running;
+                info.started = Some(now());
+            });
+            let (jobs, started) = (self.clone(), job.clone());
+            let spawned = thread::Builder::new()
+                .name("job".to_string())
+                .spawn(move || jobs.run(started));
+            match spawned {
+                Ok(_) => state.running += 1,
+                Err(e) => job.finish(JobStatus::Failed, Some(e.to_string())),
+            }
+        }
+    }
+
+    fn run(self: Arc<Self>, job: Arc<Job>) {
+        let spec = job.info.borrow().spec.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match &spec {
+            JobSpec::Seed {
+                dataset,
+                minzoom,
+                maxzoom,
+                bbox,
+            } => self.seed(&job, dataset, (*minzoom, *maxzoom), *bbox),
+            JobSpec::Purge { dataset } => {
+                let removed = admin::purge_cache(dataset.as_deref())?;
+                job.update(|info| info.done = removed);
+                Ok(())
+            }
+            JobSpec::Overviews {
+                dataset,
+                levels,
+                resampling,
+                internal,
+            } => self.build_overviews(&job, dataset, levels.as_deref(), resampling, *internal),
+            JobSpec::Statistics {
+                dataset,
+                approximate,
+            } => self.compute_statistics(&job, dataset, *approximate),
+        }))
+        .unwrap_or_else(|_| Err(Error::Io(io::Error::other("the job panicked"))));
+        match result {
+            Ok(()) if job.cancelled.load(Ordering::Relaxed) => {
+                job.finish(JobStatus::Cancelled, None)
+            }
+            Ok(()) => job.finish(JobStatus::Completed, None),
+            Err(e) => {
+                tracing::warn!(?spec, "job failed: {}", e);
+                job.finish(JobStatus::Failed, Some(e.to_string()));
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        self.run_queued(&mut state);
+    }
+
+    /// Renders the tiles one at a time, so that seeding leaves the worker threads to the
+    /// requests.
+    fn seed(
+        &self,
+        job: &Job,
+        name: &str,
+        zooms: (u8, u8),
+        bbox: Option<[f64; 4]>,
+    ) -> Result<(), Error> {
+        let (config, pool) = (&self.config, &self.pool);
+        let entry = self.registry.get(name)?;
+        let bbox = bbox.map(|[xmin, ymin, xmax, ymax]| Extent {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        });
+        let pyramid = Pyramid::new(name, &entry, bbox, zooms, config, pool)?;
+        job.update(|info| info.total = Some(pyramid.total()));
+        let style = StyleQuery::default().with_defaults(&entry.style);
+        let mut last_error = None;
+        for (z, x, row) in pyramid.into_tiles() {
+            if job.cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            let y = batch::flip_y(z, row, config);
+            match crate::cached_tile(&entry, name, (z, x, y), &style, config, pool) {
+                Ok(_) | Err(Error::OutsideBounds) => job.update(|info| info.done += 1),
+                Err(e) => {
+                    last_error = Some(format!("cannot render {}/{}/{}: {}", z, x, y, e));
+                    job.update(|info| {
+                        info.done += 1;
+                        info.failed += 1;
+                    });
+                }
+            }
+        }
+        let failed = job.info.borrow().failed;
+        match last_error {
+            Some(e) => Err(Error::Io(io::Error::other(format!(
+                "{} tiles could not be rendered, the last one with: {}",
+                failed, e
+            )))),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that a dataset is a raster file the jobs can write next to.
+    fn check_local_raster(&self, name: &str) -> Result<(), Error> {
+        let entry = self.registry.get(name)?;
+        if entry.kind != Kind::Raster || !entry.path.is_file() {
+            return Err(Error::BadRequest(
+                "this job only works with local raster files".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn compute_statistics(&self, job: &Job, name: &str, approximate: bool) -> Result<(), Error> {
+        let entry = self.registry.get(name)?;
+        // not a pooled handle, which GDAL would only save the statistics of when closing it
+        let dataset = Dataset::open(&entry.path)?;
+        job.update(|info| info.total = Some(100));
+        let statistics = statistics::compute(&dataset, approximate, &|complete| {
+            let done = (complete * 100.0) as usize;
+            if job.info.borrow().done != done {
+                job.update(|info| info.done = done);
+            }
+            !job.cancelled.load(Ordering::Relaxed)
+        });
+        // saves them
+        drop(dataset);
+        // the default rescale ranges come from the statistics
+        self.pool.evict(&entry.path);
+        raster_info::forget(&entry.path);
+        admin::purge_cache(Some(name))?;
+        if statistics.is_empty() && !job.cancelled.load(Ordering::Relaxed) {
+            return Err(Error::BadRequest(format!("{} has no valid pixels", name)));
+        }
+        Ok(())
+    }
+
+    fn build_overviews(
+        &self,
+        job: &Job,
+        name: &str,
+        levels: Option<&[u32]>,
+        resampling: &str,
+        internal: bool,
+    ) -> Result<(), Error> {
+        let entry = self.registry.get(name)?;
+        // opened read-only, GDAL writes them to an `.ovr` file
+        let open_flags = if internal {
+            GdalOpenFlags::GDAL_OF_RASTER | GdalOpenFlags::GDAL_OF_UPDATE
+        } else {
+            GdalOpenFlags::GDAL_OF_RASTER | GdalOpenFlags::GDAL_OF_READONLY
+        };
+        let options = DatasetOptions {
+            open_flags,
+            ..Default::default()
+        };
+        let dataset = Dataset::open_ex(&entry.path, options)?;
+        let (width, height) = dataset.raster_size();
+        let mut levels = match levels {
+            Some(levels) => levels.iter().map(|&level| level as c_int).collect(),
+            None => overview_levels(width.max(height), self.config.tile_width),
+        };
+        if levels.is_empty() {
+            return Err(Error::BadRequest(format!(
+                "{} already fits in a tile",
+                name
+            )));
+        }
+        job.update(|info| info.total = Some(100));
+        let resampling = CString::new(resampling)?;
+        let rv = unsafe {
+            gdal_sys::GDALBuildOverviews(
+                dataset.c_dataset(),
+                resampling.as_ptr(),
+                levels.len() as c_int,
+                levels.as_mut_ptr(),
+                0,
+                ptr::null_mut(),
+                Some(overview_progress),
+                job as *const Job as *mut c_void,
+            )
+        };
+        let result = if rv == gdal_sys::CPLErr::CE_None || job.cancelled.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err(Error::last_cpl_error(rv))
+        };
+        // flushes them
+        drop(dataset);
+        self.pool.evict(&entry.path);
+        raster_info::forget(&entry.path);
+        admin::purge_cache(Some(name))?;
+        result
+    }
+}
+
+/// Returns the factors of the overviews halving the size of a raster until it fits in a tile.
+fn overview_levels(size: usize, tile_size: usize) -> Vec<c_int> {
+    let mut levels = Vec::new();
+    let mut factor = 2;
+    while size.div_ceil(factor / 2) > tile_size {
+        levels.push(factor as c_int);
+        factor *= 2;
+    }
+    levels
+}
+
+/// Reports the progress of `GDALBuildOverviews` to its job, stopping it when cancelled.
+unsafe extern "C" fn overview_progress(
+    complete: f64,
+    _message: *const c_char,
+    data: *mut c_void,
+) -> c_int {
+    let job = &*(data as *const Job);
+    let done = (complete * 100.0) as usize;
+    if job.info.borrow().done != done {
+        job.update(|info| info.done = done);
+    }
+    c_int::from(!job.cancelled.load(Ordering::Relaxed))
+}
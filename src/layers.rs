@@ -0,0 +1,77 @@
+//! Extra middleware inserted into the router at a few points.
+
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::{Request, Response};
+use axum::routing::Route;
+use axum::{BoxError, Router};
+use tower::{Layer, Service};
+
+/// Where a layer is inserted, from the outermost to the innermost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerPoint {
+    /// Around every route, before the API keys and the admin token are checked. Custom
+    /// authentication or logging goes here.
+    BeforeAuth,
+    /// Around the routes rendering images (tiles, batches, composites, previews and
+    /// thumbnails), before looking the tiles up in the cache.
+    BeforeRender,
+    /// Around the tile route, but only for the tiles missing from the cache, which need to be
+    /// rendered or read from an archive.
+    AfterCache,
+}
+
+type ApplyLayer = Arc<dyn Fn(Router) -> Router + Send + Sync>;
+
+/// The layers added at each point, in the order they were added, with the last one being the
+/// outermost.
+#[derive(Clone, Default)]
+pub struct Layers {
+    before_auth: Vec<ApplyLayer>,
+    before_render: Vec<ApplyLayer>,
+    after_cache: Vec<ApplyLayer>,
+}
+
+impl Layers {
+    pub fn push<L, ResBody>(&mut self, point: LayerPoint, layer: L)
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<ResBody>, Error = Infallible>
+            + Clone
+            + Send
+            + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        ResBody: HttpBody<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        let apply: ApplyLayer = match point {
+            // this one also runs for the requests not matching any route
+            LayerPoint::BeforeAuth => Arc::new(move |router: Router| router.layer(layer.clone())),
+            _ => Arc::new(move |router: Router| router.route_layer(layer.clone())),
+        };
+        let layers = match point {
+            LayerPoint::BeforeAuth => &mut self.before_auth,
+            LayerPoint::BeforeRender => &mut self.before_render,
+            LayerPoint::AfterCache => &mut self.after_cache,
+        };
+        layers.push(apply);
+    }
+
+    /// Adds the layers of another set after the ones of this one.
+    pub fn extend(&mut self, other: Layers) {
+        self.before_auth.extend(other.before_auth);
+        self.before_render.extend(other.before_render);
+        self.after_cache.extend(other.after_cache);
+    }
+
+    pub fn apply(&self, point: LayerPoint, router: Router) -> Router {
+        let layers = match point {
+            LayerPoint::BeforeAuth => &self.before_auth,
+            LayerPoint::BeforeRender => &self.before_render,
+            LayerPoint::AfterCache => &self.after_cache,
+        };
+        layers.iter().fold(router, |router, apply| apply(router))
+    }
+}
@@ -0,0 +1,832 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use axum::body::{self, Body, Full, HttpBody};
+use axum::extract::{Extension, RequestParts};
+use axum::http::{self, Request};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, Route};
+use axum::{extract, BoxError, Json, Router};
+use bytes::Bytes;
+use clap::Subcommand;
+use gdal::spatial_ref::SpatialRef;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service, ServiceBuilder};
+
+pub use self::access::redact_uri;
+use self::canvas::Canvas;
+pub use self::config::Config;
+use self::config::{TenantConfig, WorkerConfig};
+use self::dataset_pool::DatasetPool;
+pub use self::error::Error;
+use self::filter::Filter;
+use self::layers::{LayerPoint, Layers};
+use self::registry::{Entry, Kind, Registry};
+use self::renderer::{Renderers, TileRenderer};
+use self::speckle::Speckle;
+use self::style::{Style, StyleQuery};
+use self::tile_grid::Extent;
+use self::virtual_host::VirtualHosts;
+
+mod access;
+mod admin;
+mod archive;
+mod batch;
+mod bench;
+mod cache;
+mod canvas;
+mod cluster;
+mod composite;
+mod compressed;
+pub mod config;
+mod crs;
+mod dataset;
+mod dataset_pool;
+pub mod error;
+mod export;
+mod feather;
+mod filter;
+mod footprint;
+mod geojson;
+mod geopackage;
+mod health;
+mod icc;
+mod info;
+mod jobs;
+pub mod layers;
+mod mbtiles;
+mod memory_cache;
+mod metadata;
+mod metrics;
+mod openapi;
+mod pmtiles;
+mod point;
+mod prefetch;
+mod preview;
+mod profile;
+mod prune;
+mod quota;
+mod raster_info;
+mod rat;
+mod registry;
+mod remote;
+mod render;
+pub mod renderer;
+mod reproject;
+#[cfg(feature = "scripts")]
+mod script;
+mod seed;
+mod sentinel2;
+#[cfg(feature = "sentry")]
+mod sentry;
+mod slow;
+mod speckle;
+mod stac;
+mod statistics;
+pub mod style;
+mod thumbnail;
+pub mod tile_grid;
+mod tilejson;
+mod version;
+mod viewer;
+mod virtual_host;
+mod vrt;
+mod watcher;
+mod wcs;
+mod wms;
+mod workers;
+mod zarr;
+mod zonal;
+
+#[derive(Serialize)]
+struct ImageInfo {
+    extent: Extent,
+    extent_wgs84: Extent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extent_crs: Option<Extent>,
+    projection_info: ProjectionInfo,
+    bands: Vec<BandInfo>,
+}
+
+/// How the stored values of a band map to physical ones.
+#[derive(Serialize)]
+struct BandInfo {
+    band: isize,
+    scale: f64,
+    offset: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    /// The classes of the values, for thematic rasters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attribute_table: Option<rat::AttributeTable>,
+}
+
+#[derive(Deserialize)]
+struct InfoQuery {
+    crs: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ProjectionInfo {
+    wkt: String,
+    proj4: String,
+    usage: Option<Extent>,
+    name: Option<String>,
+    bounds: Option<Extent>,
+}
+
+/// Describes a CRS, with the bounds of its area of use in its own coordinates.
+///
+/// `spatial_ref` must use the traditional GIS axis order, like the ones from
+/// `dataset::spatial_ref`.
+fn get_projection_info(spatial_ref: &SpatialRef) -> Result<ProjectionInfo, Error> {
+    let area_of_use = spatial_ref.area_of_use();
+    let projection_usage = area_of_use.as_ref().map(|area_of_use| Extent {
+        xmin: area_of_use.west_lon_degree,
+        xmax: area_of_use.east_lon_degree,
+        ymin: area_of_use.south_lat_degree,
+        ymax: area_of_use.north_lat_degree,
+    });
+
+    let transform = crs::transform(&crs::wgs84()?, spatial_ref)?;
+    let name = spatial_ref.name()?;
+    // the area of use can reach beyond the domain of the projection
+    let projection_bounds = projection_usage
+        .as_ref()
+        .filter(|extent| extent.xmin <= extent.xmax)
+        .and_then(|extent| dataset::reproject_extent(extent, &transform).ok());
+    let projection_info = ProjectionInfo {
+        wkt: spatial_ref.to_pretty_wkt()?,
+        proj4: spatial_ref.to_proj4()?,
+        usage: projection_usage,
+        name: Some(name),
+        bounds: projection_bounds,
+    };
+    Ok(projection_info)
+}
+
+async fn info(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<InfoQuery>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+) -> Result<Json<ImageInfo>, Error> {
+    let path = registry.resolve(&file)?;
+    let info = workers::run(move || read_info(&path, &query, &pool)).await?;
+    Ok(Json(info))
+}
+
+fn read_info(path: &Path, query: &InfoQuery, pool: &DatasetPool) -> Result<ImageInfo, Error> {
+    let dataset = pool.get(path)?;
+    let raster = raster_info::get(path, &dataset)?;
+    let source_srs = dataset::spatial_ref(&dataset)?;
+    let extent_wgs84 = match &raster.extent_wgs84 {
+        Some(extent_wgs84) => extent_wgs84.clone(),
+        None => {
+            let transform = crs::transform(&source_srs, &crs::wgs84()?)?;
+            dataset::reproject_extent(&raster.extent, &transform)?
+        }
+    };
+    let extent_crs = match &query.crs {
+        Some(crs) => {
+            let transform = crs::transform(&source_srs, &crs::parse_srs(crs)?)?;
+            Some(dataset::reproject_extent(&raster.extent, &transform)?)
+        }
+        None => None,
+    };
+
+    let bands = raster
+        .bands
+        .iter()
+        .zip(1..)
+        .map(|(band, i)| BandInfo {
+            band: i,
+            scale: band.scale,
+            offset: band.offset,
+            unit: band.unit.clone(),
+            attribute_table: rat::read(&dataset, i),
+        })
+        .collect();
+
+    let info = ImageInfo {
+        extent: raster.extent.clone(),
+        extent_wgs84,
+        extent_crs,
+        projection_info: get_projection_info(&source_srs)?,
+        bands,
+    };
+    Ok(info)
+}
+
+/// An encoded PNG, shared with the caches instead of copied into the response.
+pub struct Png(pub Bytes);
+
+impl IntoResponse for Png {
+    fn into_response(self) -> Response {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "image/png")
+            .header("Content-Length", self.0.len())
+            .body(body::boxed(Full::from(self.0)))
+            .unwrap()
+    }
+}
+
+/// Returns the path of a tile in the cache, with `y` as in `cached_tile`.
+fn tile_cache_path(
+    entry: &Entry,
+    file: &str,
+    (z, x, y): (u8, u32, u32),
+    style: &StyleQuery,
+) -> Result<String, Error> {
+    Ok(format!(
+        "{}/{}{}_{}_{}{}{}.png",
+        cache::dir().display(),
+        cache::entry_prefix(file),
+        z,
+        x,
+        y,
+        Style::cache_key(style)?,
+        entry.script_cache_key()
+    ))
+}
+
+/// Renders a tile unless it's already cached, returning the PNG and whether it was rendered.
+///
+/// `y` is the row in the tile grid, flipped according to `reverse_y`.
+fn cached_tile(
+    entry: &Entry,
+    file: &str,
+    (z, x, y): (u8, u32, u32),
+    style: &StyleQuery,
+    config: &Config,
+    pool: &DatasetPool,
+) -> Result<(Bytes, bool), Error> {
+    let file_name = tile_cache_path(entry, file, (z, x, y), style)?;
+    if let Some(png) = memory_cache::get(&file_name) {
+        return Ok((png, false));
+    }
+    if let Some(png) = cache::read(Path::new(&file_name), config.cache_verify_crc)? {
+        let png = Bytes::from(png);
+        memory_cache::insert(&file_name, png.clone());
+        return Ok((png, false));
+    }
+    let _lock = match config.cache_lock_wait {
+        Some(wait) => match cache::claim(Path::new(&file_name), config.cache_verify_crc, wait)? {
+            cache::Claim::Cached(png) => {
+                let png = Bytes::from(png);
+                memory_cache::insert(&file_name, png.clone());
+                return Ok((png, false));
+            }
+            cache::Claim::Render(lock) => lock,
+        },
+        None => None,
+    };
+
+    let span = tracing::info_span!("render_tile", dataset = file, z, x, y);
+    let _enter = span.enter();
+    let y = if config.reverse_y {
+        (1 << z) - 1 - y
+    } else {
+        y
+    };
+    slow::record_tile(file, (z, x, y));
+    let _rendering = render::Rendering::start();
+    let png = render_tile(entry, (z, x, y), style, config, pool)?;
+    render::stage("cache-write", || cache::write(Path::new(&file_name), &png))?;
+    let png = Bytes::from(png);
+    memory_cache::insert(&file_name, png.clone());
+    Ok((png, true))
+}
+
+/// Renders a tile as PNG, with `y` counted from the bottom of the tile grid.
+fn render_tile(
+    entry: &Entry,
+    (z, x, y): (u8, u32, u32),
+    style: &StyleQuery,
+    config: &Config,
+    pool: &DatasetPool,
+) -> Result<Vec<u8>, Error> {
+    let filter = Filter::parse(style)?;
+    let speckle = style.speckle.as_deref().map(Speckle::parse).transpose()?;
+    // the speckle filter runs first, so the other filters need the pixels it got right
+    let border =
+        filter.map_or(0, |filter| filter.border()) + speckle.map_or(0, |speckle| speckle.radius());
+    let mut tile_extent = config.tile_grid.tile_extent(x, y, z);
+    // the filters read the pixels around the tile too
+    let (width, height) = (
+        config.tile_width + 2 * border,
+        config.tile_height + 2 * border,
+    );
+    let dx = (tile_extent.xmax - tile_extent.xmin) / config.tile_width as f64 * border as f64;
+    let dy = (tile_extent.ymax - tile_extent.ymin) / config.tile_height as f64 * border as f64;
+    tile_extent.xmin -= dx;
+    tile_extent.xmax += dx;
+    tile_extent.ymin -= dy;
+    tile_extent.ymax += dy;
+    let out = match &entry.mosaic {
+        Some(mosaic) => render::stage("render", || {
+            mosaic.render(&tile_extent, width, height, style)
+        })?,
+        None => {
+            let path = entry.styled_path(style)?;
+            let (dataset, info) = render::stage("open", || -> Result<_, Error> {
+                let dataset = pool.get(path)?;
+                let info = raster_info::get(path, &dataset)?;
+                Ok((dataset, info))
+            })?;
+            let style = Style::parse(style, dataset.raster_count())?;
+            #[cfg(feature = "scripts")]
+            let style = Style {
+                script: entry.script.clone(),
+                ..style
+            };
+            let renderer = renderer::for_entry(config, entry)?;
+            let warp = reproject::grid_warp(&info, &config.tile_grid, config.resampling)?;
+            // the pan-sharpened VRT isn't a mosaic
+            let feather = entry.feather.as_ref().filter(|_| path == entry.path);
+            if let Some(warp) = warp {
+                drop(dataset);
+                reproject::render(
+                    pool,
+                    path,
+                    &info,
+                    &warp,
+                    &tile_extent,
+                    width,
+                    height,
+                    &style,
+                    renderer,
+                )?
+            } else if let Some(feather) = feather {
+                drop(dataset);
+                render::render_with(
+                    &info,
+                    &tile_extent,
+                    width,
+                    height,
+                    &style,
+                    renderer,
+                    |bands, window| {
+                        feather.read(pool, &info, &tile_extent, (width, height), bands, window)
+                    },
+                )?
+            } else if info.band_interleaved && style.bands.len() > 1 {
+                // let one of the reads reuse the handle
+                drop(dataset);
+                render::render_with(
+                    &info,
+                    &tile_extent,
+                    width,
+                    height,
+                    &style,
+                    renderer,
+                    |bands, window| render::read_bands_parallel(path, pool, bands, window),
+                )?
+            } else {
+                render::render(
+                    &dataset,
+                    &info,
+                    &tile_extent,
+                    width,
+                    height,
+                    &style,
+                    renderer,
+                )?
+            }
+        }
+    };
+    let out = match filter {
+        Some(filter) => render::stage("filter", || filter.apply(&out, border))?,
+        None if border > 0 => Canvas::crop(&out, border)?,
+        None => out,
+    };
+    render::stage("encode", || render::encode_png(&out))
+}
+
+async fn tile(
+    extract::Path((file, z, x, y)): extract::Path<(String, u8, u32, u32)>,
+    extract::Query(style): extract::Query<StyleQuery>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+    pool: Extension<Arc<DatasetPool>>,
+) -> Result<Response, Error> {
+    tile_grid::check_tile(z, x, y)?;
+    let entry = registry.get(&file)?;
+    let style = style.with_defaults(&entry.style);
+    // the name the tile is cached under
+    let file = registry.cache_name(&file);
+    if entry.kind == Kind::GeoPackage && style.is_default() {
+        let row = if config.reverse_y {
+            y
+        } else {
+            (1 << z) - 1 - y
+        };
+        let path = entry.path.clone();
+        let tile_grid = config.tile_grid.clone();
+        let tile =
+            workers::run(move || geopackage::read_tile(&path, &tile_grid, z, x, row)).await?;
+        if let Some(tile) = tile {
+            return Ok(tile.into_response());
+        }
+    }
+    let response = match entry.kind {
+        Kind::Raster | Kind::GeoPackage | Kind::Stac => {
+            let (png, rendered) = {
+                let (entry, file, style, config, pool) = (
+                    entry.clone(),
+                    file.clone(),
+                    style.clone(),
+                    config.0.clone(),
+                    pool.0.clone(),
+                );
+                workers::run(move || cached_tile(&entry, &file, (z, x, y), &style, &config, &pool))
+                    .await?
+            };
+            if rendered && config.prefetch_budget > 0 {
+                prefetch::prefetch(entry, file, (z, x, y), style, config.0, pool.0);
+            }
+            Png(png).into_response()
+        }
+        Kind::MbTiles => {
+            let row = if config.reverse_y {
+                (1 << z) - 1 - y
+            } else {
+                y
+            };
+            workers::run(move || mbtiles::read_tile(&entry.path, z, x, row))
+                .await?
+                .into_response()
+        }
+        Kind::PmTiles => {
+            let row = if config.reverse_y {
+                y
+            } else {
+                (1 << z) - 1 - y
+            };
+            workers::run(move || entry.pmtiles()?.read_tile(z, x, row))
+                .await?
+                .into_response()
+        }
+        Kind::Wms => {
+            let wms = entry.wms.clone().ok_or(Error::OutsideBounds)?;
+            workers::run(move || wms.tile(&file, (z, x, y), &config))
+                .await?
+                .into_response()
+        }
+    };
+    Ok(response)
+}
+
+/// Serves the tiles found in the cache, so that the `AfterCache` layers only see the ones
+/// being rendered.
+async fn serve_cached(request: Request<Body>, next: Next<Body>) -> Response {
+    let mut parts = RequestParts::new(request);
+    if let Some(path) = tile_cache_key(&mut parts).await {
+        if let Some(png) = memory_cache::get(&path) {
+            return Png(png).into_response();
+        }
+        let verify_crc = parts
+            .extensions()
+            .get::<Config>()
+            .is_some_and(|config| config.cache_verify_crc);
+        // missing and corrupt entries are rendered below, without blocking on the file system
+        if let Ok(Some(png)) = cache::read_async(Path::new(&path), verify_crc).await {
+            let png = Bytes::from(png);
+            memory_cache::insert(&path, png.clone());
+            return Png(png).into_response();
+        }
+    }
+    match parts.try_into_request() {
+        Ok(request) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Returns the cache path of a tile request, if the tile would be cached and its style is valid.
+async fn tile_cache_key(parts: &mut RequestParts<Body>) -> Option<String> {
+    let extract::Path((file, z, x, y)) = parts
+        .extract::<extract::Path<(String, u8, u32, u32)>>()
+        .await
+        .ok()?;
+    let extract::Query(style) = parts.extract::<extract::Query<StyleQuery>>().await.ok()?;
+    let Extension(registry) = parts.extract::<Extension<Arc<Registry>>>().await.ok()?;
+    let entry = registry.get(&file).ok()?;
+    let style = style.with_defaults(&entry.style);
+    entry.styled_path(&style).ok()?;
+    let cached = match entry.kind {
+        Kind::Raster | Kind::Stac => true,
+        // the tiles stored in the GeoPackage are read from it
+        Kind::GeoPackage => !style.is_default(),
+        Kind::MbTiles | Kind::PmTiles | Kind::Wms => false,
+    };
+    let path = tile_cache_path(&entry, &registry.cache_name(&file), (z, x, y), &style).ok()?;
+    Some(path).filter(|_| cached)
+}
+
+/// Builds the router of the tile server, to serve it or mount it in another application.
+pub struct TileServer;
+
+impl TileServer {
+    pub fn builder() -> TileServerBuilder {
+        TileServerBuilder::default()
+    }
+}
+
+#[derive(Default)]
+pub struct TileServerBuilder {
+    config: Option<Config>,
+    root: Option<PathBuf>,
+    renderers: Renderers,
+    layers: Layers,
+}
+
+impl TileServerBuilder {
+    /// Sets the configuration, read from the environment by default.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Sets the directory the datasets are found in, the current one by default.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Registers a custom renderer, which the datasets select with their `renderer` setting.
+    pub fn renderer(
+        mut self,
+        name: impl Into<String>,
+        renderer: impl TileRenderer + 'static,
+    ) -> Self {
+        self.renderers.insert(name.into(), Arc::new(renderer));
+        self
+    }
+
+    /// Inserts a tower layer into the router, at one of the points described in `LayerPoint`.
+    pub fn layer<L, ResBody>(mut self, point: LayerPoint, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = http::Response<ResBody>, Error = Infallible>
+            + Clone
+            + Send
+            + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        ResBody: HttpBody<Data = Bytes> + Send + 'static,
+        ResBody::Error: Into<BoxError>,
+    {
+        self.layers.push(point, layer);
+        self
+    }
+
+    /// Loads the datasets and returns the router serving them.
+    ///
+    /// The tiles are cached in the `cache` directory, relative to the current one. When the
+    /// configuration has a `watch_interval`, this must be called from a Tokio runtime.
+    pub fn build(self) -> Result<Router, Error> {
+        error::log_gdal_errors();
+        let mut config = self.config.unwrap_or_else(Config::from_env);
+        config.tile_grid.resolve_crs()?;
+        apply_process_settings(&config)?;
+        std::fs::create_dir_all(cache::dir().join("thumbnails"))?;
+        config.renderers.extend(self.renderers);
+        config.layers.extend(self.layers);
+        let root = self.root.unwrap_or_else(|| PathBuf::from("."));
+        let registry = Arc::new(Registry::new(root, config.remote.clone())?);
+        let tenants = match &config.tenants {
+            Some(path) => read_tenants(path)?,
+            None => BTreeMap::new(),
+        };
+        let pool = Arc::new(DatasetPool::new(config.pool.clone()));
+        let access = match &config.api_keys {
+            Some(path) => Some(Arc::new(access::Access::open(path, config.audit_dir())?)),
+            None => None,
+        };
+        let cluster = match &config.cluster {
+            Some(cluster) => Some(Arc::new(cluster::Cluster::new(cluster.clone())?)),
+            None => None,
+        };
+        let jobs = Arc::new(jobs::Jobs::new(
+            config.clone(),
+            registry.clone(),
+            pool.clone(),
+        ));
+        if let Some(interval) = config.watch_interval {
+            tokio::spawn(watcher::watch(registry.clone(), pool.clone(), interval));
+        }
+
+        let layers = config.layers.clone();
+        let mut router = Router::new().merge(data_router(&layers));
+        let mut hosts = BTreeMap::new();
+        for (name, tenant) in tenants {
+            for host in &tenant.hosts {
+                hosts.insert(host.to_ascii_lowercase(), name.clone());
+            }
+            let registry = Arc::new(Registry::with_tenant(
+                tenant.root,
+                config.remote.clone(),
+                Some(name.clone()),
+            )?);
+            if let Some(interval) = config.watch_interval {
+                tokio::spawn(watcher::watch(registry.clone(), pool.clone(), interval));
+            }
+            let tenant_router = data_router(&layers).layer(Extension(registry.clone()));
+            let tenant_router = match &tenant.api_keys {
+                Some(path) => tenant_router.layer(Extension(Arc::new(access::Access::open(
+                    path,
+                    config.audit_dir().join(&name),
+                )?))),
+                None => tenant_router,
+            };
+            router = router.nest(&registry.url_prefix(), tenant_router);
+        }
+
+        let router = router
+            .route("/healthz", get(health::healthz))
+            .route("/readyz", get(health::readyz))
+            .route("/version", get(version::version))
+            .route("/metrics", get(metrics::metrics))
+            .route("/openapi.json", get(openapi::openapi))
+            .nest("/admin", admin::router());
+        let router = layers
+            .apply(LayerPoint::BeforeAuth, router)
+            .layer(middleware::from_fn(slow::log_slow_requests));
+        #[cfg(feature = "sentry")]
+        let router = router
+            .layer(middleware::from_fn(sentry::report_errors))
+            .layer(sentry_tower::SentryHttpLayer::new())
+            .layer(sentry_tower::NewSentryLayer::new_from_top());
+        let router = match access {
+            Some(access) => router.layer(Extension(access)),
+            None => router,
+        };
+        let router = match cluster {
+            Some(cluster) => router.layer(Extension(cluster)),
+            None => router,
+        };
+        let router = router
+            .layer(Extension(config))
+            .layer(Extension(jobs))
+            .layer(Extension(registry))
+            .layer(Extension(pool));
+        if hosts.is_empty() {
+            return Ok(router);
+        }
+        // the paths are rewritten before the router picks the route
+        let router = ServiceBuilder::new()
+            .layer(Extension(Arc::new(VirtualHosts(hosts))))
+            .layer(middleware::from_fn(virtual_host::route))
+            .service(router);
+        Ok(Router::new().fallback(router))
+    }
+}
+
+/// The settings that apply to the whole process, rather than to the router being built.
+#[derive(PartialEq)]
+struct ProcessSettings {
+    cache_dir: PathBuf,
+    remote: (Option<u64>, u32, f64),
+    workers: WorkerConfig,
+    png_srgb: bool,
+    memory_cache_size: usize,
+    #[cfg(feature = "sentry")]
+    sentry_dsn: Option<String>,
+}
+
+/// The settings of the first router built.
+static PROCESS_SETTINGS: Mutex<Option<ProcessSettings>> = Mutex::new(None);
+
+/// Applies the process-wide settings of the first router built, failing for the next ones if
+/// their settings differ, since they would change those of the other routers.
+fn apply_process_settings(config: &Config) -> Result<(), Error> {
+    let settings = ProcessSettings {
+        cache_dir: config.cache_dir.clone(),
+        remote: (
+            config.remote.cache_size,
+            config.remote.max_retry,
+            config.remote.retry_delay,
+        ),
+        workers: config.workers.clone(),
+        png_srgb: config.png_srgb,
+        memory_cache_size: config.memory_cache_size,
+        #[cfg(feature = "sentry")]
+        sentry_dsn: config.sentry_dsn.clone(),
+    };
+    let mut applied = PROCESS_SETTINGS.lock().unwrap();
+    if let Some(applied) = &*applied {
+        if *applied != settings {
+            return Err(Error::BadRequest(
+                "a router was already built with other cache directory, remote, worker, PNG, \
+                 memory cache or Sentry settings, which apply to the whole process"
+                    .to_string(),
+            ));
+        }
+        return Ok(());
+    }
+    cache::set_dir(&config.cache_dir);
+    remote::configure(&config.remote)?;
+    workers::configure(&config.workers);
+    render::set_png_srgb(config.png_srgb);
+    memory_cache::set_max_bytes(config.memory_cache_size);
+    #[cfg(feature = "sentry")]
+    if let Some(dsn) = &config.sentry_dsn {
+        sentry::init(dsn)?;
+    }
+    *applied = Some(settings);
+    Ok(())
+}
+
+/// Reads the tenants from a JSON file, keyed by the names used in their URLs.
+fn read_tenants(path: &Path) -> Result<BTreeMap<String, TenantConfig>, Error> {
+    let tenants: BTreeMap<String, TenantConfig> =
+        serde_json::from_slice(&std::fs::read(path)?).map_err(std::io::Error::from)?;
+    for name in tenants.keys() {
+        registry::validate_name(name)?;
+    }
+    Ok(tenants)
+}
+
+/// Builds the routes reading the datasets, which are served for each tenant too.
+fn data_router(layers: &Layers) -> Router {
+    let tiles = Router::new().route("/tile/:file/:z/:x/:y", get(tile));
+    let tiles = layers
+        .apply(LayerPoint::AfterCache, tiles)
+        .route_layer(middleware::from_fn(cluster::forward))
+        .route_layer(middleware::from_fn(serve_cached));
+    let rendering = Router::new()
+        .merge(tiles)
+        .route("/batch/:file", post(batch::batch))
+        .route("/composite/:layers/:z/:x/:y", get(composite::composite))
+        .route("/preview/:file", get(preview::preview))
+        .route("/thumbnail/:file", get(thumbnail::thumbnail))
+        .route("/wcs", get(wcs::wcs));
+    let rendering = layers.apply(LayerPoint::BeforeRender, rendering);
+
+    Router::new()
+        .merge(rendering)
+        .route("/info/:file", get(info))
+        .route("/tilejson/:file", get(tilejson::tilejson))
+        .route("/catalog", get(tilejson::catalog))
+        .route("/bounds/:file", get(footprint::bounds))
+        .route("/viewer/:file", get(viewer::viewer))
+        .route("/zonal/:file", post(zonal::zonal))
+        .route("/metadata/:file", get(metadata::metadata))
+        .route("/statistics/:file", get(statistics::statistics))
+        .route("/point/:file", get(point::point))
+        .route(
+            "/profile/:file",
+            get(profile::profile_get).post(profile::profile_post),
+        )
+        .route_layer(middleware::from_fn(access::authenticate))
+}
+
+/// The commands run on the datasets of the current directory instead of serving them.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Renders the tiles of a dataset, bypassing the cache, and reports the rendering latency
+    Bench(bench::Options),
+    /// Renders the tiles of a dataset into the cache, and optionally into an archive
+    Seed(seed::Options),
+    /// Renders an extent of a dataset into a georeferenced image
+    Export(export::Options),
+    /// Prints the information of a dataset, with suggested zoom levels and rescale ranges
+    Info(info::Options),
+}
+
+pub fn run_command(command: Command) -> Result<(), Error> {
+    error::log_gdal_errors();
+    match command {
+        Command::Bench(options) => bench::run(options),
+        Command::Seed(options) => seed::run(options),
+        Command::Export(options) => export::run(options),
+        Command::Info(options) => info::run(options),
+    }
+}
+
+/// Parses a command option counting something, which must be at least one.
+fn parse_count(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or_else(|| format!("expected a positive integer, got {}", value))
+}
+
+/// Parses a positive and finite command option.
+fn parse_positive(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&value: &f64| value > 0.0 && value.is_finite())
+        .ok_or_else(|| format!("expected a positive number, got {}", value))
+}
+
+/// Parses the `--bbox` of a command.
+fn parse_bbox(bbox: &str) -> Result<Extent, String> {
+    preview::parse_bbox(bbox).map_err(|e| e.to_string())
+}
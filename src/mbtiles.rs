@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::archive::{self, RawTile};
+use crate::error::Error;
+
+pub fn metadata(connection: &Connection) -> Result<BTreeMap<String, String>, Error> {
+    let mut statement = connection.prepare("SELECT name, value FROM metadata")?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Reads a tile, with `row` counted from the bottom as in the MBTiles schema.
+pub fn read_tile(path: &Path, z: u8, x: u32, row: u32) -> Result<RawTile, Error> {
+    let connection = archive::open_sqlite(path)?;
+    let format = metadata(&connection)?
+        .remove("format")
+        .unwrap_or_else(|| "png".to_string());
+    let data: Vec<u8> = connection
+        .query_row(
+            "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            [z as u32, x, row],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or(Error::OutsideBounds)?;
+    let encoding = if data.starts_with(&[0x1f, 0x8b]) {
+        Some("gzip")
+    } else {
+        None
+    };
+    Ok(RawTile {
+        data: data.into(),
+        format,
+        encoding,
+    })
+}
+
+/// Returns the zoom levels present in the archive.
+pub fn zoom_range(connection: &Connection) -> Result<Option<(u8, u8)>, Error> {
+    let range = connection.query_row(
+        "SELECT MIN(zoom_level), MAX(zoom_level) FROM tiles",
+        [],
+        |row| Ok((row.get::<_, Option<u8>>(0)?, row.get::<_, Option<u8>>(1)?)),
+    )?;
+    Ok(match range {
+        (Some(min), Some(max)) => Some((min, max)),
+        _ => None,
+    })
+}
+
+/// Writes tiles into a new or existing MBTiles archive, in a single transaction.
+pub struct Writer {
+    connection: Connection,
+}
+
+impl Writer {
+    pub fn create(path: &Path, metadata: &BTreeMap<&str, String>) -> Result<Self, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA synchronous = OFF;
+             CREATE TABLE IF NOT EXISTS metadata (name TEXT, value TEXT);
+             CREATE UNIQUE INDEX IF NOT EXISTS name ON metadata (name);
+             CREATE TABLE IF NOT EXISTS tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+             CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);
+             BEGIN;",
+        )?;
+        for (name, value) in metadata {
+            connection.execute(
+                "INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)",
+                [name, value.as_str()],
+            )?;
+        }
+        Ok(Self { connection })
+    }
+
+    /// Adds or replaces a tile, with `row` counted from the bottom as in the MBTiles schema.
+    pub fn insert(&self, z: u8, x: u32, row: u32, data: &[u8]) -> Result<(), Error> {
+        self.connection
+            .prepare_cached(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(rusqlite::params![z, x, row, data])?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
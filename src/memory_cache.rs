@@ -0,0 +1,118 @@
+//! The most recently used tiles, kept in memory in front of the cache directory, so that the
+//! popular ones are served without reading their files.
+//!
+//! The tiles are keyed by their paths in the cache directory, and dropped along with their files
+//! by `admin::purge_cache`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use axum::body::Bytes;
+
+struct Tiles {
+    /// The tiles by key, with when they were last used.
+    entries: BTreeMap<String, (Bytes, u64)>,
+    /// The keys by when they were last used.
+    used: BTreeMap<u64, String>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl Tiles {
+    fn remove(&mut self, key: &str) {
+        if let Some((png, used)) = self.entries.remove(key) {
+            self.used.remove(&used);
+            self.bytes -= png.len();
+        }
+    }
+
+    /// Drops the least recently used tiles until they fit in `max_bytes`.
+    fn trim(&mut self, max_bytes: usize) {
+        while self.bytes > max_bytes {
+            let key = match self.used.pop_first() {
+                Some((_, key)) => key,
+                None => break,
+            };
+            if let Some((png, _)) = self.entries.remove(&key) {
+                self.bytes -= png.len();
+            }
+        }
+    }
+}
+
+static TILES: Mutex<Tiles> = Mutex::new(Tiles {
+    entries: BTreeMap::new(),
+    used: BTreeMap::new(),
+    bytes: 0,
+    clock: 0,
+});
+static MAX_BYTES: AtomicUsize = AtomicUsize::new(0);
+static HITS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the size of the tiles kept in memory, dropping the least recently used ones above it.
+/// Nothing is kept with 0, the default.
+pub fn set_max_bytes(max_bytes: usize) {
+    MAX_BYTES.store(max_bytes, Ordering::Relaxed);
+    TILES.lock().unwrap().trim(max_bytes);
+}
+
+/// Returns a tile kept in memory, marking it as used.
+pub fn get(key: &str) -> Option<Bytes> {
+    let mut tiles = TILES.lock().unwrap();
+    tiles.clock += 1;
+    let clock = tiles.clock;
+    let (png, used) = tiles.entries.get_mut(key)?;
+    let (png, last_used) = (png.clone(), std::mem::replace(used, clock));
+    tiles.used.remove(&last_used);
+    tiles.used.insert(clock, key.to_string());
+    HITS.fetch_add(1, Ordering::Relaxed);
+    Some(png)
+}
+
+/// Keeps a tile in memory, unless it's larger than all of them may be.
+pub fn insert(key: &str, png: Bytes) {
+    let max_bytes = MAX_BYTES.load(Ordering::Relaxed);
+    if png.len() > max_bytes {
+        return;
+    }
+    let mut tiles = TILES.lock().unwrap();
+    tiles.remove(key);
+    tiles.clock += 1;
+    let clock = tiles.clock;
+    tiles.bytes += png.len();
+    tiles.entries.insert(key.to_string(), (png, clock));
+    tiles.used.insert(clock, key.to_string());
+    tiles.trim(max_bytes);
+}
+
+/// Drops the tiles whose file names start with `prefix`, or all of them.
+pub fn purge(prefix: Option<&str>) {
+    let mut tiles = TILES.lock().unwrap();
+    let keys = tiles
+        .entries
+        .keys()
+        .filter(|key| {
+            prefix.is_none_or(|prefix| {
+                Path::new(key)
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(prefix))
+            })
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in keys {
+        tiles.remove(&key);
+    }
+}
+
+/// The size of the tiles in memory.
+pub fn bytes() -> usize {
+    TILES.lock().unwrap().bytes
+}
+
+/// Tiles served from memory since the server started.
+pub fn hits() -> u64 {
+    HITS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::Json;
+use gdal::Metadata;
+use serde::Serialize;
+
+use crate::dataset;
+use crate::error::Error;
+use crate::registry::Registry;
+use crate::workers;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum DomainMetadata {
+    Items(BTreeMap<String, String>),
+    Xml(String),
+}
+
+#[derive(Serialize)]
+pub struct DatasetMetadata {
+    driver: String,
+    description: String,
+    domains: BTreeMap<String, DomainMetadata>,
+    bands: Vec<BandMetadata>,
+}
+
+#[derive(Serialize)]
+pub struct BandMetadata {
+    band: isize,
+    description: String,
+    domains: BTreeMap<String, DomainMetadata>,
+}
+
+/// Collects all metadata domains of a GDAL object.
+pub fn domains<M: Metadata>(object: &M) -> BTreeMap<String, DomainMetadata> {
+    let mut domains = BTreeMap::new();
+    let mut names = object.metadata_domains();
+    if !names.iter().any(|name| name.is_empty()) {
+        names.push(String::new());
+    }
+    for name in names {
+        let items = match object.metadata_domain(&name) {
+            Some(items) if !items.is_empty() => items,
+            _ => continue,
+        };
+        let metadata = if name.starts_with("xml:") {
+            DomainMetadata::Xml(items.concat())
+        } else {
+            DomainMetadata::Items(
+                items
+                    .iter()
+                    .map(|item| match item.split_once('=') {
+                        Some((key, value)) => (key.to_string(), value.to_string()),
+                        None => (item.clone(), String::new()),
+                    })
+                    .collect(),
+            )
+        };
+        domains.insert(name, metadata);
+    }
+    domains
+}
+
+fn read_metadata(path: &Path) -> Result<DatasetMetadata, Error> {
+    let dataset = dataset::open(path)?;
+    let mut bands = Vec::new();
+    for band in 1..=dataset.raster_count() {
+        let rasterband = dataset.rasterband(band)?;
+        bands.push(BandMetadata {
+            band,
+            description: rasterband.description()?,
+            domains: domains(&rasterband),
+        });
+    }
+    Ok(DatasetMetadata {
+        driver: dataset.driver().short_name(),
+        description: dataset.description()?,
+        domains: domains(&dataset),
+        bands,
+    })
+}
+
+pub async fn metadata(
+    extract::Path(file): extract::Path<String>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Json<DatasetMetadata>, Error> {
+    let path = registry.resolve(&file)?;
+    let metadata = workers::run(move || read_metadata(&path)).await?;
+    Ok(Json(metadata))
+}
@@ -0,0 +1,99 @@
+use std::fmt::Write;
+
+use axum::http::header;
+use axum::response::IntoResponse;
+
+use crate::cache;
+use crate::cluster;
+use crate::memory_cache;
+use crate::workers;
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl ToString) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value.to_string());
+}
+
+/// Reports the server metrics in the Prometheus text format.
+pub async fn metrics() -> impl IntoResponse {
+    let stats = workers::stats();
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "tile_server_worker_threads",
+        "gauge",
+        "GDAL jobs that can run at once.",
+        stats.threads,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_worker_jobs_running",
+        "gauge",
+        "GDAL jobs running.",
+        stats.running,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_worker_queue_depth",
+        "gauge",
+        "GDAL jobs waiting for a thread.",
+        stats.queued,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_worker_queue_max_depth",
+        "gauge",
+        "GDAL jobs that can wait for a thread before requests are rejected.",
+        stats.queue_depth,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_worker_rejected_total",
+        "counter",
+        "Requests rejected because the queue was full.",
+        stats.rejected,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_worker_timed_out_total",
+        "counter",
+        "GDAL jobs abandoned after running past the read deadline.",
+        stats.timed_out,
+    );
+    write_metric(
+        &mut out,
+        "tile_server_cluster_forwarded_total",
+        "counter",
+        "Tile requests forwarded to the peer rendering them.",
+        cluster::forwarded(),
+    );
+    write_metric(
+        &mut out,
+        "tile_server_cluster_forward_failed_total",
+        "counter",
+        "Tile requests rendered locally because their peer couldn't be reached.",
+        cluster::failed(),
+    );
+    write_metric(
+        &mut out,
+        "tile_server_cache_corrupt_total",
+        "counter",
+        "Corrupt cache entries found and removed.",
+        cache::corrupt_entries(),
+    );
+    write_metric(
+        &mut out,
+        "tile_server_memory_cache_bytes",
+        "gauge",
+        "Size of the tiles kept in memory.",
+        memory_cache::bytes(),
+    );
+    write_metric(
+        &mut out,
+        "tile_server_memory_cache_hits_total",
+        "counter",
+        "Tiles served from memory.",
+        memory_cache::hits(),
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
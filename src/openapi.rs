@@ -0,0 +1,292 @@
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde_json::{json, Value};
+
+use crate::compressed;
+use crate::error::Error;
+
+fn path_param(name: &str, ty: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": { "type": ty },
+    })
+}
+
+fn query_param(name: &str, ty: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": ty },
+    })
+}
+
+fn file_param() -> Value {
+    path_param("file", "string")
+}
+
+fn response(description: &str, content_type: &str) -> Value {
+    json!({
+        "200": {
+            "description": description,
+            "content": { content_type: {} },
+        },
+    })
+}
+
+fn operation(summary: &str, parameters: Vec<Value>, content_type: &str) -> Value {
+    json!({
+        "summary": summary,
+        "parameters": parameters,
+        "responses": response(summary, content_type),
+    })
+}
+
+fn style_params() -> Vec<Value> {
+    vec![
+        query_param("bands", "string", "One or three comma-separated bands"),
+        query_param("rescale", "string", "min,max range mapped to 0-255"),
+        query_param(
+            "scale",
+            "string",
+            "db to convert the values to decibels before rescaling them, -25,0 by default",
+        ),
+        query_param("colormap", "string", "gray, viridis, magma or terrain"),
+        query_param(
+            "pansharpen",
+            "boolean",
+            "Render the pan-sharpened bands, for datasets with a panchromatic band",
+        ),
+    ]
+}
+
+fn filter_params() -> Vec<Value> {
+    vec![
+        query_param("sharpen", "number", "Unsharp mask amount, up to 5"),
+        query_param("blur", "number", "Gaussian blur radius in pixels, up to 10"),
+        query_param(
+            "speckle",
+            "string",
+            "boxcar or lee speckle filter, with an optional window size and number of looks, like lee,7,4",
+        ),
+    ]
+}
+
+fn crs_param() -> Value {
+    query_param(
+        "crs",
+        "string",
+        "CRS of the input coordinates, defaults to EPSG:4326",
+    )
+}
+
+fn bands_param() -> Value {
+    query_param("bands", "string", "Comma-separated list of bands")
+}
+
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "tile-server",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/tile/{file}/{z}/{x}/{y}": {
+                "get": operation(
+                    "Render a map tile",
+                    [
+                        vec![
+                            file_param(),
+                            path_param("z", "integer"),
+                            path_param("x", "integer"),
+                            path_param("y", "integer"),
+                        ],
+                        style_params(),
+                        filter_params(),
+                    ]
+                    .concat(),
+                    "image/png",
+                ),
+            },
+            "/batch/{file}": {
+                "post": {
+                    "summary": "Tar archive of rendered tiles",
+                    "parameters": ([vec![file_param()], style_params(), filter_params()].concat()),
+                    "requestBody": {
+                        "required": true,
+                        "description": "Either {\"tiles\": [[z, x, y], ...]} or {\"bbox\": [xmin, ymin, xmax, ymax], \"minzoom\": z, \"maxzoom\": z}, with the bounding box in the tile grid CRS",
+                        "content": { "application/json": {} },
+                    },
+                    "responses": response("Tar archive of rendered tiles", "application/x-tar"),
+                },
+            },
+            "/composite/{layers}/{z}/{x}/{y}": {
+                "get": operation(
+                    "Blend several datasets into a map tile",
+                    vec![
+                        json!({
+                            "name": "layers",
+                            "in": "path",
+                            "required": true,
+                            "description": "Comma-separated datasets from bottom to top, with an optional :opacity suffix",
+                            "schema": { "type": "string" },
+                        }),
+                        path_param("z", "integer"),
+                        path_param("x", "integer"),
+                        path_param("y", "integer"),
+                    ],
+                    "image/png",
+                ),
+            },
+            "/info/{file}": {
+                "get": operation(
+                    "Dataset extent, projection and attribute tables",
+                    vec![
+                        file_param(),
+                        query_param("crs", "string", "Also return the extent in this CRS"),
+                    ],
+                    "application/json",
+                ),
+            },
+            "/metadata/{file}": {
+                "get": operation("Raw GDAL metadata", vec![file_param()], "application/json"),
+            },
+            "/point/{file}": {
+                "get": operation(
+                    "Pixel values and their classes at a location",
+                    vec![
+                        file_param(),
+                        json!({ "name": "lon", "in": "query", "required": true, "schema": { "type": "number" } }),
+                        json!({ "name": "lat", "in": "query", "required": true, "schema": { "type": "number" } }),
+                        crs_param(),
+                        bands_param(),
+                    ],
+                    "application/json",
+                ),
+            },
+            "/profile/{file}": {
+                "get": operation(
+                    "Values sampled along a line",
+                    vec![
+                        file_param(),
+                        json!({
+                            "name": "line",
+                            "in": "query",
+                            "required": true,
+                            "description": "GeoJSON LineString",
+                            "schema": { "type": "string" },
+                        }),
+                        query_param("samples", "integer", "Number of samples"),
+                        crs_param(),
+                        bands_param(),
+                    ],
+                    "application/json",
+                ),
+                "post": {
+                    "summary": "Values sampled along a line",
+                    "parameters": [file_param()],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": {} },
+                    },
+                    "responses": response("Values sampled along a line", "application/json"),
+                },
+            },
+            "/statistics/{file}": {
+                "get": operation(
+                    "Statistics and histograms of the bands",
+                    vec![file_param()],
+                    "application/json",
+                ),
+            },
+            "/zonal/{file}": {
+                "post": {
+                    "summary": "Statistics of the pixels inside a polygon",
+                    "parameters": [file_param(), crs_param(), bands_param()],
+                    "requestBody": {
+                        "required": true,
+                        "description": "GeoJSON Polygon or MultiPolygon",
+                        "content": { "application/json": {} },
+                    },
+                    "responses": response("Statistics of the pixels inside a polygon", "application/json"),
+                },
+            },
+            "/preview/{file}": {
+                "get": operation(
+                    "Render an arbitrary extent",
+                    [
+                        vec![
+                            file_param(),
+                            query_param("bbox", "string", "xmin,ymin,xmax,ymax"),
+                            query_param("width", "integer", "Output width"),
+                            query_param("height", "integer", "Output height"),
+                            query_param("crs", "string", "CRS of the bounding box"),
+                        ],
+                        style_params(),
+                    ]
+                    .concat(),
+                    "image/png",
+                ),
+            },
+            "/wcs": {
+                "get": operation(
+                    "WCS 2.0 GetCoverage, downloading the values of a raster",
+                    vec![
+                        query_param("service", "string", "WCS"),
+                        query_param("request", "string", "GetCoverage"),
+                        query_param("coverageId", "string", "The name of the dataset"),
+                        query_param("subset", "string", "Repeatable, like x(100000,200000)"),
+                        query_param("subsettingCrs", "string", "CRS of the subsets"),
+                        query_param("outputCrs", "string", "CRS to reproject the coverage to"),
+                        query_param("scaleFactor", "number", "Multiple of the native size"),
+                        query_param("scaleSize", "string", "Output size, like x(512),y(512)"),
+                    ],
+                    "image/tiff",
+                ),
+            },
+            "/thumbnail/{file}": {
+                "get": operation(
+                    "Overview image of the whole dataset",
+                    vec![file_param(), query_param("size", "integer", "Largest dimension")],
+                    "image/png",
+                ),
+            },
+            "/tilejson/{file}": {
+                "get": operation("TileJSON document", vec![file_param()], "application/json"),
+            },
+            "/catalog": {
+                "get": operation("TileJSON documents of all datasets", vec![], "application/json"),
+            },
+            "/bounds/{file}": {
+                "get": operation("Valid-data footprint as GeoJSON", vec![file_param()], "application/json"),
+            },
+            "/viewer/{file}": {
+                "get": operation("Interactive map viewer", vec![file_param()], "text/html"),
+            },
+            "/healthz": {
+                "get": operation("Liveness probe", vec![], "text/plain"),
+            },
+            "/readyz": {
+                "get": operation("Readiness probe", vec![], "application/json"),
+            },
+            "/version": {
+                "get": operation("Version and build information", vec![], "application/json"),
+            },
+            "/metrics": {
+                "get": operation("Metrics in the Prometheus text format", vec![], "text/plain"),
+            },
+            "/openapi.json": {
+                "get": operation("This document", vec![], "application/json"),
+            },
+        },
+    })
+}
+
+pub async fn openapi(headers: HeaderMap) -> Result<Response, Error> {
+    let document = compressed::encode("openapi".to_string(), &document())?;
+    Ok(document.respond(&headers))
+}
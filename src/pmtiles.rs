@@ -0,0 +1,717 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gdal_sys::VSILFILE;
+use serde_json::Value;
+
+use crate::archive::RawTile;
+use crate::error::Error;
+use crate::remote;
+use crate::tile_grid::{self, Extent};
+
+const HEADER_LENGTH: usize = 127;
+const SEEK_SET: i32 = 0;
+/// Directories can point to leaf directories, but at most this deep.
+const MAX_DEPTH: usize = 4;
+/// The root directory must fit in the first 16 KiB of the archive, along with the header.
+const MAX_ROOT_LENGTH: usize = 16384 - HEADER_LENGTH;
+
+fn invalid_data(msg: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))
+}
+
+/// Handles of an archive kept open besides the ones in use.
+const MAX_IDLE_FILES: usize = 8;
+
+/// A file read through the GDAL virtual file system, so that `/vsicurl/` URLs use range requests.
+struct VsiFile(*mut VSILFILE);
+
+// the handle can be used from any thread, as long as it's by one at a time
+unsafe impl Send for VsiFile {}
+
+impl VsiFile {
+    fn open(path: &str) -> Result<Self, Error> {
+        let c_path = CString::new(path)?;
+        let file = remote::with_path_options(Path::new(path), || unsafe {
+            gdal_sys::VSIFOpenL(c_path.as_ptr(), b"rb\0".as_ptr() as *const _)
+        });
+        if file.is_null() {
+            return Err(Error::last_cpl_error(gdal_sys::CPLErr::CE_Failure));
+        }
+        Ok(Self(file))
+    }
+
+    fn read_at(&self, offset: u64, length: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; length];
+        let read = unsafe {
+            if gdal_sys::VSIFSeekL(self.0, offset, SEEK_SET) != 0 {
+                return Err(invalid_data("cannot seek in archive"));
+            }
+            gdal_sys::VSIFReadL(buf.as_mut_ptr() as *mut _, 1, length, self.0)
+        };
+        if read != length {
+            return Err(invalid_data("unexpected end of archive"));
+        }
+        Ok(buf)
+    }
+}
+
+impl Drop for VsiFile {
+    fn drop(&mut self) {
+        unsafe {
+            gdal_sys::VSIFCloseL(self.0);
+        }
+    }
+}
+
+/// The fixed-size header of a PMTiles v3 archive.
+pub struct Header {
+    root_dir: (u64, u64),
+    metadata: (u64, u64),
+    leaf_dirs_offset: u64,
+    tile_data_offset: u64,
+    internal_compression: u8,
+    tile_compression: u8,
+    tile_type: u8,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub bounds: Extent,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < HEADER_LENGTH || &buf[..7] != b"PMTiles" || buf[7] != 3 {
+            return Err(invalid_data("not a PMTiles v3 archive"));
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        let degrees_at =
+            |i: usize| i32::from_le_bytes(buf[i..i + 4].try_into().unwrap()) as f64 / 1e7;
+        Ok(Self {
+            root_dir: (u64_at(8), u64_at(16)),
+            metadata: (u64_at(24), u64_at(32)),
+            leaf_dirs_offset: u64_at(40),
+            tile_data_offset: u64_at(56),
+            internal_compression: buf[97],
+            tile_compression: buf[98],
+            tile_type: buf[99],
+            min_zoom: buf[100],
+            max_zoom: buf[101],
+            bounds: Extent {
+                xmin: degrees_at(102),
+                ymin: degrees_at(106),
+                xmax: degrees_at(110),
+                ymax: degrees_at(114),
+            },
+        })
+    }
+
+    fn format(&self) -> &'static str {
+        match self.tile_type {
+            1 => "pbf",
+            2 => "png",
+            3 => "jpg",
+            4 => "webp",
+            5 => "avif",
+            _ => "",
+        }
+    }
+
+    fn tile_encoding(&self) -> Option<&'static str> {
+        match self.tile_compression {
+            2 => Some("gzip"),
+            3 => Some("br"),
+            4 => Some("zstd"),
+            _ => None,
+        }
+    }
+}
+
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf
+            .split_first()
+            .ok_or_else(|| invalid_data("truncated directory"))?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint too long"))
+}
+
+fn parse_directory(mut buf: &[u8]) -> Result<Vec<Entry>, Error> {
+    let buf = &mut buf;
+    let count = read_varint(buf)? as usize;
+    let mut entries = Vec::with_capacity(count.min(1 << 16));
+    let mut tile_id = 0;
+    for _ in 0..count {
+        tile_id += read_varint(buf)?;
+        entries.push(Entry {
+            tile_id,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        });
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(buf)?;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(buf)?;
+    }
+    for i in 0..entries.len() {
+        let offset = read_varint(buf)?;
+        entries[i].offset = if offset == 0 && i > 0 {
+            entries[i - 1].offset + entries[i - 1].length
+        } else {
+            offset.wrapping_sub(1)
+        };
+    }
+    Ok(entries)
+}
+
+/// Maps tile coordinates to their position on the Hilbert curve used to order the archive.
+fn tile_id(z: u8, x: u32, y: u32) -> u64 {
+    let base = ((1u64 << (2 * z as u64)) - 1) / 3;
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    base + d
+}
+
+/// An open PMTiles v3 archive, either a local file or a `/vsicurl/` URL, with its header and
+/// root directory, so that they're only read once.
+pub struct PmTiles {
+    path: String,
+    header: Header,
+    root: Vec<Entry>,
+    /// Open handles of the archive, each used by one request at a time.
+    files: Mutex<Vec<VsiFile>>,
+}
+
+impl PmTiles {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = VsiFile::open(path)?;
+        let header = Header::parse(&file.read_at(0, HEADER_LENGTH)?)?;
+        let mut archive = Self {
+            path: path.to_string(),
+            header,
+            root: Vec::new(),
+            files: Mutex::new(Vec::new()),
+        };
+        archive.root = parse_directory(&archive.read_internal(&file, archive.header.root_dir)?)?;
+        archive.files.lock().unwrap().push(file);
+        Ok(archive)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Reads from an idle handle of the archive, or a new one.
+    fn with_file<T>(&self, f: impl FnOnce(&VsiFile) -> Result<T, Error>) -> Result<T, Error> {
+        let file = self.files.lock().unwrap().pop();
+        let file = match file {
+            Some(file) => file,
+            None => VsiFile::open(&self.path)?,
+        };
+        let result = f(&file);
+        let mut files = self.files.lock().unwrap();
+        if files.len() < MAX_IDLE_FILES {
+            files.push(file);
+        }
+        result
+    }
+
+    fn read_internal(
+        &self,
+        file: &VsiFile,
+        (offset, length): (u64, u64),
+    ) -> Result<Vec<u8>, Error> {
+        let data = file.read_at(offset, length as usize)?;
+        match self.header.internal_compression {
+            0 | 1 => Ok(data),
+            2 => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(&data[..]).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            _ => Err(invalid_data("unsupported directory compression")),
+        }
+    }
+
+    /// Returns the JSON metadata of the archive.
+    pub fn metadata(&self) -> Result<Value, Error> {
+        if self.header.metadata.1 == 0 {
+            return Ok(Value::Null);
+        }
+        let metadata = self.with_file(|file| self.read_internal(file, self.header.metadata))?;
+        serde_json::from_slice(&metadata).map_err(|e| invalid_data(&e.to_string()))
+    }
+
+    /// Reads a tile, with `y` counted from the top as in the XYZ scheme.
+    pub fn read_tile(&self, z: u8, x: u32, y: u32) -> Result<RawTile, Error> {
+        tile_grid::check_tile(z, x, y)?;
+        let tile_id = tile_id(z, x, y);
+        let mut leaf;
+        let mut entries = &self.root;
+        for _ in 0..MAX_DEPTH {
+            let entry = match entries.binary_search_by_key(&tile_id, |entry| entry.tile_id) {
+                Ok(i) => &entries[i],
+                Err(0) => break,
+                Err(i) => &entries[i - 1],
+            };
+            if entry.run_length == 0 {
+                let directory = (self.header.leaf_dirs_offset + entry.offset, entry.length);
+                leaf =
+                    self.with_file(|file| parse_directory(&self.read_internal(file, directory)?))?;
+                entries = &leaf;
+            } else if tile_id < entry.tile_id + entry.run_length {
+                let (offset, length) = (
+                    self.header.tile_data_offset + entry.offset,
+                    entry.length as usize,
+                );
+                let data = self.with_file(|file| file.read_at(offset, length))?;
+                return Ok(RawTile {
+                    data: data.into(),
+                    format: self.header.format().to_string(),
+                    encoding: self.header.tile_encoding(),
+                });
+            } else {
+                break;
+            }
+        }
+        Err(Error::OutsideBounds)
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Encodes sorted entries in the layout read by `parse_directory`.
+fn serialize_directory(entries: &[Entry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, entries.len() as u64);
+    let mut last_id = 0;
+    for entry in entries {
+        write_varint(&mut buf, entry.tile_id - last_id);
+        last_id = entry.tile_id;
+    }
+    for entry in entries {
+        write_varint(&mut buf, entry.run_length);
+    }
+    for entry in entries {
+        write_varint(&mut buf, entry.length);
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        let contiguous = i > 0 && entry.offset == entries[i - 1].offset + entries[i - 1].length;
+        write_varint(&mut buf, if contiguous { 0 } else { entry.offset + 1 });
+    }
+    buf
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Builds the root directory and the leaf directories it points to, if the entries don't fit
+/// in the root.
+fn build_directories(entries: &[Entry]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let root = gzip(&serialize_directory(entries))?;
+    if root.len() <= MAX_ROOT_LENGTH {
+        return Ok((root, Vec::new()));
+    }
+    let mut leaf_size = 4096;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = gzip(&serialize_directory(chunk))?;
+            root_entries.push(Entry {
+                tile_id: chunk[0].tile_id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u64,
+                run_length: 0,
+            });
+            leaves.extend_from_slice(&leaf);
+        }
+        let root = gzip(&serialize_directory(&root_entries))?;
+        if root.len() <= MAX_ROOT_LENGTH {
+            return Ok((root, leaves));
+        }
+        leaf_size *= 2;
+    }
+}
+
+/// Writes PNG tiles into a new PMTiles v3 archive.
+///
+/// The tiles can come in any order. Their data goes to a temporary file next to the archive
+/// until `finish` writes the directories in front of it.
+pub struct Writer {
+    path: PathBuf,
+    data_path: PathBuf,
+    data: BufWriter<File>,
+    data_length: u64,
+    entries: Vec<Entry>,
+    zoom_range: (u8, u8),
+    bounds: Extent,
+    metadata: Value,
+}
+
+impl Writer {
+    /// Starts an archive for the zoom levels in `zoom_range`, with WGS 84 `bounds`.
+    pub fn create(
+        path: &Path,
+        zoom_range: (u8, u8),
+        bounds: Extent,
+        metadata: Value,
+    ) -> Result<Self, Error> {
+        let mut data_path = path.as_os_str().to_owned();
+        data_path.push(".tiles");
+        let data_path = PathBuf::from(data_path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            data: BufWriter::new(File::create(&data_path)?),
+            data_path,
+            data_length: 0,
+            entries: Vec::new(),
+            zoom_range,
+            bounds,
+            metadata,
+        })
+    }
+
+    /// Adds a tile, with `y` counted from the top as in the XYZ scheme.
+    pub fn insert(&mut self, z: u8, x: u32, y: u32, data: &[u8]) -> Result<(), Error> {
+        self.data.write_all(data)?;
+        self.entries.push(Entry {
+            tile_id: tile_id(z, x, y),
+            offset: self.data_length,
+            length: data.len() as u64,
+            run_length: 1,
+        });
+        self.data_length += data.len() as u64;
+        Ok(())
+    }
+
+    fn header(&self, root_length: u64, metadata_length: u64, leaves_length: u64) -> Vec<u8> {
+        let root_offset = HEADER_LENGTH as u64;
+        let metadata_offset = root_offset + root_length;
+        let leaves_offset = metadata_offset + metadata_length;
+        let data_offset = leaves_offset + leaves_length;
+        let tiles = self.entries.len() as u64;
+        let degrees = |value: f64| ((value * 1e7) as i32).to_le_bytes();
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(b"PMTiles\x03");
+        for value in [
+            root_offset,
+            root_length,
+            metadata_offset,
+            metadata_length,
+            leaves_offset,
+            leaves_length,
+            data_offset,
+            self.data_length,
+            tiles,
+            tiles,
+            tiles,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        // not clustered, gzip-compressed directories, uncompressed PNG tiles
+        header.extend_from_slice(&[0, 2, 1, 2, self.zoom_range.0, self.zoom_range.1]);
+        let bounds = &self.bounds;
+        for value in [bounds.xmin, bounds.ymin, bounds.xmax, bounds.ymax] {
+            header.extend_from_slice(&degrees(value));
+        }
+        header.push(self.zoom_range.0);
+        header.extend_from_slice(&degrees((bounds.xmin + bounds.xmax) / 2.0));
+        header.extend_from_slice(&degrees((bounds.ymin + bounds.ymax) / 2.0));
+        header
+    }
+
+    /// Writes the header and the directories, followed by the tile data.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.data.flush()?;
+        self.entries.sort_by_key(|entry| entry.tile_id);
+        let (root, leaves) = build_directories(&self.entries)?;
+        let metadata = gzip(self.metadata.to_string().as_bytes())?;
+        let header = self.header(
+            root.len() as u64,
+            metadata.len() as u64,
+            leaves.len() as u64,
+        );
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        for part in [&header, &root, &metadata, &leaves] {
+            file.write_all(part)?;
+        }
+        io::copy(&mut File::open(&self.data_path)?, &mut file)?;
+        file.flush()?;
+        fs::remove_file(&self.data_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use flate2::read::GzDecoder;
+    use serde_json::json;
+
+    use super::*;
+
+    fn fields(entries: &[Entry]) -> Vec<(u64, u64, u64, u64)> {
+        entries
+            .iter()
+            .map(|entry| (entry.tile_id, entry.offset, entry.length, entry.run_length))
+            .collect()
+    }
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut decompressed).unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn varints() {
+        let mut buf = &[
+            0, 1, 0x7f, 0x80, 0x01, 0xac, 0x02, 0xff, 0xff, 0xff, 0xff, 0x0f,
+        ][..];
+        for &value in &[0, 1, 127, 128, 300, u32::MAX as u64] {
+            assert_eq!(read_varint(&mut buf).unwrap(), value);
+        }
+        assert!(buf.is_empty());
+        assert!(read_varint(&mut &[0x80, 0x80][..]).is_err());
+        assert!(read_varint(&mut &[0xff; 11][..]).is_err());
+    }
+
+    #[test]
+    fn directories() {
+        let buf = [
+            // the count, then the deltas of the tile ids
+            &[3, 0, 1, 99][..],
+            // the run lengths, the last entry pointing to a leaf directory
+            &[1, 3, 0],
+            // the lengths
+            &[10, 5, 0xe8, 0x07],
+            // the offsets plus one, or zero to follow the previous entry
+            &[1, 0, 0xad, 0x02],
+        ]
+        .concat();
+        assert_eq!(
+            fields(&parse_directory(&buf).unwrap()),
+            [(0, 0, 10, 1), (1, 10, 5, 3), (100, 300, 1000, 0)]
+        );
+        assert!(parse_directory(&[0]).unwrap().is_empty());
+        assert!(parse_directory(&[]).is_err());
+        assert!(parse_directory(&[2, 0]).is_err());
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as u64, u64::MAX];
+        let mut buf = Vec::new();
+        for &value in &values {
+            write_varint(&mut buf, value);
+        }
+        assert_eq!(&buf[..4], &[0, 1, 127, 0x80]);
+        let mut rest = &buf[..];
+        for &value in &values {
+            assert_eq!(read_varint(&mut rest).unwrap(), value);
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn directory_round_trip() {
+        let entries = vec![
+            Entry {
+                tile_id: 0,
+                offset: 0,
+                length: 10,
+                run_length: 1,
+            },
+            // contiguous with the previous one
+            Entry {
+                tile_id: 1,
+                offset: 10,
+                length: 5,
+                run_length: 3,
+            },
+            // a gap, then data shared with the first tile
+            Entry {
+                tile_id: 100,
+                offset: 0,
+                length: 10,
+                run_length: 1,
+            },
+            // a leaf directory
+            Entry {
+                tile_id: 1 << 40,
+                offset: 1 << 33,
+                length: 1000,
+                run_length: 0,
+            },
+        ];
+        let parsed = parse_directory(&serialize_directory(&entries)).unwrap();
+        assert_eq!(fields(&parsed), fields(&entries));
+        assert!(parse_directory(&serialize_directory(&[]))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn leaf_directories() {
+        // with irregular ids and lengths, so that the directory doesn't compress into the root
+        let mut state = 1u64;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+            state >> 40
+        };
+        let (mut tile_id, mut offset) = (0, 0);
+        let entries = (0..100_000)
+            .map(|_| {
+                tile_id += 1 + next() % 1000;
+                let length = 1 + next() % 100_000;
+                offset += length;
+                Entry {
+                    tile_id,
+                    offset: offset - length,
+                    length,
+                    run_length: 1,
+                }
+            })
+            .collect::<Vec<_>>();
+        let (root, leaves) = build_directories(&entries).unwrap();
+        assert!(root.len() <= MAX_ROOT_LENGTH);
+        assert!(!leaves.is_empty());
+        let mut all = Vec::new();
+        for leaf in parse_directory(&gunzip(&root)).unwrap() {
+            assert_eq!(leaf.run_length, 0);
+            let (start, end) = (leaf.offset as usize, (leaf.offset + leaf.length) as usize);
+            let leaf_entries = parse_directory(&gunzip(&leaves[start..end])).unwrap();
+            assert_eq!(leaf_entries[0].tile_id, leaf.tile_id);
+            all.extend(leaf_entries);
+        }
+        assert_eq!(fields(&all), fields(&entries));
+
+        let (root, leaves) = build_directories(&entries[..10]).unwrap();
+        assert!(leaves.is_empty());
+        assert_eq!(
+            fields(&parse_directory(&gunzip(&root)).unwrap()),
+            fields(&entries[..10])
+        );
+    }
+
+    #[test]
+    fn tile_ids() {
+        assert_eq!(tile_id(0, 0, 0), 0);
+        assert_eq!(
+            [
+                tile_id(1, 0, 0),
+                tile_id(1, 0, 1),
+                tile_id(1, 1, 1),
+                tile_id(1, 1, 0)
+            ],
+            [1, 2, 3, 4]
+        );
+        assert_eq!(tile_id(2, 0, 0), 5);
+        assert_eq!(tile_id(12, 3423, 1763), 19078479);
+        // the ids of a zoom level follow the ones of the previous levels, without gaps
+        for z in 0..5u8 {
+            let n = 1u32 << z;
+            let ids = (0..n)
+                .flat_map(|x| (0..n).map(move |y| tile_id(z, x, y)))
+                .collect::<BTreeSet<_>>();
+            let base = ((1u64 << (2 * z)) - 1) / 3;
+            assert_eq!(ids, (base..base + (n as u64).pow(2)).collect());
+        }
+        let max = (1 << tile_grid::MAX_ZOOM) - 1;
+        assert!(tile_id(tile_grid::MAX_ZOOM, max, max) > tile_id(tile_grid::MAX_ZOOM - 1, 0, 0));
+    }
+    #[test]
+    fn writer_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("pmtiles-test-{}.pmtiles", std::process::id()));
+        let bounds = Extent {
+            xmin: -10.0,
+            ymin: -5.5,
+            xmax: 20.25,
+            ymax: 45.0,
+        };
+        let mut writer = Writer::create(&path, (0, 2), bounds, json!({"name": "test"})).unwrap();
+        let tiles = [(2, 3, 1), (0, 0, 0), (1, 1, 0), (2, 0, 3)];
+        for &(z, x, y) in &tiles {
+            writer
+                .insert(z, x, y, format!("{}/{}/{}", z, x, y).as_bytes())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let archive = PmTiles::open(path.to_str().unwrap()).unwrap();
+        let header = archive.header();
+        assert_eq!((header.min_zoom, header.max_zoom), (0, 2));
+        assert_eq!(
+            [
+                header.bounds.xmin,
+                header.bounds.ymin,
+                header.bounds.xmax,
+                header.bounds.ymax
+            ],
+            [-10.0, -5.5, 20.25, 45.0]
+        );
+        assert_eq!(archive.metadata().unwrap(), json!({"name": "test"}));
+        for &(z, x, y) in &tiles {
+            let tile = archive.read_tile(z, x, y).unwrap();
+            assert_eq!(&tile.data[..], format!("{}/{}/{}", z, x, y).as_bytes());
+            assert_eq!(tile.format, "png");
+            assert_eq!(tile.encoding, None);
+        }
+        for (z, x, y) in [(1, 0, 0), (3, 0, 0), (1, 2, 0), (31, 0, 0), (255, 0, 0)] {
+            assert!(matches!(
+                archive.read_tile(z, x, y),
+                Err(Error::OutsideBounds)
+            ));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
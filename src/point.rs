@@ -0,0 +1,120 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::Json;
+use gdal::Dataset;
+use serde::{Deserialize, Serialize};
+
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::rat;
+use crate::registry::Registry;
+use crate::workers;
+
+#[derive(Deserialize)]
+pub struct PointQuery {
+    lon: f64,
+    lat: f64,
+    crs: Option<String>,
+    bands: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PointInfo {
+    x: f64,
+    y: f64,
+    col: usize,
+    row: usize,
+    values: Vec<BandValue>,
+}
+
+#[derive(Serialize)]
+pub struct BandValue {
+    pub band: isize,
+    pub value: Option<f64>,
+    pub scaled: Option<f64>,
+    /// The unit of the scaled value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// The name of the class of the value in the attribute table of the band.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+}
+
+pub fn read_value(
+    dataset: &Dataset,
+    band: isize,
+    col: usize,
+    row: usize,
+) -> Result<BandValue, Error> {
+    let rasterband = dataset.rasterband(band)?;
+    let buf = rasterband.read_as::<f64>((col as isize, row as isize), (1, 1), (1, 1), None)?;
+    let value = Some(buf.data[0]).filter(|&v| Some(v) != rasterband.no_data_value());
+    let scaled =
+        value.map(|v| v * rasterband.scale().unwrap_or(1.0) + rasterband.offset().unwrap_or(0.0));
+    Ok(BandValue {
+        band,
+        value,
+        scaled,
+        unit: dataset::band_unit(dataset, band),
+        class: value.and_then(|value| rat::class_name(dataset, band, value)),
+    })
+}
+
+pub fn parse_bands(bands: &str, band_count: isize) -> Result<Vec<isize>, Error> {
+    bands
+        .split(',')
+        .map(|band| match band.trim().parse::<isize>() {
+            Ok(band) if band >= 1 && band <= band_count => Ok(band),
+            _ => Err(Error::BadRequest(format!("invalid band: {}", band))),
+        })
+        .collect()
+}
+
+fn query_point(path: &Path, query: &PointQuery) -> Result<PointInfo, Error> {
+    let dataset = dataset::open(path)?;
+    let spatial_ref = dataset::spatial_ref(&dataset)?;
+    let source_srs = match &query.crs {
+        Some(crs) => crs::parse_srs(crs)?,
+        None => crs::wgs84()?,
+    };
+
+    let mut x = [query.lon];
+    let mut y = [query.lat];
+    let mut z = [0.0];
+    let transform = crs::transform(&source_srs, &spatial_ref)?;
+    transform.transform_coords(&mut x, &mut y, &mut z)?;
+
+    let geo_transform = dataset::geo_transform(&dataset)?;
+    let (col, row) = dataset::pixel_at(&geo_transform, dataset.raster_size(), x[0], y[0])
+        .ok_or(Error::OutsideBounds)?;
+
+    let bands = match &query.bands {
+        Some(bands) => parse_bands(bands, dataset.raster_count())?,
+        None => (1..=dataset.raster_count()).collect(),
+    };
+    let values = bands
+        .into_iter()
+        .map(|band| read_value(&dataset, band, col, row))
+        .collect::<Result<_, _>>()?;
+
+    Ok(PointInfo {
+        x: x[0],
+        y: y[0],
+        col,
+        row,
+        values,
+    })
+}
+
+pub async fn point(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<PointQuery>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Json<PointInfo>, Error> {
+    let path = registry.resolve(&file)?;
+    let info = workers::run(move || query_point(&path, &query)).await?;
+    Ok(Json(info))
+}
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::registry::Entry;
+use crate::style::StyleQuery;
+use crate::workers;
+
+/// Prefetched tiles being rendered.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of prefetched tiles being rendered.
+pub fn in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Lists the tiles around one at the same zoom level, in the `z/x/y` scheme of the tile endpoint.
+fn neighbours(z: u8, x: u32, y: u32) -> Vec<(u32, u32)> {
+    let count = 1i64 << z;
+    let mut neighbours = Vec::with_capacity(8);
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if (dx, dy) != (0, 0) && (0..count).contains(&nx) && (0..count).contains(&ny) {
+                neighbours.push((nx as u32, ny as u32));
+            }
+        }
+    }
+    neighbours
+}
+
+/// Renders the neighbours of a tile in the background, since clients usually request them
+/// next.
+///
+/// This is skipped while requests are waiting for a worker thread, and at most
+/// `Config::prefetch_budget` tiles are prefetched at once.
+pub fn prefetch(
+    entry: Entry,
+    file: String,
+    (z, x, y): (u8, u32, u32),
+    style: StyleQuery,
+    config: Config,
+    pool: Arc<DatasetPool>,
+) {
+    for (x, y) in neighbours(z, x, y) {
+        if workers::stats().queued > 0 {
+            return;
+        }
+        let reserved = IN_FLIGHT.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_flight| {
+            Some(in_flight + 1).filter(|&in_flight| in_flight <= config.prefetch_budget)
+        });
+        if reserved.is_err() {
+            return;
+        }
+        let (entry, file, style, config, pool) = (
+            entry.clone(),
+            file.clone(),
+            style.clone(),
+            config.clone(),
+            pool.clone(),
+        );
+        tokio::spawn(async move {
+            let result = workers::run(move || {
+                crate::cached_tile(&entry, &file, (z, x, y), &style, &config, &pool)
+            })
+            .await;
+            match result {
+                Ok(_) | Err(Error::OutsideBounds) | Err(Error::Overloaded(_)) => {}
+                Err(e) => tracing::debug!("cannot prefetch {}/{}/{}: {}", z, x, y, e),
+            }
+            IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
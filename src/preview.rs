@@ -0,0 +1,139 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use gdal::Dataset;
+use serde::Deserialize;
+
+use crate::config::{Config, OutputLimits};
+use crate::dataset;
+use crate::error::Error;
+use crate::raster_info::RasterInfo;
+use crate::registry::Registry;
+use crate::render;
+use crate::renderer::RgbRenderer;
+use crate::style::{Style, StyleQuery};
+use crate::tile_grid::Extent;
+use crate::workers;
+use crate::Png;
+
+const DEFAULT_SIZE: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct PreviewQuery {
+    bbox: Option<String>,
+    width: Option<usize>,
+    height: Option<usize>,
+    crs: Option<String>,
+}
+
+pub fn parse_bbox(bbox: &str) -> Result<Extent, Error> {
+    let values = bbox
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::BadRequest(format!("invalid bbox: {}", bbox)))?;
+    match values[..] {
+        [xmin, ymin, xmax, ymax] if xmin < xmax && ymin < ymax => Ok(Extent {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        }),
+        _ => Err(Error::BadRequest(format!("invalid bbox: {}", bbox))),
+    }
+}
+
+/// Picks an output size matching the aspect ratio of the extent when a dimension is missing,
+/// failing if it's over the limits.
+pub fn output_size(
+    extent: &Extent,
+    width: Option<usize>,
+    height: Option<usize>,
+    limits: &OutputLimits,
+) -> Result<(usize, usize), Error> {
+    let aspect = (extent.xmax - extent.xmin) / (extent.ymax - extent.ymin);
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f64 / aspect).round() as usize),
+        (None, Some(height)) => ((height as f64 * aspect).round() as usize, height),
+        (None, None) if aspect >= 1.0 => (
+            DEFAULT_SIZE,
+            (DEFAULT_SIZE as f64 / aspect).round() as usize,
+        ),
+        (None, None) => (
+            (DEFAULT_SIZE as f64 * aspect).round() as usize,
+            DEFAULT_SIZE,
+        ),
+    };
+    if width == 0 || height == 0 || width > limits.max_width || height > limits.max_height {
+        return Err(Error::BadRequest(format!(
+            "the output size must be between 1x1 and {}x{} pixels, not {}x{}",
+            limits.max_width, limits.max_height, width, height
+        )));
+    }
+    if width.saturating_mul(height) > limits.max_pixels {
+        return Err(Error::BadRequest(format!(
+            "the output can have at most {} pixels, not {}x{}",
+            limits.max_pixels, width, height
+        )));
+    }
+    Ok((width, height))
+}
+
+/// Fails if rendering an image would read more source pixels than allowed, like a large extent
+/// of a dataset without overviews.
+pub fn check_read_cost(
+    dataset: &Dataset,
+    info: &RasterInfo,
+    extent: &Extent,
+    size: (usize, usize),
+    bands: usize,
+    limits: &OutputLimits,
+) -> Result<(), Error> {
+    let cost = render::read_cost(dataset, info, extent, size, bands)?;
+    if cost > limits.max_read_pixels {
+        return Err(Error::BadRequest(format!(
+            "the request would read {} source pixels, over the limit of {}; use a smaller \
+             extent or add overviews to the dataset",
+            cost, limits.max_read_pixels
+        )));
+    }
+    Ok(())
+}
+
+fn render_preview(
+    path: &Path,
+    query: &PreviewQuery,
+    style: &StyleQuery,
+    limits: &OutputLimits,
+) -> Result<Vec<u8>, Error> {
+    let dataset = &*dataset::open_in_crs(path, query.crs.as_deref())?;
+    let info = RasterInfo::read(dataset)?;
+    let extent = match &query.bbox {
+        Some(bbox) => parse_bbox(bbox)?,
+        None => info.extent.clone(),
+    };
+    let (width, height) = output_size(&extent, query.width, query.height, limits)?;
+    let style = Style::parse(style, dataset.raster_count())?;
+    let bands = style.bands.len();
+    check_read_cost(dataset, &info, &extent, (width, height), bands, limits)?;
+    let out = render::render(dataset, &info, &extent, width, height, &style, &RgbRenderer)?;
+    render::encode_png(&out)
+}
+
+pub async fn preview(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<PreviewQuery>,
+    extract::Query(style): extract::Query<StyleQuery>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Png, Error> {
+    let entry = registry.get(&file)?;
+    let style = style.with_defaults(&entry.style);
+    let limits = config.output_limits.clone();
+    let png =
+        workers::run(move || render_preview(entry.styled_path(&style)?, &query, &style, &limits))
+            .await?;
+    Ok(Png(png.into()))
+}
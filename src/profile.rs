@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::geojson::{self, Geometry};
+use crate::point;
+use crate::registry::Registry;
+use crate::workers;
+
+const DEFAULT_SAMPLES: usize = 100;
+const MAX_SAMPLES: usize = 2000;
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+#[derive(Deserialize)]
+pub struct ProfileQuery {
+    line: String,
+    samples: Option<usize>,
+    crs: Option<String>,
+    bands: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileRequest {
+    line: Geometry,
+    samples: Option<usize>,
+    crs: Option<String>,
+    bands: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Profile {
+    bands: Vec<isize>,
+    samples: Vec<Sample>,
+}
+
+#[derive(Serialize)]
+pub struct Sample {
+    distance: f64,
+    x: f64,
+    y: f64,
+    values: Vec<Option<f64>>,
+}
+
+fn haversine((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+fn euclidean((x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> f64 {
+    (x2 - x1).hypot(y2 - y1)
+}
+
+/// Returns `samples` evenly spaced points along the line, with their distance from the start.
+fn sample_line(
+    line: &[(f64, f64)],
+    samples: usize,
+    distance: fn((f64, f64), (f64, f64)) -> f64,
+) -> Vec<(f64, (f64, f64))> {
+    let lengths = line
+        .windows(2)
+        .map(|w| distance(w[0], w[1]))
+        .collect::<Vec<_>>();
+    let total = lengths.iter().sum::<f64>();
+
+    let mut points = Vec::with_capacity(samples);
+    let mut segment = 0;
+    let mut start = 0.0;
+    for i in 0..samples {
+        let d = total * i as f64 / (samples - 1) as f64;
+        while segment + 1 < lengths.len() && start + lengths[segment] < d {
+            start += lengths[segment];
+            segment += 1;
+        }
+        let (p, q) = (line[segment], line[segment + 1]);
+        let t = if lengths[segment] > 0.0 {
+            ((d - start) / lengths[segment]).min(1.0)
+        } else {
+            0.0
+        };
+        points.push((d, (p.0 + (q.0 - p.0) * t, p.1 + (q.1 - p.1) * t)));
+    }
+    points
+}
+
+fn query_profile(
+    path: &Path,
+    line: Geometry,
+    samples: Option<usize>,
+    crs: Option<&str>,
+    bands: Option<&str>,
+) -> Result<Profile, Error> {
+    let line = line.into_line_string()?;
+    if line.len() < 2 {
+        return Err(Error::BadRequest(
+            "line must have at least two positions".to_string(),
+        ));
+    }
+    let samples = samples.unwrap_or(DEFAULT_SAMPLES);
+    if !(2..=MAX_SAMPLES).contains(&samples) {
+        return Err(Error::BadRequest(format!(
+            "samples must be between 2 and {}",
+            MAX_SAMPLES
+        )));
+    }
+
+    let dataset = dataset::open(path)?;
+    let spatial_ref = dataset::spatial_ref(&dataset)?;
+    let source_srs = match crs {
+        Some(crs) => crs::parse_srs(crs)?,
+        None => crs::wgs84()?,
+    };
+    let distance = if source_srs.is_geographic() {
+        haversine
+    } else {
+        euclidean
+    };
+    let points = sample_line(&line, samples, distance);
+
+    let mut xs = points.iter().map(|(_, p)| p.0).collect::<Vec<_>>();
+    let mut ys = points.iter().map(|(_, p)| p.1).collect::<Vec<_>>();
+    let mut zs = vec![0.0; points.len()];
+    let transform = crs::transform(&source_srs, &spatial_ref)?;
+    transform.transform_coords(&mut xs, &mut ys, &mut zs)?;
+
+    let bands = match bands {
+        Some(bands) => point::parse_bands(bands, dataset.raster_count())?,
+        None => (1..=dataset.raster_count()).collect(),
+    };
+    let geo_transform = dataset::geo_transform(&dataset)?;
+    let raster_size = dataset.raster_size();
+    let mut samples = Vec::with_capacity(points.len());
+    for (i, &(distance, (x, y))) in points.iter().enumerate() {
+        let values = match dataset::pixel_at(&geo_transform, raster_size, xs[i], ys[i]) {
+            Some((col, row)) => bands
+                .iter()
+                .map(|&band| point::read_value(&dataset, band, col, row).map(|v| v.scaled))
+                .collect::<Result<_, _>>()?,
+            None => vec![None; bands.len()],
+        };
+        samples.push(Sample {
+            distance,
+            x,
+            y,
+            values,
+        });
+    }
+
+    Ok(Profile { bands, samples })
+}
+
+pub async fn profile_get(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<ProfileQuery>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Json<Profile>, Error> {
+    let path = registry.resolve(&file)?;
+    let line = geojson::parse(&query.line)?;
+    let profile = workers::run(move || {
+        query_profile(
+            &path,
+            line,
+            query.samples,
+            query.crs.as_deref(),
+            query.bands.as_deref(),
+        )
+    })
+    .await?;
+    Ok(Json(profile))
+}
+
+pub async fn profile_post(
+    extract::Path(file): extract::Path<String>,
+    registry: Extension<Arc<Registry>>,
+    Json(request): Json<ProfileRequest>,
+) -> Result<Json<Profile>, Error> {
+    let path = registry.resolve(&file)?;
+    let profile = workers::run(move || {
+        query_profile(
+            &path,
+            request.line,
+            request.samples,
+            request.crs.as_deref(),
+            request.bands.as_deref(),
+        )
+    })
+    .await?;
+    Ok(Json(profile))
+}
@@ -0,0 +1,42 @@
+//! Removal of the cached files of the datasets that are gone, on a background thread, so that
+//! deregistering a dataset or deleting its file doesn't wait for the cache to be walked.
+
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+use crate::admin;
+use crate::registry::Registry;
+use crate::vrt;
+
+/// Removes the cached tiles and thumbnails of a dataset and the VRTs built for it, by the name
+/// it's cached under. Returns the number of files removed.
+fn prune(cache_name: &str) -> io::Result<usize> {
+    Ok(admin::purge_cache(Some(cache_name))? + vrt::remove(cache_name)?)
+}
+
+/// Prunes the caches of some datasets removed from a registry, skipping the ones registered
+/// again by the time it gets to them.
+pub fn schedule(registry: Arc<Registry>, names: Vec<String>) {
+    if names.is_empty() {
+        return;
+    }
+    let spawned = thread::Builder::new()
+        .name("prune".to_string())
+        .spawn(move || {
+            for name in names {
+                if registry.get(&name).is_ok() {
+                    continue;
+                }
+                match prune(&registry.cache_name(&name)) {
+                    Ok(removed) => {
+                        tracing::info!("removed {} cached files of dataset {}", removed, name)
+                    }
+                    Err(e) => tracing::warn!("cannot prune the cache of {}: {}", name, e),
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        tracing::warn!("cannot start pruning the cache: {}", e);
+    }
+}
@@ -0,0 +1,208 @@
+//! Tile quotas of the API keys, counted per day or month and kept across restarts.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::access;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    /// Returns the current period, like `2024-05-01` or `2024-05`, and the seconds until the
+    /// next one, in UTC.
+    fn current(self) -> (String, u64) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        let today = now / 86400;
+        let (year, month, day) = access::civil_from_days(today as i64);
+        let (label, next) = match self {
+            Period::Day => (format!("{:04}-{:02}-{:02}", year, month, day), today + 1),
+            Period::Month => {
+                let next_month = (today + 1..)
+                    .find(|&day| access::civil_from_days(day as i64).2 == 1)
+                    .expect("months end");
+                (format!("{:04}-{:02}", year, month), next_month)
+            }
+        };
+        (label, next * 86400 - now)
+    }
+}
+
+/// The tiles a key can be served in each period.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Quota {
+    pub period: Period,
+    /// Tiles after which the responses carry a warning and a message is logged.
+    pub soft: Option<u64>,
+    /// Tiles after which the requests are rejected until the next period.
+    pub hard: Option<u64>,
+}
+
+/// The tiles served to a key in a period.
+#[derive(Default, Deserialize, Serialize)]
+struct Usage {
+    period: String,
+    tiles: u64,
+}
+
+/// What a quota allows after serving some tiles.
+pub struct Remaining {
+    /// Tiles left before the hard limit, if there is one.
+    pub tiles: Option<u64>,
+    pub soft_exceeded: bool,
+}
+
+/// The usage of the keys with quotas, by key name.
+pub struct Quotas {
+    path: PathBuf,
+    usage: Mutex<BTreeMap<String, Usage>>,
+    changed: AtomicBool,
+}
+
+impl Quotas {
+    /// Loads the usage saved in a file, if it exists.
+    pub fn load(path: PathBuf) -> Self {
+        let usage = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            usage: Mutex::new(usage),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the seconds until the next period if the key reached its hard limit.
+    pub fn check(&self, name: &str, quota: &Quota) -> Option<u64> {
+        let hard = quota.hard?;
+        let (period, retry_after) = quota.period.current();
+        let usage = self.usage.lock().unwrap();
+        let used = usage
+            .get(name)
+            .filter(|usage| usage.period == period)
+            .map_or(0, |usage| usage.tiles);
+        Some(retry_after).filter(|_| used >= hard)
+    }
+
+    /// Counts the tiles served to a key, logging a warning when it goes over the soft limit.
+    pub fn add(&self, name: &str, quota: &Quota, tiles: u64) -> Remaining {
+        let (period, _) = quota.period.current();
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(name.to_string()).or_default();
+        if usage.period != period {
+            *usage = Usage { period, tiles: 0 };
+        }
+        let before = usage.tiles;
+        usage.tiles += tiles;
+        if tiles > 0 {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        let soft_exceeded = quota.soft.is_some_and(|soft| usage.tiles > soft);
+        if let Some(soft) = quota
+            .soft
+            .filter(|&soft| before <= soft && soft < usage.tiles)
+        {
+            tracing::warn!(
+                key = name,
+                period = %usage.period,
+                "API key went over its soft quota of {} tiles",
+                soft
+            );
+        }
+        Remaining {
+            tiles: quota.hard.map(|hard| hard.saturating_sub(usage.tiles)),
+            soft_exceeded,
+        }
+    }
+
+    /// Saves the usage if it changed since the last time.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(&*self.usage.lock().unwrap())?;
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, data)?;
+        std::fs::rename(temp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Period, Quota, Quotas};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("quota-test-{}-{}.json", std::process::id(), name))
+    }
+
+    #[test]
+    fn limits() {
+        let quotas = Quotas::load(temp_path("limits"));
+        let quota = Quota {
+            period: Period::Day,
+            soft: Some(5),
+            hard: Some(10),
+        };
+        assert_eq!(quotas.check("a", &quota), None);
+        let remaining = quotas.add("a", &quota, 5);
+        assert_eq!(remaining.tiles, Some(5));
+        assert!(!remaining.soft_exceeded);
+        let remaining = quotas.add("a", &quota, 1);
+        assert_eq!(remaining.tiles, Some(4));
+        assert!(remaining.soft_exceeded);
+        assert_eq!(quotas.check("a", &quota), None);
+        // the other keys are counted apart
+        assert_eq!(quotas.add("b", &quota, 1).tiles, Some(9));
+
+        let remaining = quotas.add("a", &quota, 10);
+        assert_eq!(remaining.tiles, Some(0));
+        let retry_after = quotas.check("a", &quota).unwrap();
+        assert!((1..=86400).contains(&retry_after));
+        assert_eq!(quotas.check("b", &quota), None);
+
+        let unlimited = Quota {
+            period: Period::Month,
+            soft: None,
+            hard: None,
+        };
+        let remaining = quotas.add("c", &unlimited, 1_000_000);
+        assert_eq!(remaining.tiles, None);
+        assert!(!remaining.soft_exceeded);
+        assert_eq!(quotas.check("c", &unlimited), None);
+    }
+
+    #[test]
+    fn persistence() {
+        let path = temp_path("persistence");
+        let quota = Quota {
+            period: Period::Month,
+            soft: None,
+            hard: Some(3),
+        };
+        let quotas = Quotas::load(path.clone());
+        quotas.add("a", &quota, 3);
+        quotas.save().unwrap();
+        let quotas = Quotas::load(path.clone());
+        assert!(quotas.check("a", &quota).is_some());
+
+        // the usage of past periods is reset
+        std::fs::write(&path, r#"{"a": {"period": "2000-01", "tiles": 100}}"#).unwrap();
+        let quotas = Quotas::load(path.clone());
+        assert_eq!(quotas.check("a", &quota), None);
+        assert_eq!(quotas.add("a", &quota, 1).tiles, Some(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use gdal::raster::RasterBand;
+use gdal::{Dataset, GeoTransform, Metadata};
+
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::icc::{self, ToSrgb};
+use crate::tile_grid::Extent;
+
+/// The properties of a band used when rendering it.
+#[derive(Clone)]
+pub struct Band {
+    pub no_data: Option<f64>,
+    pub scale: f64,
+    pub offset: f64,
+    pub unit: Option<String>,
+    /// The range of the physical values in the statistics of the bands that aren't 8-bit, used
+    /// to stretch them when the style doesn't give one.
+    pub stretch: Option<(f64, f64)>,
+}
+
+/// Reads the range of a band from the `STATISTICS_MINIMUM` and `STATISTICS_MAXIMUM` metadata,
+/// in physical values.
+fn stretch(rasterband: &RasterBand, scale: f64, offset: f64) -> Option<(f64, f64)> {
+    if rasterband.band_type() == gdal_sys::GDALDataType::GDT_Byte {
+        return None;
+    }
+    let statistic = |name| {
+        rasterband
+            .metadata_item(name, "")
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|value| value.is_finite())
+            .map(|value| value * scale + offset)
+    };
+    let (min, max) = (
+        statistic("STATISTICS_MINIMUM")?,
+        statistic("STATISTICS_MAXIMUM")?,
+    );
+    // negative scales swap them
+    let (min, max) = (min.min(max), min.max(max));
+    Some((min, max)).filter(|(min, max)| min < max)
+}
+
+/// The properties of a raster needed to render and describe it, which only change along with
+/// its file.
+pub struct RasterInfo {
+    pub geo_transform: GeoTransform,
+    pub extent: Extent,
+    /// The extent in WGS 84, unless the raster has no CRS.
+    pub extent_wgs84: Option<Extent>,
+    /// The WKT of the CRS of the raster, if it has one.
+    pub wkt: Option<String>,
+    pub bands: Vec<Band>,
+    /// Whether the bands are stored apart instead of pixel-interleaved.
+    pub band_interleaved: bool,
+    /// The conversion of the colours to sRGB, for the RGB rasters with a colour profile.
+    pub to_srgb: Option<ToSrgb>,
+}
+
+impl RasterInfo {
+    pub fn read(dataset: &Dataset) -> Result<Self, Error> {
+        let geo_transform = dataset::geo_transform(dataset)?;
+        let raster_size = dataset.raster_size();
+        let extent = dataset::image_extent(&geo_transform, raster_size);
+        let source_srs = dataset::spatial_ref(dataset).ok();
+        let extent_wgs84 = source_srs.as_ref().and_then(|source_srs| {
+            let transform = crs::transform(source_srs, &crs::wgs84().ok()?).ok()?;
+            dataset::reproject_extent(&extent, &transform).ok()
+        });
+        let wkt = source_srs.and_then(|source_srs| source_srs.to_wkt().ok());
+        let bands = (1..=dataset.raster_count())
+            .map(|band| {
+                let rasterband = dataset.rasterband(band)?;
+                let scale = rasterband.scale().unwrap_or(1.0);
+                let offset = rasterband.offset().unwrap_or(0.0);
+                Ok(Band {
+                    no_data: rasterband.no_data_value(),
+                    scale,
+                    offset,
+                    unit: dataset::band_unit(dataset, band),
+                    stretch: stretch(&rasterband, scale, offset),
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        let band_interleaved = dataset
+            .metadata_item("INTERLEAVE", "IMAGE_STRUCTURE")
+            .is_some_and(|interleave| interleave == "BAND");
+        let to_srgb = if dataset.raster_count() >= 3 {
+            icc::read(dataset)
+        } else {
+            None
+        };
+        Ok(Self {
+            band_interleaved,
+            to_srgb,
+            geo_transform,
+            extent,
+            extent_wgs84,
+            wkt,
+            bands,
+        })
+    }
+
+    /// Returns a band by its 1-based index.
+    pub fn band(&self, band: isize) -> Result<&Band, Error> {
+        Some(band)
+            .filter(|&band| band >= 1)
+            .and_then(|band| self.bands.get(band as usize - 1))
+            .ok_or_else(|| Error::BadRequest(format!("invalid band: {}", band)))
+    }
+
+    /// Returns the range covering the statistics of some bands, if all of them have one.
+    pub fn stretch(&self, bands: &[isize]) -> Option<(f64, f64)> {
+        bands
+            .iter()
+            .try_fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &band| {
+                let (band_min, band_max) = self.band(band).ok()?.stretch?;
+                Some((min.min(band_min), max.max(band_max)))
+            })
+    }
+}
+
+struct CachedInfo {
+    /// The modification time of the file when the properties were read.
+    modified: Option<SystemTime>,
+    info: Arc<RasterInfo>,
+}
+
+/// The properties of the datasets read so far.
+static CACHE: Mutex<BTreeMap<PathBuf, CachedInfo>> = Mutex::new(BTreeMap::new());
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Returns the properties of the dataset at `path`, reading them from `dataset` unless they're
+/// cached and its file didn't change since.
+///
+/// Remote datasets aren't checked for changes, so their properties are kept until `clear`.
+pub fn get(path: &Path, dataset: &Dataset) -> Result<Arc<RasterInfo>, Error> {
+    let modified = modified(path);
+    if let Some(cached) = CACHE.lock().unwrap().get(path) {
+        if cached.modified == modified {
+            return Ok(cached.info.clone());
+        }
+    }
+    let info = Arc::new(RasterInfo::read(dataset)?);
+    CACHE.lock().unwrap().insert(
+        path.to_path_buf(),
+        CachedInfo {
+            modified,
+            info: info.clone(),
+        },
+    );
+    Ok(info)
+}
+
+/// Drops the cached properties, after the datasets were reloaded.
+pub fn clear() {
+    CACHE.lock().unwrap().clear();
+}
+
+/// Drops the cached properties of a dataset whose file changed.
+pub fn forget(path: &Path) {
+    CACHE.lock().unwrap().remove(path);
+}
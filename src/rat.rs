@@ -0,0 +1,161 @@
+//! Raster attribute tables, which describe the classes of thematic rasters like land cover maps,
+//! with a row for each pixel value or range of values.
+
+use std::ffi::CStr;
+
+use gdal::Dataset;
+use gdal_sys::{GDALRATFieldType, GDALRATFieldUsage, GDALRasterAttributeTableH};
+use serde::Serialize;
+use serde_json::Value;
+
+/// The most rows returned, for the tables of continuous rasters, which can have one per bin.
+const MAX_ROWS: usize = 1024;
+
+/// The names of the `GDALRATFieldUsage` values.
+const USAGES: [&str; 18] = [
+    "generic",
+    "pixel_count",
+    "name",
+    "min",
+    "max",
+    "min_max",
+    "red",
+    "green",
+    "blue",
+    "alpha",
+    "red_min",
+    "green_min",
+    "blue_min",
+    "alpha_min",
+    "red_max",
+    "green_max",
+    "blue_max",
+    "alpha_max",
+];
+
+#[derive(Serialize)]
+pub struct Column {
+    name: String,
+    /// What the column holds, like `name`, `pixel_count` or `red`.
+    usage: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct AttributeTable {
+    columns: Vec<Column>,
+    /// The values of each row, in the order of the columns, up to the first 1024 rows.
+    rows: Vec<Vec<Value>>,
+    row_count: usize,
+    /// The value of the first row and the width of the value range of each row, for the tables
+    /// of continuous rasters, whose rows don't list their values.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linear_binning: Option<(f64, f64)>,
+}
+
+fn table(dataset: &Dataset, band: isize) -> Option<GDALRasterAttributeTableH> {
+    let table = unsafe {
+        let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _);
+        if c_band.is_null() {
+            return None;
+        }
+        gdal_sys::GDALGetDefaultRAT(c_band)
+    };
+    Some(table).filter(|table| !table.is_null())
+}
+
+fn string(table: GDALRasterAttributeTableH, row: usize, column: usize) -> String {
+    unsafe {
+        let value = gdal_sys::GDALRATGetValueAsString(table, row as _, column as _);
+        if value.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr(value).to_string_lossy().into_owned()
+    }
+}
+
+fn value(table: GDALRasterAttributeTableH, row: usize, column: usize) -> Value {
+    unsafe {
+        match gdal_sys::GDALRATGetTypeOfCol(table, column as _) {
+            GDALRATFieldType::GFT_Integer => {
+                Value::from(gdal_sys::GDALRATGetValueAsInt(table, row as _, column as _))
+            }
+            // `NaN` becomes `null`
+            GDALRATFieldType::GFT_Real => Value::from(gdal_sys::GDALRATGetValueAsDouble(
+                table,
+                row as _,
+                column as _,
+            )),
+            _ => Value::from(string(table, row, column)),
+        }
+    }
+}
+
+/// Reads the attribute table of a band, if it has one.
+pub fn read(dataset: &Dataset, band: isize) -> Option<AttributeTable> {
+    let table = table(dataset, band)?;
+    let (column_count, row_count) = unsafe {
+        (
+            gdal_sys::GDALRATGetColumnCount(table).max(0) as usize,
+            gdal_sys::GDALRATGetRowCount(table).max(0) as usize,
+        )
+    };
+    let columns = (0..column_count)
+        .map(|column| unsafe {
+            let name = gdal_sys::GDALRATGetNameOfCol(table, column as _);
+            let name = if name.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(name).to_string_lossy().into_owned()
+            };
+            let usage = gdal_sys::GDALRATGetUsageOfCol(table, column as _);
+            let kind = match gdal_sys::GDALRATGetTypeOfCol(table, column as _) {
+                GDALRATFieldType::GFT_Integer => "integer",
+                GDALRATFieldType::GFT_Real => "real",
+                _ => "string",
+            };
+            Column {
+                name,
+                usage: USAGES.get(usage as usize).copied().unwrap_or("generic"),
+                kind,
+            }
+        })
+        .collect();
+    let rows = (0..row_count.min(MAX_ROWS))
+        .map(|row| {
+            (0..column_count)
+                .map(|column| value(table, row, column))
+                .collect()
+        })
+        .collect();
+    let (mut first, mut size) = (0.0, 0.0);
+    let linear = unsafe { gdal_sys::GDALRATGetLinearBinning(table, &mut first, &mut size) } != 0;
+    Some(AttributeTable {
+        columns,
+        rows,
+        row_count,
+        linear_binning: Some((first, size)).filter(|_| linear),
+    })
+}
+
+/// Returns the name of the class of a pixel value in the attribute table of its band, from the
+/// column used for names, or the first text column if none is.
+pub fn class_name(dataset: &Dataset, band: isize, value: f64) -> Option<String> {
+    let table = table(dataset, band)?;
+    let (row, column) = unsafe {
+        let mut column = gdal_sys::GDALRATGetColOfUsage(table, GDALRATFieldUsage::GFU_Name);
+        if column < 0 {
+            column = (0..gdal_sys::GDALRATGetColumnCount(table))
+                .find(|&column| {
+                    gdal_sys::GDALRATGetTypeOfCol(table, column) == GDALRATFieldType::GFT_String
+                })
+                .unwrap_or(-1);
+        }
+        (gdal_sys::GDALRATGetRowOfValue(table, value), column)
+    };
+    if row < 0 || column < 0 {
+        return None;
+    }
+    Some(string(table, row as usize, column as usize)).filter(|name| !name.is_empty())
+}
@@ -0,0 +1,750 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+
+use crate::admin;
+use crate::config::{DatasetConfig, DatasetInfo, Pansharpen, RemoteConfig};
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::feather::Feather;
+use crate::pmtiles::PmTiles;
+use crate::remote::{self, Profile};
+#[cfg(feature = "scripts")]
+use crate::script::Script;
+use crate::stac::Mosaic;
+use crate::style::StyleQuery;
+use crate::vrt;
+use crate::wms::WmsSource;
+use crate::zarr;
+
+/// Optional file describing the datasets, keyed by name.
+const INFO_FILE: &str = "datasets.json";
+/// Optional file with the credentials used for remote datasets, keyed by profile name.
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Extensions of files that can contain several variables, each registered as a dataset.
+const MULTIDIMENSIONAL_EXTENSIONS: &[&str] = &["nc", "hdf"];
+
+/// File extensions considered to be datasets when scanning a directory.
+const EXTENSIONS: &[&str] = &[
+    "tif", "tiff", "vrt", "jp2", "img", "nc", "grib", "grb", "grb2", "hdf", "png", "jpg", "jpeg",
+    "webp", "mbtiles", "pmtiles", "gpkg", "zarr",
+];
+
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Lists the names of the datasets found in a directory, including Zarr stores, which are
+/// directories themselves.
+pub fn scan(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let is_dataset = if file_type.is_dir() {
+            zarr::is_zarr(&path)
+        } else {
+            file_type.is_file() && is_supported(&path)
+        };
+        if is_dataset {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Checks that a name can be used in URLs and cache file names.
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    {
+        return Err(Error::BadRequest(format!("invalid dataset name: {}", name)));
+    }
+    Ok(())
+}
+
+/// How the tiles of a dataset are produced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A raster rendered through GDAL.
+    Raster,
+    /// Pre-rendered tiles served as-is from an MBTiles archive.
+    MbTiles,
+    /// Pre-rendered tiles served as-is from a local or remote PMTiles archive.
+    PmTiles,
+    /// A GeoPackage tile pyramid, served as-is when it matches the tile grid.
+    GeoPackage,
+    /// A mosaic of the items found by a STAC API search, rendered through GDAL.
+    Stac,
+    /// Tiles requested from a remote WMS and cached.
+    Wms,
+}
+
+impl Kind {
+    pub fn from_path(path: &Path) -> Self {
+        let path_str = path.to_string_lossy();
+        if path_str.starts_with("PG:") || path_str.starts_with("ZARR:") {
+            return Kind::Raster;
+        }
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("mbtiles") => Kind::MbTiles,
+            Some(extension) if extension.eq_ignore_ascii_case("pmtiles") => Kind::PmTiles,
+            Some(extension) if extension.eq_ignore_ascii_case("gpkg") => Kind::GeoPackage,
+            _ => Kind::Raster,
+        }
+    }
+}
+
+/// A registered dataset.
+#[derive(Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub kind: Kind,
+    pub info: DatasetInfo,
+    /// Styling parameters used when requests don't pass them.
+    pub style: StyleQuery,
+    /// The custom renderer of the dataset, if any.
+    pub renderer: Option<String>,
+    /// The script applied to the values read, with the `scripts` feature.
+    #[cfg(feature = "scripts")]
+    pub script: Option<Arc<Script>>,
+    /// For the variables of multidimensional files, the name of the file they were found in.
+    pub parent: Option<String>,
+    pub mosaic: Option<Arc<Mosaic>>,
+    pub wms: Option<Arc<WmsSource>>,
+    /// The pan-sharpened VRT of the dataset, if it has a panchromatic band.
+    pub pansharpened: Option<PathBuf>,
+    /// For mosaics and groups, the blending of their overlapping rasters.
+    pub feather: Option<Arc<Feather>>,
+    /// The PMTiles archive, once opened.
+    archive: Arc<Mutex<Option<Arc<PmTiles>>>>,
+}
+
+impl Entry {
+    /// Returns the path as passed to GDAL, which might be a URL or a `/vsi` path.
+    pub fn location(&self) -> Result<&str, Error> {
+        self.path
+            .to_str()
+            .ok_or_else(|| Error::BadRequest(format!("invalid path: {}", self.path.display())))
+    }
+
+    /// Returns the PMTiles archive of the dataset, opening it on first use, so that its header
+    /// and root directory aren't read again for each tile.
+    pub fn pmtiles(&self) -> Result<Arc<PmTiles>, Error> {
+        let mut archive = self.archive.lock().unwrap();
+        if let Some(archive) = &*archive {
+            return Ok(archive.clone());
+        }
+        let opened = Arc::new(PmTiles::open(self.location()?)?);
+        *archive = Some(opened.clone());
+        Ok(opened)
+    }
+
+    /// Closes the PMTiles archive after its file changed, so that it's opened again.
+    pub fn forget_archive(&self) {
+        self.archive.lock().unwrap().take();
+    }
+
+    /// Returns a suffix identifying the script in cache keys, empty without one.
+    pub fn script_cache_key(&self) -> String {
+        #[cfg(feature = "scripts")]
+        if let Some(script) = &self.script {
+            return script.cache_key();
+        }
+        String::new()
+    }
+
+    /// Returns the path to render a style from, which is the pan-sharpened VRT with
+    /// `pansharpen=true`.
+    pub fn styled_path(&self, style: &StyleQuery) -> Result<&Path, Error> {
+        match style.pansharpen.as_deref() {
+            None | Some("false") => Ok(&self.path),
+            Some("true") => self.pansharpened.as_deref().ok_or_else(|| {
+                Error::BadRequest("the dataset has no panchromatic band".to_string())
+            }),
+            Some(value) => Err(Error::BadRequest(format!(
+                "pansharpen must be true or false, not {}",
+                value
+            ))),
+        }
+    }
+
+    pub fn new(path: PathBuf, info: DatasetInfo) -> Self {
+        Self {
+            kind: Kind::from_path(&path),
+            path,
+            info,
+            style: StyleQuery::default(),
+            renderer: None,
+            #[cfg(feature = "scripts")]
+            script: None,
+            parent: None,
+            mosaic: None,
+            wms: None,
+            pansharpened: None,
+            feather: None,
+            archive: Arc::default(),
+        }
+    }
+
+    /// Builds the entry of a STAC mosaic, using the search URL as its path.
+    pub fn mosaic(mosaic: Mosaic, info: DatasetInfo) -> Self {
+        Self {
+            path: PathBuf::from(&mosaic.search().url),
+            kind: Kind::Stac,
+            info,
+            style: StyleQuery::default(),
+            renderer: None,
+            #[cfg(feature = "scripts")]
+            script: None,
+            parent: None,
+            mosaic: Some(Arc::new(mosaic)),
+            wms: None,
+            pansharpened: None,
+            feather: None,
+            archive: Arc::default(),
+        }
+    }
+
+    /// Builds the entry of a cascaded WMS layer, using the service URL as its path.
+    pub fn wms(wms: WmsSource, info: DatasetInfo) -> Self {
+        Self {
+            path: PathBuf::from(&wms.url),
+            kind: Kind::Wms,
+            info,
+            style: StyleQuery::default(),
+            renderer: None,
+            #[cfg(feature = "scripts")]
+            script: None,
+            parent: None,
+            mosaic: None,
+            wms: Some(Arc::new(wms)),
+            pansharpened: None,
+            feather: None,
+            archive: Arc::default(),
+        }
+    }
+}
+
+fn feather(width: Option<f64>, sources: Vec<PathBuf>) -> Result<Option<Arc<Feather>>, Error> {
+    width
+        .map(|width| Ok(Arc::new(Feather::new(width, sources)?)))
+        .transpose()
+}
+
+/// Compiles the script of a dataset, which is rejected without the `scripts` feature.
+#[cfg_attr(not(feature = "scripts"), allow(unused_variables))]
+fn set_script(entry: &mut Entry, script: Option<&str>) -> Result<(), Error> {
+    #[cfg(feature = "scripts")]
+    {
+        entry.script = script.map(Script::parse).transpose()?.map(Arc::new);
+    }
+    #[cfg(not(feature = "scripts"))]
+    if script.is_some() {
+        return Err(Error::BadRequest(
+            "scripts need the `scripts` feature".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Makes a raster dataset open with the given CRS, if any, instead of the one in its metadata,
+/// or in pixel space when `flat` is set.
+fn apply_overrides(entry: &Entry, srs_override: Option<String>, flat: bool) -> Result<(), Error> {
+    if let Some(srs) = &srs_override {
+        if entry.kind != Kind::Raster {
+            return Err(Error::BadRequest(
+                "the CRS can only be overridden for rasters".to_string(),
+            ));
+        }
+        if flat {
+            return Err(Error::BadRequest(
+                "flat rasters can't have a CRS override".to_string(),
+            ));
+        }
+        crs::parse_srs(srs)?;
+    }
+    if flat && entry.kind != Kind::Raster {
+        return Err(Error::BadRequest(
+            "only rasters can be served in pixel space".to_string(),
+        ));
+    }
+    dataset::set_srs_override(&entry.path, srs_override);
+    dataset::set_flat(&entry.path, flat);
+    Ok(())
+}
+
+/// What a scan of the data directory found.
+#[derive(Default)]
+pub struct Changes {
+    pub added: Vec<String>,
+    /// Datasets whose files are gone, with their paths.
+    pub removed: Vec<(String, PathBuf)>,
+    /// Datasets whose files were modified or replaced, with their paths.
+    pub modified: Vec<(String, PathBuf)>,
+}
+
+/// Maps the dataset names used in URLs to the files they are read from.
+pub struct Registry {
+    dir: PathBuf,
+    remote: RemoteConfig,
+    profiles: RwLock<BTreeMap<String, Profile>>,
+    datasets: RwLock<BTreeMap<String, Entry>>,
+    /// The modification times of the local dataset files, as of the last scan.
+    modified: Mutex<BTreeMap<PathBuf, SystemTime>>,
+    /// The tenant the datasets belong to, if any, whose name prefixes their cache entries.
+    tenant: Option<String>,
+}
+
+impl Registry {
+    pub fn new(dir: PathBuf, remote: RemoteConfig) -> io::Result<Self> {
+        Self::with_tenant(dir, remote, None)
+    }
+
+    /// Builds the registry of the datasets of a tenant, or of the server without one.
+    pub fn with_tenant(
+        dir: PathBuf,
+        remote: RemoteConfig,
+        tenant: Option<String>,
+    ) -> io::Result<Self> {
+        let registry = Self {
+            dir,
+            remote,
+            profiles: RwLock::new(BTreeMap::new()),
+            datasets: RwLock::new(BTreeMap::new()),
+            modified: Mutex::new(BTreeMap::new()),
+            tenant,
+        };
+        registry.reload()?;
+        // so that the files changed before the first scan are noticed
+        registry.modified_files(SystemTime::now());
+        Ok(registry)
+    }
+
+    /// Returns the name the cache entries of a dataset start with, which is prefixed by the tenant
+    /// so that the tenants can have datasets with the same name.
+    pub fn cache_name(&self, name: &str) -> String {
+        match &self.tenant {
+            // not allowed in dataset names
+            Some(tenant) => format!("{}~{}", tenant, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Returns the path the data routes of the datasets are nested under, empty without a tenant.
+    pub fn url_prefix(&self) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("/t/{}", tenant),
+            None => String::new(),
+        }
+    }
+
+    /// Reads a JSON file from the data directory, if present.
+    fn read_json<T: DeserializeOwned + Default>(&self, file_name: &str) -> io::Result<T> {
+        let path = self.dir.join(file_name);
+        if !path.exists() {
+            return Ok(T::default());
+        }
+        let json = std::fs::read(&path)?;
+        serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replaces the registered datasets with the ones found in the data directory, dropping
+    /// those added at runtime. Returns the number of datasets found.
+    pub fn reload(&self) -> io::Result<usize> {
+        *self.profiles.write().unwrap() = self.read_json(PROFILES_FILE)?;
+        let mut config: BTreeMap<String, DatasetConfig> = self.read_json(INFO_FILE)?;
+        let mut datasets = BTreeMap::new();
+        for name in scan(&self.dir)? {
+            let config = config.remove(&name).unwrap_or_default();
+            match self.file_entries(&name, config) {
+                Ok(entries) => datasets.extend(entries),
+                Err(e) => tracing::warn!("skipping dataset {}: {}", name, e),
+            }
+        }
+        let mut groups = Vec::new();
+        for (name, config) in config {
+            if !config.has_source() {
+                tracing::warn!("{} describes missing dataset {}", INFO_FILE, name);
+                continue;
+            }
+            if config.group.is_some() {
+                // groups are built once their members are known
+                groups.push((name, config));
+                continue;
+            }
+            match validate_name(&name).and_then(|_| self.entry(&name, config)) {
+                Ok(entry) => {
+                    datasets.insert(name, entry);
+                }
+                Err(e) => tracing::warn!("skipping dataset {}: {}", name, e),
+            }
+        }
+        for (name, config) in groups {
+            let entry = validate_name(&name).and_then(|_| {
+                let srs_override = config.srs_override.clone();
+                let members = config.group.unwrap_or_default();
+                let mut entry =
+                    self.group_entry(&name, &members, &datasets, config.feather, config.info)?;
+                entry.style = config.style.unwrap_or_default();
+                entry.renderer = config.renderer;
+                set_script(&mut entry, config.script.as_deref())?;
+                apply_overrides(&entry, srs_override, config.flat)?;
+                self.apply_pansharpen(&name, &mut entry, config.pansharpen.as_ref())?;
+                Ok(entry)
+            });
+            match entry {
+                Ok(entry) => {
+                    datasets.insert(name, entry);
+                }
+                Err(e) => tracing::warn!("skipping dataset group {}: {}", name, e),
+            }
+        }
+        let count = datasets.len();
+        *self.datasets.write().unwrap() = datasets;
+        Ok(count)
+    }
+
+    /// Registers the files that appeared in the data directory since the last scan and drops the
+    /// ones that disappeared, leaving the other datasets alone. Files modified after `settled`
+    /// might still be being written, so they are left for a later scan.
+    ///
+    /// The datasets read from local files that changed since the last scan are returned too, so
+    /// that their handles and cached tiles can be dropped.
+    pub fn sync(&self, settled: SystemTime) -> io::Result<Changes> {
+        let names = scan(&self.dir)?;
+        let mut removed = Vec::new();
+        self.datasets.write().unwrap().retain(|name, entry| {
+            let keep = match &entry.parent {
+                Some(parent) => self.dir.join(parent).exists(),
+                None => entry.path != self.dir.join(name) || entry.path.exists(),
+            };
+            if !keep {
+                removed.push((name.clone(), entry.path.clone()));
+            }
+            keep
+        });
+        let mut config: BTreeMap<String, DatasetConfig> = self.read_json(INFO_FILE)?;
+        let mut added = Vec::new();
+        for name in names {
+            let known = {
+                let datasets = self.datasets.read().unwrap();
+                datasets.contains_key(&name)
+                    || datasets
+                        .values()
+                        .any(|entry| entry.parent.as_deref() == Some(name.as_str()))
+            };
+            if known || self.dir.join(&name).metadata()?.modified()? > settled {
+                continue;
+            }
+            let config = config.remove(&name).unwrap_or_default();
+            match self.file_entries(&name, config) {
+                Ok(entries) => {
+                    for (name, entry) in entries {
+                        self.insert(name.clone(), entry);
+                        added.push(name);
+                    }
+                }
+                Err(e) => tracing::warn!("skipping dataset {}: {}", name, e),
+            }
+        }
+        let modified = self.modified_files(settled);
+        Ok(Changes {
+            added,
+            removed,
+            modified,
+        })
+    }
+
+    /// Compares the modification times of the local files of the datasets with the ones seen in
+    /// the last scan. Remote datasets aren't checked.
+    fn modified_files(&self, settled: SystemTime) -> Vec<(String, PathBuf)> {
+        let datasets = self.datasets.read().unwrap();
+        let mut times = self.modified.lock().unwrap();
+        let mut checked = BTreeMap::new();
+        let mut modified = Vec::new();
+        for (name, entry) in datasets.iter() {
+            let time = *checked.entry(&entry.path).or_insert_with(|| {
+                entry
+                    .path
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            });
+            // files still being written are compared once they settle
+            let time = match time {
+                Some(time) if time <= settled => time,
+                _ => continue,
+            };
+            match times.insert(entry.path.clone(), time) {
+                Some(previous) if previous != time => {
+                    modified.push((name.clone(), entry.path.clone()))
+                }
+                _ => {}
+            }
+        }
+        let paths = checked.into_keys().collect::<BTreeSet<_>>();
+        times.retain(|path, _| paths.contains(path));
+        modified
+    }
+
+    /// Builds the entries of a file found in the data directory: one for each variable of
+    /// multidimensional files like NetCDF with several of them, named like `file.nc.variable`,
+    /// or a single one otherwise.
+    fn file_entries(
+        &self,
+        name: &str,
+        mut config: DatasetConfig,
+    ) -> Result<Vec<(String, Entry)>, Error> {
+        let path = config
+            .path
+            .get_or_insert_with(|| self.dir.join(name))
+            .clone();
+        let multidimensional = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                MULTIDIMENSIONAL_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+            });
+        if !multidimensional || config.array.variable.is_some() || config.sentinel2.is_some() {
+            return Ok(vec![(name.to_string(), self.entry(name, config)?)]);
+        }
+        let dataset = dataset::open(&path)?;
+        let subdatasets = dataset::subdatasets(&dataset);
+        if dataset.raster_count() > 0 || subdatasets.is_empty() {
+            return Ok(vec![(name.to_string(), self.entry(name, config)?)]);
+        }
+        let default_style = config.style.clone().unwrap_or_default();
+        let mut entries = Vec::new();
+        for (subdataset, _) in subdatasets {
+            // like `NETCDF:"file.nc":t2m`
+            let variable = subdataset
+                .rsplit(':')
+                .next()
+                .unwrap_or_default()
+                .trim_matches(['"', '/']);
+            let layer_name = variable
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>();
+            let layer_name = format!("{}.{}", name, layer_name);
+            let variable_config = config.variables.get(variable).cloned().unwrap_or_default();
+            let mut entry = Entry::new(PathBuf::from(subdataset.as_str()), variable_config.info);
+            entry.style = variable_config
+                .style
+                .unwrap_or_default()
+                .with_defaults(&default_style);
+            entry.renderer = config.renderer.clone();
+            set_script(&mut entry, config.script.as_deref())?;
+            entry.parent = Some(name.to_string());
+            apply_overrides(&entry, config.srs_override.clone(), config.flat)?;
+            entries.push((layer_name, entry));
+        }
+        Ok(entries)
+    }
+
+    /// Builds an entry, mapping remote paths to the GDAL ones, selecting the array to read
+    /// from multidimensional datasets and building the VRT mosaics of directories.
+    pub fn entry(&self, name: &str, config: DatasetConfig) -> Result<Entry, Error> {
+        let (srs_override, flat) = (config.srs_override.clone(), config.flat);
+        let pansharpen = config.pansharpen.clone();
+        let feathered = config.feather.is_some();
+        let style = config.style.clone().unwrap_or_default();
+        let renderer = config.renderer.clone();
+        let script = config.script.clone();
+        let mut entry = self.source_entry(name, config)?;
+        if feathered && entry.feather.is_none() {
+            return Err(Error::BadRequest(
+                "only mosaics and groups can be feathered".to_string(),
+            ));
+        }
+        entry.style = style.with_defaults(&entry.style);
+        entry.renderer = renderer;
+        set_script(&mut entry, script.as_deref())?;
+        apply_overrides(&entry, srs_override, flat)?;
+        self.apply_pansharpen(name, &mut entry, pansharpen.as_ref())?;
+        Ok(entry)
+    }
+
+    /// Writes the pan-sharpened VRT of a raster dataset with a panchromatic band.
+    fn apply_pansharpen(
+        &self,
+        name: &str,
+        entry: &mut Entry,
+        pansharpen: Option<&Pansharpen>,
+    ) -> Result<(), Error> {
+        let pansharpen = match pansharpen {
+            Some(pansharpen) => pansharpen,
+            None => return Ok(()),
+        };
+        if entry.kind != Kind::Raster || entry.parent.is_some() {
+            return Err(Error::BadRequest(
+                "only rasters can be pan-sharpened".to_string(),
+            ));
+        }
+        let panchromatic = self.dir.join(&pansharpen.panchromatic);
+        let (path, rebuilt) = vrt::pansharpen(
+            &self.cache_name(name),
+            &entry.path,
+            &panchromatic,
+            pansharpen,
+        )?;
+        remote::alias_path_options(&entry.path, &path);
+        if rebuilt {
+            admin::purge_cache(Some(&self.cache_name(name)))?;
+        }
+        entry.pansharpened = Some(path);
+        Ok(())
+    }
+
+    fn source_entry(&self, name: &str, config: DatasetConfig) -> Result<Entry, Error> {
+        let profiles = self.profiles.read().unwrap();
+        let profile = match &config.profile {
+            Some(name) => Some(
+                profiles
+                    .get(name)
+                    .ok_or_else(|| Error::BadRequest(format!("unknown profile: {}", name)))?,
+            ),
+            None => None,
+        };
+        if let Some(members) = &config.group {
+            let datasets = self.datasets.read().unwrap();
+            return self.group_entry(name, members, &datasets, config.feather, config.info);
+        }
+        if let Some(search) = config.stac {
+            let mosaic = Mosaic::new(search, self.remote.clone(), profile.cloned())?;
+            return Ok(Entry::mosaic(mosaic, config.info));
+        }
+        if let Some(wms) = config.wms {
+            remote::check_allowed(&wms.url, &self.remote)?;
+            return Ok(Entry::wms(wms, config.info));
+        }
+        let pattern = match (&config.glob, &config.path) {
+            (Some(glob), _) => Some(self.dir.join(glob)),
+            (None, Some(path)) if path.is_dir() && !zarr::is_zarr(path) => Some(path.clone()),
+            _ => None,
+        };
+        if let Some(pattern) = pattern {
+            let (path, rebuilt, sources) =
+                vrt::mosaic(&self.cache_name(name), &pattern.to_string_lossy())?;
+            if rebuilt {
+                admin::purge_cache(Some(&self.cache_name(name)))?;
+            }
+            let mut entry = Entry::new(path, config.info);
+            entry.feather = feather(config.feather, sources)?;
+            return Ok(entry);
+        }
+        let path = config
+            .path
+            .ok_or_else(|| Error::BadRequest("missing dataset path".to_string()))?;
+        let path = remote::gdal_path(&path, &self.remote, profile)?;
+        if let Some(preset) = &config.sentinel2 {
+            let (subdataset, style) = remote::with_path_options(&path, || preset.open(&path))?;
+            remote::alias_path_options(&path, &subdataset);
+            let mut entry = Entry::new(subdataset, config.info);
+            entry.style = style;
+            return Ok(entry);
+        }
+        let array = &config.array;
+        let path = match &array.variable {
+            Some(variable) if zarr::is_zarr(&path) => {
+                let array_path = zarr::array_path(&path, variable, &array.indices);
+                remote::alias_path_options(&path, &array_path);
+                array_path
+            }
+            Some(_) => {
+                return Err(Error::BadRequest(format!(
+                    "variables are only supported for Zarr stores: {}",
+                    path.display()
+                )))
+            }
+            None => path,
+        };
+        Ok(Entry::new(path, config.info))
+    }
+
+    /// Builds the entry of a group, a VRT mosaic of other raster datasets.
+    fn group_entry(
+        &self,
+        name: &str,
+        members: &[String],
+        datasets: &BTreeMap<String, Entry>,
+        feather_width: Option<f64>,
+        info: DatasetInfo,
+    ) -> Result<Entry, Error> {
+        if members.is_empty() {
+            return Err(Error::BadRequest(format!("empty dataset group: {}", name)));
+        }
+        let sources = members
+            .iter()
+            .map(|member| match datasets.get(member) {
+                Some(entry) if entry.kind == Kind::Raster => Ok(entry.path.clone()),
+                Some(_) => Err(Error::BadRequest(format!(
+                    "only raster datasets can be grouped: {}",
+                    member
+                ))),
+                None => Err(Error::UnknownDataset(member.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (path, rebuilt) = vrt::build(&self.cache_name(name), &sources)?;
+        remote::alias_path_options(&sources[0], &path);
+        if rebuilt {
+            admin::purge_cache(Some(&self.cache_name(name)))?;
+        }
+        let mut entry = Entry::new(path, info);
+        entry.feather = feather(feather_width, sources)?;
+        Ok(entry)
+    }
+
+    pub fn insert(&self, name: String, entry: Entry) -> Option<Entry> {
+        self.datasets.write().unwrap().insert(name, entry)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Entry> {
+        self.datasets.write().unwrap().remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Entry, Error> {
+        self.datasets
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownDataset(name.to_string()))
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, Error> {
+        self.get(name).map(|entry| entry.path)
+    }
+
+    pub fn entries(&self) -> Vec<(String, Entry)> {
+        self.datasets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect()
+    }
+}
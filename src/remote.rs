@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::RwLock;
+
+use gdal::config;
+use serde::Deserialize;
+
+use crate::config::RemoteConfig;
+use crate::error::Error;
+
+/// Applies the HTTP settings to GDAL.
+pub fn configure(remote: &RemoteConfig) -> Result<(), Error> {
+    // don't list the parent "directory" of every file opened, looking for sidecar files
+    config::set_config_option("GDAL_DISABLE_READDIR_ON_OPEN", "EMPTY_DIR")?;
+    config::set_config_option("GDAL_HTTP_MAX_RETRY", &remote.max_retry.to_string())?;
+    config::set_config_option("GDAL_HTTP_RETRY_DELAY", &remote.retry_delay.to_string())?;
+    if let Some(cache_size) = remote.cache_size {
+        config::set_config_option("CPL_VSIL_CURL_CACHE_SIZE", &cache_size.to_string())?;
+    }
+    Ok(())
+}
+
+fn host(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let authority = rest.split(['/', '?']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    Some(host).filter(|host| !host.is_empty())
+}
+
+fn is_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed_hosts.iter().any(|allowed| {
+        let allowed = allowed.to_ascii_lowercase();
+        match allowed.strip_prefix("*.") {
+            Some(suffix) => host
+                .strip_suffix(suffix)
+                .is_some_and(|prefix| prefix.ends_with('.')),
+            None => host == allowed,
+        }
+    })
+}
+
+/// Checks that an `http(s)://` URL points to an allowed host.
+pub fn check_allowed(url: &str, remote: &RemoteConfig) -> Result<(), Error> {
+    match host(url) {
+        Some(host) if is_allowed(host, &remote.allowed_hosts) => Ok(()),
+        _ => Err(Error::BadRequest(format!("host not allowed: {}", url))),
+    }
+}
+
+/// Percent-encodes a URL query parameter value.
+pub fn encode_component(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Downloads a document, like a STAC API response, from an allowed host.
+pub fn read_url(url: &str, remote: &RemoteConfig) -> Result<Vec<u8>, Error> {
+    check_allowed(url, remote)?;
+    // the streaming variant doesn't expect the server to support range requests
+    let path = CString::new(format!("/vsicurl_streaming/{}", url))?;
+    let mut data = ptr::null_mut();
+    let mut size = 0;
+    let ok = unsafe {
+        gdal_sys::VSIIngestFile(ptr::null_mut(), path.as_ptr(), &mut data, &mut size, -1)
+    };
+    if ok == 0 {
+        return Err(Error::last_cpl_error(gdal_sys::CPLErr::CE_Failure));
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(data, size as usize) }.to_vec();
+    unsafe { gdal_sys::VSIFree(data as *mut _) };
+    Ok(bytes)
+}
+
+/// Maps `http(s)://` dataset paths to `/vsicurl/` ones, checking them against the allowlist,
+/// and `s3://`, `az://` and `gs://` ones to `/vsis3/`, `/vsiaz/` and `/vsigs/`. Other paths
+/// are returned unchanged.
+pub fn gdal_path(
+    path: &Path,
+    remote: &RemoteConfig,
+    profile: Option<&Profile>,
+) -> Result<PathBuf, Error> {
+    if let Some(path) = object_store_path(path, profile) {
+        return path;
+    }
+    let url = match path.to_str() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => url,
+        _ => return Ok(path.to_path_buf()),
+    };
+    check_allowed(url, remote)?;
+    Ok(PathBuf::from(format!("/vsicurl/{}", url)))
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "YES" } else { "NO" }.to_string()
+}
+
+fn collect_options(options: &[(&'static str, Option<String>)]) -> Vec<(&'static str, String)> {
+    options
+        .iter()
+        .cloned()
+        .filter_map(|(key, value)| value.map(|value| (key, value)))
+        .collect()
+}
+
+/// Credentials and endpoint of an S3 or S3-compatible service.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct S3Profile {
+    region: Option<String>,
+    /// Host and port of an S3-compatible service like MinIO.
+    endpoint: Option<String>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    https: Option<bool>,
+    virtual_hosting: Option<bool>,
+    /// Access public buckets without credentials.
+    no_sign_request: Option<bool>,
+}
+
+/// Credentials of an Azure Blob Storage account.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AzureProfile {
+    connection_string: Option<String>,
+    account: Option<String>,
+    access_key: Option<String>,
+    sas_token: Option<String>,
+    no_sign_request: Option<bool>,
+}
+
+/// Credentials of a Google Cloud Storage account.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct GcsProfile {
+    /// Path to a service account JSON key.
+    application_credentials: Option<String>,
+    /// HMAC keys.
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    oauth2_refresh_token: Option<String>,
+    /// Project billed for requester-pays buckets.
+    user_project: Option<String>,
+    no_sign_request: Option<bool>,
+}
+
+/// Credentials for one of the supported object storage services.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Profile {
+    S3(S3Profile),
+    Azure(AzureProfile),
+    Gcs(GcsProfile),
+}
+
+impl Profile {
+    fn options(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Profile::S3(p) => collect_options(&[
+                ("AWS_REGION", p.region.clone()),
+                ("AWS_S3_ENDPOINT", p.endpoint.clone()),
+                ("AWS_ACCESS_KEY_ID", p.access_key_id.clone()),
+                ("AWS_SECRET_ACCESS_KEY", p.secret_access_key.clone()),
+                ("AWS_SESSION_TOKEN", p.session_token.clone()),
+                ("AWS_HTTPS", p.https.map(yes_no)),
+                ("AWS_VIRTUAL_HOSTING", p.virtual_hosting.map(yes_no)),
+                ("AWS_NO_SIGN_REQUEST", p.no_sign_request.map(yes_no)),
+            ]),
+            Profile::Azure(p) => collect_options(&[
+                (
+                    "AZURE_STORAGE_CONNECTION_STRING",
+                    p.connection_string.clone(),
+                ),
+                ("AZURE_STORAGE_ACCOUNT", p.account.clone()),
+                ("AZURE_STORAGE_ACCESS_KEY", p.access_key.clone()),
+                ("AZURE_STORAGE_SAS_TOKEN", p.sas_token.clone()),
+                // the name used before GDAL 3.5
+                ("AZURE_SAS", p.sas_token.clone()),
+                ("AZURE_NO_SIGN_REQUEST", p.no_sign_request.map(yes_no)),
+            ]),
+            Profile::Gcs(p) => collect_options(&[
+                (
+                    "GOOGLE_APPLICATION_CREDENTIALS",
+                    p.application_credentials.clone(),
+                ),
+                ("GS_ACCESS_KEY_ID", p.access_key_id.clone()),
+                ("GS_SECRET_ACCESS_KEY", p.secret_access_key.clone()),
+                ("GS_OAUTH2_REFRESH_TOKEN", p.oauth2_refresh_token.clone()),
+                ("GS_USER_PROJECT", p.user_project.clone()),
+                ("GS_NO_SIGN_REQUEST", p.no_sign_request.map(yes_no)),
+            ]),
+        }
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            Profile::S3(_) => "/vsis3/",
+            Profile::Azure(_) => "/vsiaz/",
+            Profile::Gcs(_) => "/vsigs/",
+        }
+    }
+}
+
+/// GDAL configuration options to use when opening each remote dataset.
+static PATH_OPTIONS: RwLock<BTreeMap<PathBuf, Vec<(&'static str, String)>>> =
+    RwLock::new(BTreeMap::new());
+
+/// URL schemes of object storage paths and the GDAL file systems they map to.
+const OBJECT_STORES: &[(&str, &str)] = &[
+    ("s3://", "/vsis3/"),
+    ("az://", "/vsiaz/"),
+    ("gs://", "/vsigs/"),
+];
+
+/// Maps an object storage path like `s3://bucket/key` to a GDAL one like `/vsis3/bucket/key`,
+/// using the credentials of a profile. Returns `None` for other paths.
+fn object_store_path(path: &Path, profile: Option<&Profile>) -> Option<Result<PathBuf, Error>> {
+    let path = path.to_str()?;
+    let path = OBJECT_STORES.iter().find_map(|(scheme, prefix)| {
+        path.strip_prefix(scheme)
+            .map(|key| format!("{}{}", prefix, key))
+    })?;
+    if let Some(profile) = profile {
+        if !path.starts_with(profile.prefix()) {
+            return Some(Err(Error::BadRequest(format!(
+                "profile cannot be used for {}",
+                path
+            ))));
+        }
+        PATH_OPTIONS
+            .write()
+            .unwrap()
+            .insert(PathBuf::from(&path), profile.options());
+    }
+    Some(Ok(PathBuf::from(path)))
+}
+
+/// Makes a path derived from a remote one, like a subdataset, use the same configuration options.
+pub fn alias_path_options(path: &Path, alias: &Path) {
+    let mut path_options = PATH_OPTIONS.write().unwrap();
+    if let Some(options) = path_options.get(path).cloned() {
+        path_options.insert(alias.to_path_buf(), options);
+    }
+}
+
+/// Runs `f` with the configuration options of a remote dataset set on the current thread.
+///
+/// GDAL reads them when the dataset is opened, so they only need to be set then.
+pub fn with_path_options<T>(path: &Path, f: impl FnOnce() -> T) -> T {
+    let options = PATH_OPTIONS.read().unwrap().get(path).cloned();
+    let options = match options {
+        Some(options) => options,
+        None => return f(),
+    };
+    for (key, value) in &options {
+        let _ = config::set_thread_local_config_option(key, value);
+    }
+    let result = f();
+    for (key, _) in &options {
+        let _ = config::clear_thread_local_config_option(key);
+    }
+    result
+}
@@ -0,0 +1,463 @@
+use std::io;
+use std::mem;
+use std::os::raw::c_int;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::Instant;
+
+use gdal::raster::{Buffer, RasterCreationOption};
+use gdal::{vsi, Dataset, Driver};
+
+use crate::bench;
+use crate::canvas::{BufferPool, Canvas};
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::raster_info::RasterInfo;
+use crate::renderer::{SourceBand, TileData, TileRenderer};
+use crate::slow;
+use crate::style::Style;
+use crate::tile_grid::Extent;
+use crate::workers;
+
+/// Runs a stage of rendering a tile in its own span, logging how long it took.
+pub fn stage<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    let span = tracing::debug_span!("stage", name, elapsed_us = tracing::field::Empty);
+    let _enter = span.enter();
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    bench::record(name, elapsed);
+    slow::record_stage(name, elapsed);
+    let elapsed_us = elapsed.as_micros() as u64;
+    span.record("elapsed_us", &elapsed_us);
+    tracing::debug!(elapsed_us, "finished {}", name);
+    result
+}
+
+static RENDERING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a tile as being rendered until it's dropped.
+pub struct Rendering(());
+
+impl Rendering {
+    pub fn start() -> Self {
+        RENDERING.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for Rendering {
+    fn drop(&mut self) {
+        RENDERING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the number of tiles being rendered.
+pub fn in_flight() -> usize {
+    RENDERING.load(Ordering::Relaxed)
+}
+
+pub static VALUES: BufferPool<f64> = BufferPool::new();
+pub static CHANNELS: BufferPool<u8> = BufferPool::new();
+
+/// Reads a window of some bands in a single call into a pixel-interleaved buffer, so that the
+/// drivers decode each block once for all of them instead of once per band.
+pub fn read_bands(dataset: &Dataset, bands: &[isize], window: &Window) -> Result<Vec<f64>, Error> {
+    let Window {
+        input_position,
+        input_size,
+        output_size,
+        ..
+    } = *window;
+    let mut band_list = bands.iter().map(|&band| band as c_int).collect::<Vec<_>>();
+    let mut data = VALUES.take(output_size.0 * output_size.1 * bands.len(), 0.0);
+    let pixel_space = bands.len() * mem::size_of::<f64>();
+    // like `INIT_RASTERIO_EXTRA_ARG`, with the deadline checked between the blocks
+    let mut extra_arg = gdal_sys::GDALRasterIOExtraArg {
+        nVersion: 1,
+        eResampleAlg: gdal_sys::GDALRIOResampleAlg::GRIORA_NearestNeighbour,
+        pfnProgress: Some(workers::check_deadline),
+        pProgressData: ptr::null_mut(),
+        bFloatingPointWindowValidity: 0,
+        dfXOff: 0.0,
+        dfYOff: 0.0,
+        dfXSize: 0.0,
+        dfYSize: 0.0,
+    };
+    let rv = unsafe {
+        gdal_sys::GDALDatasetRasterIOEx(
+            dataset.c_dataset(),
+            gdal_sys::GDALRWFlag::GF_Read,
+            input_position.0 as _,
+            input_position.1 as _,
+            input_size.0 as _,
+            input_size.1 as _,
+            data.as_mut_ptr() as *mut _,
+            output_size.0 as _,
+            output_size.1 as _,
+            gdal_sys::GDALDataType::GDT_Float64,
+            band_list.len() as _,
+            band_list.as_mut_ptr(),
+            pixel_space as _,
+            (pixel_space * output_size.0) as _,
+            mem::size_of::<f64>() as _,
+            &mut extra_arg,
+        )
+    };
+    if rv != gdal_sys::CPLErr::CE_None {
+        if workers::deadline_passed() {
+            return Err(Error::DeadlineExceeded);
+        }
+        return Err(Error::last_cpl_error(rv));
+    }
+    Ok(data)
+}
+
+/// Flags returned by `GDALGetDataCoverageStatus`.
+const COVERAGE_STATUS_DATA: c_int = 0x02;
+const COVERAGE_STATUS_EMPTY: c_int = 0x04;
+
+/// Checks whether a window of some bands is known to hold no data, like the missing blocks of a
+/// sparse COG, without reading it.
+fn is_empty(dataset: &Dataset, bands: &[isize], window: &Window) -> bool {
+    let Window {
+        input_position,
+        input_size,
+        ..
+    } = *window;
+    bands.iter().all(|&band| {
+        let status = unsafe {
+            let c_band = gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _);
+            gdal_sys::GDALGetDataCoverageStatus(
+                c_band,
+                input_position.0 as _,
+                input_position.1 as _,
+                input_size.0 as _,
+                input_size.1 as _,
+                COVERAGE_STATUS_DATA,
+                ptr::null_mut(),
+            )
+        };
+        // drivers that don't know report the window as having data
+        status == COVERAGE_STATUS_EMPTY
+    })
+}
+
+/// A band read on another thread, or left for the current one.
+enum BandRead<'scope> {
+    Spawned(ScopedJoinHandle<'scope, Result<Vec<f64>, Error>>),
+    Deferred(isize),
+}
+
+/// Reads the bands of a window at once, with a handle from the pool each, and interleaves them.
+/// For datasets whose bands are stored apart, this waits for the slowest band instead of for all
+/// of them in turn.
+///
+/// The other threads are borrowed from the workers, so the bands are read in turn on the current
+/// thread when they are all busy.
+pub fn read_bands_parallel(
+    path: &Path,
+    pool: &DatasetPool,
+    bands: &[isize],
+    window: &Window,
+) -> Result<Vec<f64>, Error> {
+    if is_empty(&*pool.get(path)?, bands, window) {
+        return Err(Error::OutsideBounds);
+    }
+    let deadline = workers::deadline();
+    let read = move |band| {
+        workers::with_deadline(deadline, || {
+            let dataset = pool.get(path)?;
+            read_bands(&dataset, &[band], window)
+        })
+    };
+    let planes = thread::scope(|scope| {
+        let reads = bands
+            .iter()
+            .enumerate()
+            .map(|(i, &band)| {
+                // the current thread reads the first band
+                let permit = if i > 0 { workers::try_borrow() } else { None };
+                match permit {
+                    Some(permit) => BandRead::Spawned(scope.spawn(move || {
+                        let _permit = permit;
+                        read(band)
+                    })),
+                    None => BandRead::Deferred(band),
+                }
+            })
+            .collect::<Vec<_>>();
+        reads
+            .into_iter()
+            .map(|read_band| match read_band {
+                BandRead::Spawned(handle) => handle
+                    .join()
+                    .map_err(|_| Error::Internal("band read panicked".to_string()))?,
+                BandRead::Deferred(band) => read(band),
+            })
+            .collect::<Result<Vec<_>, Error>>()
+    })?;
+    let pixels = window.output_size.0 * window.output_size.1;
+    let mut data = VALUES.take(pixels * bands.len(), 0.0);
+    for (i, plane) in planes.into_iter().enumerate() {
+        for (pixel, &value) in plane.iter().enumerate() {
+            data[pixel * bands.len() + i] = value;
+        }
+        VALUES.put(plane);
+    }
+    Ok(data)
+}
+
+/// The source window of a tile and where it goes in the output.
+#[derive(Clone, Copy)]
+pub struct Window {
+    pub input_position: (isize, isize),
+    pub input_size: (usize, usize),
+    pub output_position: (isize, isize),
+    pub output_size: (usize, usize),
+}
+
+/// Finds the window of the dataset covering a tile, failing if they don't intersect.
+pub fn window(
+    info: &RasterInfo,
+    tile_extent: &Extent,
+    width: usize,
+    height: usize,
+) -> Result<Window, Error> {
+    let geo_transform = &info.geo_transform;
+    let (x_size, y_size) = (geo_transform[1], geo_transform[5]);
+    let image_extent = &info.extent;
+    let intersection_extent = Extent {
+        xmin: tile_extent.xmin.max(image_extent.xmin),
+        ymin: tile_extent.ymin.max(image_extent.ymin),
+        xmax: tile_extent.xmax.min(image_extent.xmax),
+        ymax: tile_extent.ymax.min(image_extent.ymax),
+    };
+    tracing::trace!(?image_extent, ?intersection_extent);
+    if intersection_extent.xmin >= intersection_extent.xmax
+        || intersection_extent.ymin >= intersection_extent.ymax
+    {
+        return Err(Error::OutsideBounds);
+    }
+    let px = (intersection_extent.xmin - image_extent.xmin) / x_size;
+    let py = (intersection_extent.ymin - image_extent.ymax) / y_size;
+    let px1 = (intersection_extent.xmax - image_extent.xmin) / x_size;
+    let py1 = (intersection_extent.ymax - image_extent.ymax) / y_size;
+
+    let src_width = (tile_extent.xmax - tile_extent.xmin) / x_size;
+    let src_height = (tile_extent.ymin - tile_extent.ymax) / y_size;
+
+    let src_tile_width_ratio = width as f64 / src_width;
+    let src_tile_height_ratio = height as f64 / src_height;
+
+    let off_left = (intersection_extent.xmin - tile_extent.xmin) / x_size;
+    let off_top = (intersection_extent.ymax - tile_extent.ymax) / y_size;
+    let off_right = (tile_extent.xmax - intersection_extent.xmax) / x_size;
+    let off_bottom = (tile_extent.ymin - intersection_extent.ymin) / y_size;
+
+    let off_left = off_left.round() as isize;
+    let off_top = off_top.round() as isize;
+    let off_right = off_right.round() as isize;
+    let off_bottom = off_bottom.round() as isize;
+
+    let win_x = px.round() as isize;
+    let win_y = py1.round() as isize;
+    let win_w = (px1 - px).round() as usize;
+    let win_h = (py - py1).round() as usize;
+
+    tracing::trace!(win_x, win_y, win_w, win_h, off_left, off_top, off_right, off_bottom);
+
+    let ol = (off_left as f64 * src_tile_width_ratio).round() as usize;
+    let ot = (off_top as f64 * src_tile_height_ratio).round() as usize;
+    let or = (off_right as f64 * src_tile_width_ratio).round() as usize;
+    let ob = (off_bottom as f64 * src_tile_height_ratio).round() as usize;
+
+    Ok(Window {
+        input_position: (win_x, win_y),
+        input_size: (win_w, win_h),
+        output_position: (ol as isize, ot as isize),
+        output_size: (width - ol - or, height - ot - ob),
+    })
+}
+
+/// Estimates the source pixels read to render an extent of some bands of a dataset, taking into
+/// account the overview level GDAL picks for the output resolution.
+pub fn read_cost(
+    dataset: &Dataset,
+    info: &RasterInfo,
+    extent: &Extent,
+    (width, height): (usize, usize),
+    bands: usize,
+) -> Result<usize, Error> {
+    let Window {
+        input_size,
+        output_size,
+        ..
+    } = match window(info, extent, width, height) {
+        Err(Error::OutsideBounds) => return Ok(0),
+        window => window?,
+    };
+    let factor = (input_size.0 as f64 / output_size.0.max(1) as f64)
+        .min(input_size.1 as f64 / output_size.1.max(1) as f64);
+    let band = dataset.rasterband(1)?;
+    let raster_width = dataset.raster_size().0 as f64;
+    let mut overview_factor = 1.0;
+    for i in 0..band.overview_count()? {
+        let overview_factor_i = raster_width / band.overview(i as isize)?.size().0 as f64;
+        if overview_factor_i <= factor {
+            overview_factor = f64::max(overview_factor, overview_factor_i);
+        }
+    }
+    let pixels = (input_size.0 as f64 / overview_factor) * (input_size.1 as f64 / overview_factor);
+    Ok(pixels as usize * bands)
+}
+
+/// Renders the given extent of a dataset into a `width` by `height` RGBA `MEM` dataset.
+///
+/// The dataset and the intermediate buffers are taken from pools, since allocating them for
+/// each tile adds up at high request rates.
+///
+/// The values read are styled by `renderer`, usually `RgbRenderer`.
+/// Tiles over blocks the dataset knows are empty fail with `Error::OutsideBounds` without reading
+/// them.
+pub fn render(
+    dataset: &Dataset,
+    info: &RasterInfo,
+    tile_extent: &Extent,
+    width: usize,
+    height: usize,
+    style: &Style,
+    renderer: &dyn TileRenderer,
+) -> Result<Canvas, Error> {
+    render_with(
+        info,
+        tile_extent,
+        width,
+        height,
+        style,
+        renderer,
+        |bands, window| {
+            if is_empty(dataset, bands, window) {
+                return Err(Error::OutsideBounds);
+            }
+            read_bands(dataset, bands, window)
+        },
+    )
+}
+
+/// Like `render`, but reads the bands with `read`, which returns them pixel-interleaved.
+pub fn render_with(
+    info: &RasterInfo,
+    tile_extent: &Extent,
+    width: usize,
+    height: usize,
+    style: &Style,
+    renderer: &dyn TileRenderer,
+    read: impl FnOnce(&[isize], &Window) -> Result<Vec<f64>, Error>,
+) -> Result<Canvas, Error> {
+    let window = stage("window-calc", || window(info, tile_extent, width, height))?;
+    // the rasters that aren't 8-bit are stretched over their statistics by default
+    let stretched;
+    let style = match info.stretch(&style.bands) {
+        Some(rescale) if style.rescale.is_none() => {
+            stretched = Style {
+                rescale: Some(rescale),
+                ..style.clone()
+            };
+            &stretched
+        }
+        _ => style,
+    };
+    let Window {
+        output_position,
+        output_size,
+        ..
+    } = window;
+
+    let out = Canvas::new(width, height)?;
+    let pixels = output_size.0 * output_size.1;
+    let bands = style
+        .bands
+        .iter()
+        .map(|&band| {
+            let band = info.band(band)?;
+            Ok(SourceBand {
+                no_data: band.no_data,
+                scale: band.scale,
+                offset: band.offset,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut data = stage("read", || read(&style.bands, &window))?;
+    if let Some(speckle) = &style.speckle {
+        stage("speckle", || speckle.apply(&mut data, &bands, output_size));
+    }
+    #[cfg(feature = "scripts")]
+    if let Some(script) = &style.script {
+        stage("script", || script.apply(&mut data, &bands, &style.bands));
+    }
+    let tile = TileData {
+        values: &data,
+        bands: &bands,
+        width: output_size.0,
+        height: output_size.1,
+    };
+    let channels = renderer.render(&tile, style);
+    VALUES.put(data);
+    let mut channels = channels?;
+    if channels.iter().any(|channel| channel.len() != pixels) {
+        return Err(Error::Io(io::Error::other(
+            "the renderer returned channels of the wrong size",
+        )));
+    }
+    // only for the colours as stored, not for band combinations or colormaps
+    if let Some(to_srgb) = &info.to_srgb {
+        if style.bands == [1, 2, 3] && style.colormap.is_none() {
+            let [red, green, blue, _] = &mut channels;
+            stage("colour", || to_srgb.apply(red, green, blue));
+        }
+    }
+    for (i, channel) in IntoIterator::into_iter(channels).enumerate() {
+        let buf = Buffer::new(output_size, channel);
+        out.rasterband(i as isize + 1)?
+            .write(output_position, output_size, &buf)?;
+        CHANNELS.put(buf.data);
+    }
+    Ok(out)
+}
+
+/// Whether the PNGs are marked as sRGB.
+static PNG_SRGB: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the PNGs get an `sRGB` chunk, for the viewers that don't assume it.
+pub fn set_png_srgb(srgb: bool) {
+    PNG_SRGB.store(srgb, Ordering::Relaxed);
+}
+
+pub fn write_png(dataset: &Dataset, file_name: &str) -> Result<(), Error> {
+    let png_driver = Driver::get("PNG")?;
+    let options = if PNG_SRGB.load(Ordering::Relaxed) {
+        &[RasterCreationOption {
+            key: "SOURCE_ICC_PROFILE_NAME",
+            value: "sRGB",
+        }][..]
+    } else {
+        &[]
+    };
+    dataset.create_copy(&png_driver, file_name, options)?;
+    Ok(())
+}
+
+/// Encodes a dataset as PNG in memory.
+pub fn encode_png(dataset: &Dataset) -> Result<Vec<u8>, Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = format!(
+        "/vsimem/render_{}.png",
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    write_png(dataset, &file_name)?;
+    Ok(vsi::get_vsi_mem_file_bytes_owned(&file_name)?)
+}
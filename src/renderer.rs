@@ -0,0 +1,166 @@
+//! The styling stage of the tile pipeline, which can be replaced for some datasets.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::registry::Entry;
+use crate::render::CHANNELS;
+use crate::style::Style;
+
+/// How the stored values of a band map to physical ones.
+#[derive(Clone, Copy, Debug)]
+pub struct SourceBand {
+    pub no_data: Option<f64>,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl SourceBand {
+    /// Checks whether a stored value is nodata, like zero is when the band doesn't have a
+    /// nodata value.
+    pub fn is_no_data(&self, value: f64) -> bool {
+        match self.no_data {
+            Some(no_data) => value == no_data || value.is_nan(),
+            None => value == 0.0,
+        }
+    }
+}
+
+/// The values read for a tile, or for the part of it covered by the dataset.
+pub struct TileData<'a> {
+    /// The stored values of the bands selected by the style, pixel-interleaved, row by row.
+    pub values: &'a [f64],
+    /// The bands selected by the style, in the same order.
+    pub bands: &'a [SourceBand],
+    pub width: usize,
+    pub height: usize,
+}
+
+impl TileData<'_> {
+    pub fn pixels(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// Turns the values read for a tile into its red, green, blue and alpha channels, with one byte
+/// per pixel in each.
+///
+/// Applications embedding the server can register their own with
+/// `TileServerBuilder::renderer`, and select them with the `renderer` of the datasets.
+pub trait TileRenderer: Send + Sync {
+    fn render(&self, tile: &TileData<'_>, style: &Style) -> Result<[Vec<u8>; 4], Error>;
+}
+
+/// Custom renderers, by name.
+pub type Renderers = BTreeMap<String, Arc<dyn TileRenderer>>;
+
+/// Returns the renderer of a dataset, or `RgbRenderer` if it doesn't name one.
+pub fn for_entry<'a>(config: &'a Config, entry: &Entry) -> Result<&'a dyn TileRenderer, Error> {
+    match &entry.renderer {
+        Some(name) => config
+            .renderers
+            .get(name)
+            .map(|renderer| &**renderer)
+            .ok_or_else(|| Error::BadRequest(format!("unknown renderer: {}", name))),
+        None => Ok(&RgbRenderer),
+    }
+}
+
+/// How the raw values of a band are masked and converted to physical values.
+#[derive(Clone, Copy)]
+struct BandValues {
+    /// The nodata value, or zero when the band doesn't have one.
+    no_data: f64,
+    /// Whether NaN is also nodata, which holds when the band has a nodata value.
+    nan_is_no_data: bool,
+    scale: f64,
+    offset: f64,
+}
+
+impl BandValues {
+    // `|` and `&` instead of `||` and `&&` keep this free of branches
+    fn is_no_data(&self, value: f64) -> bool {
+        (value == self.no_data) | (self.nan_is_no_data & value.is_nan())
+    }
+}
+
+impl From<&SourceBand> for BandValues {
+    fn from(band: &SourceBand) -> Self {
+        Self {
+            no_data: band.no_data.unwrap_or(0.0),
+            nan_is_no_data: band.no_data.is_some(),
+            scale: band.scale,
+            offset: band.offset,
+        }
+    }
+}
+
+/// Scales the pixel-interleaved values of `N` bands to one channel per band, in a single pass
+/// that also builds the alpha channel, transparent where any band is nodata.
+fn scale_channels<const N: usize>(
+    data: &[f64],
+    bands: &[BandValues],
+    style: &Style,
+    pixels: usize,
+) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let bands: &[BandValues; N] = bands.try_into().expect("one entry per band");
+    let mut channels: [Vec<u8>; N] = std::array::from_fn(|_| CHANNELS.take(pixels, 0));
+    let mut alpha = CHANNELS.take(pixels, 0);
+    for (i, (values, a)) in data.chunks_exact(N).zip(alpha.iter_mut()).enumerate() {
+        let values: &[f64; N] = values.try_into().unwrap();
+        let mut opaque = true;
+        for ((&value, band), channel) in values.iter().zip(bands).zip(channels.iter_mut()) {
+            opaque &= !band.is_no_data(value);
+            channel[i] = style.scale(value * band.scale + band.offset);
+        }
+        // 255 when opaque, 0 otherwise
+        *a = (opaque as u8).wrapping_neg();
+    }
+    (channels.into(), alpha)
+}
+
+/// Renders one band as grey or through a colormap, or three bands as RGB.
+///
+/// The band scale and offset are applied before styling, so that the styles use physical values.
+/// Pixels matching the band `NODATA` value, or zero when there's none, are made transparent.
+pub struct RgbRenderer;
+
+impl TileRenderer for RgbRenderer {
+    fn render(&self, tile: &TileData<'_>, style: &Style) -> Result<[Vec<u8>; 4], Error> {
+        let pixels = tile.pixels();
+        let bands = tile.bands.iter().map(BandValues::from).collect::<Vec<_>>();
+        // a fixed band count lets the compiler unroll and vectorize the loop
+        let (mut channels, alpha) = match bands.len() {
+            1 => scale_channels::<1>(tile.values, &bands, style, pixels),
+            3 => scale_channels::<3>(tile.values, &bands, style, pixels),
+            _ => {
+                return Err(Error::BadRequest(
+                    "either one or three bands must be selected".to_string(),
+                ))
+            }
+        };
+
+        if let Some(lut) = style.colormap_lut() {
+            let values = channels.remove(0);
+            channels = (0..3)
+                .map(|i| {
+                    let mut channel = CHANNELS.take_empty(pixels);
+                    channel.extend(values.iter().map(|&v| lut[v as usize][i]));
+                    channel
+                })
+                .collect();
+            CHANNELS.put(values);
+        } else if channels.len() == 1 {
+            for _ in 0..2 {
+                let mut channel = CHANNELS.take_empty(pixels);
+                channel.extend_from_slice(&channels[0]);
+                channels.push(channel);
+            }
+        }
+        let [red, green, blue]: [Vec<u8>; 3] = channels.try_into().expect("three channels");
+        Ok([red, green, blue, alpha])
+    }
+}
@@ -0,0 +1,111 @@
+//! Rendering of the rasters in another CRS than the tile grid, which are warped to it when
+//! rendering their tiles, so that they don't need to be reprojected beforehand.
+//!
+//! The warped VRTs are kept in the dataset pool like the other handles, and their properties
+//! are read once for each source.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::canvas::Canvas;
+use crate::crs;
+use crate::dataset::Resampling;
+use crate::dataset_pool::{DatasetPool, Warp};
+use crate::error::Error;
+use crate::raster_info::RasterInfo;
+use crate::render;
+use crate::renderer::TileRenderer;
+use crate::style::Style;
+use crate::tile_grid::{Extent, TileGrid};
+
+/// Whether the CRS of the datasets, by WKT, is the one of the grid, by WKT.
+static SAME_CRS: Mutex<BTreeMap<(String, String), bool>> = Mutex::new(BTreeMap::new());
+
+/// The properties of the warped datasets, with the ones of their sources they were built from.
+#[allow(clippy::type_complexity)]
+static WARPED_INFO: Mutex<BTreeMap<(PathBuf, Warp), (Arc<RasterInfo>, Arc<RasterInfo>)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Returns how to warp a dataset to the CRS of the grid, if it's in another one. Datasets
+/// without a CRS are assumed to be in the one of the grid.
+pub fn grid_warp(
+    info: &RasterInfo,
+    tile_grid: &TileGrid,
+    resampling: Resampling,
+) -> Result<Option<Warp>, Error> {
+    let (source_wkt, grid_wkt) = match (&info.wkt, tile_grid.wkt()) {
+        (Some(source_wkt), Some(grid_wkt)) => (source_wkt, grid_wkt),
+        _ => return Ok(None),
+    };
+    let key = (source_wkt.clone(), grid_wkt.to_string());
+    let same = SAME_CRS.lock().unwrap().get(&key).copied();
+    let same = match same {
+        Some(same) => same,
+        None => {
+            let same = crs::parse_srs(source_wkt)? == crs::parse_srs(grid_wkt)?;
+            SAME_CRS.lock().unwrap().insert(key, same);
+            same
+        }
+    };
+    Ok(Some(Warp {
+        wkt: grid_wkt.to_string(),
+        resampling,
+    })
+    .filter(|_| !same))
+}
+
+/// Renders a tile of a raster reprojected to the CRS of the grid.
+///
+/// The warped VRT doesn't keep the metadata of the bands, so their statistics, scale and offset,
+/// along with the colour profile, are taken from `info`, the properties of the source raster.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    pool: &DatasetPool,
+    path: &Path,
+    info: &Arc<RasterInfo>,
+    warp: &Warp,
+    tile_extent: &Extent,
+    width: usize,
+    height: usize,
+    style: &Style,
+    renderer: &dyn TileRenderer,
+) -> Result<Canvas, Error> {
+    let warped = render::stage("open", || pool.get_warped(path, warp))?;
+    let key = (path.to_path_buf(), warp.clone());
+    let cached = WARPED_INFO
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|(source, _)| Arc::ptr_eq(source, info))
+        .map(|(_, warped)| warped.clone());
+    let warped_info = match cached {
+        Some(warped_info) => warped_info,
+        None => {
+            let read = RasterInfo::read(&warped)?;
+            let warped_info = Arc::new(RasterInfo {
+                geo_transform: read.geo_transform,
+                extent: read.extent,
+                extent_wgs84: info.extent_wgs84.clone(),
+                wkt: Some(warp.wkt.clone()),
+                bands: info.bands.clone(),
+                band_interleaved: false,
+                to_srgb: info.to_srgb.clone(),
+            });
+            WARPED_INFO
+                .lock()
+                .unwrap()
+                .insert(key, (info.clone(), warped_info.clone()));
+            warped_info
+        }
+    };
+    render::render(
+        &warped,
+        &warped_info,
+        tile_extent,
+        width,
+        height,
+        style,
+        renderer,
+    )
+}
@@ -0,0 +1,530 @@
+//! Per-pixel expressions applied to the values read from a dataset before styling them.
+//!
+//! A script is an arithmetic expression of `v`, the stored value of a pixel, and `band`, the
+//! number of its band, like `if(band == 1, v * 0.0001, v) - 0.1`. It supports `+`, `-`, `*`,
+//! `/`, `%` and `^`, comparisons returning 1 or 0, and the functions in `Function`.
+
+use crate::cluster;
+use crate::error::Error;
+use crate::renderer::SourceBand;
+
+/// How deeply parentheses, function calls, signs and powers can be nested, so that parsing a
+/// script like `((((…` can't run out of stack.
+const MAX_NESTING: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Floor,
+    Ceil,
+    Round,
+    Min,
+    Max,
+    Pow,
+    Clamp,
+    If,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        let function = match name {
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "log10" => Self::Log10,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            "round" => Self::Round,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "pow" => Self::Pow,
+            "clamp" => Self::Clamp,
+            "if" => Self::If,
+            _ => return None,
+        };
+        Some(function)
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Self::Min | Self::Max | Self::Pow => 2,
+            Self::Clamp | Self::If => 3,
+            _ => 1,
+        }
+    }
+
+    fn call(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Self::Abs, [x]) => x.abs(),
+            (Self::Sqrt, [x]) => x.sqrt(),
+            (Self::Exp, [x]) => x.exp(),
+            (Self::Ln, [x]) => x.ln(),
+            (Self::Log10, [x]) => x.log10(),
+            (Self::Floor, [x]) => x.floor(),
+            (Self::Ceil, [x]) => x.ceil(),
+            (Self::Round, [x]) => x.round(),
+            (Self::Min, [x, y]) => x.min(*y),
+            (Self::Max, [x, y]) => x.max(*y),
+            (Self::Pow, [x, y]) => x.powf(*y),
+            // unlike `f64::clamp`, this doesn't panic when the bounds are swapped
+            (Self::Clamp, [x, min, max]) => x.max(*min).min(*max),
+            (Self::If, [condition, a, b]) => {
+                if *condition != 0.0 {
+                    *a
+                } else {
+                    *b
+                }
+            }
+            _ => unreachable!("checked when parsing"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl BinaryOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        let bool = |value: bool| value as u8 as f64;
+        match self {
+            Self::Add => a + b,
+            Self::Sub => a - b,
+            Self::Mul => a * b,
+            Self::Div => a / b,
+            Self::Rem => a % b,
+            Self::Pow => a.powf(b),
+            Self::Lt => bool(a < b),
+            Self::Le => bool(a <= b),
+            Self::Gt => bool(a > b),
+            Self::Ge => bool(a >= b),
+            Self::Eq => bool(a == b),
+            Self::Ne => bool(a != b),
+        }
+    }
+}
+
+/// An instruction of the stack machine scripts are compiled to.
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Const(f64),
+    Value,
+    Band,
+    Neg,
+    Binary(BinaryOp),
+    Call(Function),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token<'a> {
+    Number(f64),
+    Ident(&'a str),
+    Symbol(&'a str),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token<'_>)>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = source.char_indices().peekable();
+    while let Some(&(start, c)) = rest.peek() {
+        if c.is_whitespace() {
+            rest.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = rest.peek() {
+                // exponents, like `1e-3`
+                let sign = (c == '-' || c == '+') && source[..i].ends_with(['e', 'E']);
+                if !(c.is_ascii_alphanumeric() || c == '.' || sign) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                rest.next();
+            }
+            let number = source[start..end]
+                .parse()
+                .map_err(|_| invalid(start, "invalid number"))?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = rest.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + 1;
+                rest.next();
+            }
+            tokens.push((start, Token::Ident(&source[start..end])));
+        } else {
+            let two = source.get(start..start + 2);
+            let symbol = match two {
+                Some(symbol @ ("<=" | ">=" | "==" | "!=")) => symbol,
+                _ if "+-*/%^<>(),".contains(c) => &source[start..start + 1],
+                _ => return Err(invalid(start, "unexpected character")),
+            };
+            for _ in 0..symbol.len() {
+                rest.next();
+            }
+            tokens.push((start, Token::Symbol(symbol)));
+        }
+    }
+    Ok(tokens)
+}
+
+fn invalid(position: usize, msg: &str) -> Error {
+    Error::BadRequest(format!("invalid script at {}: {}", position, msg))
+}
+
+/// A recursive descent parser emitting the operations in postfix order.
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    position: usize,
+    end: usize,
+    ops: Vec<Op>,
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).map(|&(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |&(offset, _)| offset)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = self.peek() == Some(Token::Symbol(symbol));
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(invalid(self.offset(), &format!("expected `{}`", symbol)))
+        }
+    }
+
+    fn binary(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let op = ops
+            .iter()
+            .find(|(symbol, _)| self.peek() == Some(Token::Symbol(symbol)))?;
+        self.position += 1;
+        Some(op.1)
+    }
+
+    fn comparison(&mut self) -> Result<(), Error> {
+        use BinaryOp::*;
+        self.sum()?;
+        let ops = [
+            ("<", Lt),
+            ("<=", Le),
+            (">", Gt),
+            (">=", Ge),
+            ("==", Eq),
+            ("!=", Ne),
+        ];
+        if let Some(op) = self.binary(&ops) {
+            self.sum()?;
+            self.ops.push(Op::Binary(op));
+        }
+        Ok(())
+    }
+
+    fn sum(&mut self) -> Result<(), Error> {
+        self.product()?;
+        while let Some(op) = self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)]) {
+            self.product()?;
+            self.ops.push(Op::Binary(op));
+        }
+        Ok(())
+    }
+
+    fn product(&mut self) -> Result<(), Error> {
+        self.unary()?;
+        let ops = [
+            ("*", BinaryOp::Mul),
+            ("/", BinaryOp::Div),
+            ("%", BinaryOp::Rem),
+        ];
+        while let Some(op) = self.binary(&ops) {
+            self.unary()?;
+            self.ops.push(Op::Binary(op));
+        }
+        Ok(())
+    }
+
+    /// Every level of nesting goes through here, which keeps track of it.
+    fn unary(&mut self) -> Result<(), Error> {
+        if self.nesting == MAX_NESTING {
+            return Err(invalid(self.offset(), "too deeply nested"));
+        }
+        self.nesting += 1;
+        if self.eat("-") {
+            self.unary()?;
+            self.ops.push(Op::Neg);
+        } else {
+            self.atom()?;
+            // right-associative, and binding tighter than the unary minus, so `-2^2` is -4
+            if self.eat("^") {
+                self.unary()?;
+                self.ops.push(Op::Binary(BinaryOp::Pow));
+            }
+        }
+        self.nesting -= 1;
+        Ok(())
+    }
+
+    fn atom(&mut self) -> Result<(), Error> {
+        let offset = self.offset();
+        let token = self
+            .peek()
+            .ok_or_else(|| invalid(offset, "unexpected end"))?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => self.ops.push(Op::Const(number)),
+            Token::Ident("v") => self.ops.push(Op::Value),
+            Token::Ident("band") => self.ops.push(Op::Band),
+            Token::Ident("nan") => self.ops.push(Op::Const(f64::NAN)),
+            Token::Ident(name) => {
+                let function = Function::from_name(name)
+                    .ok_or_else(|| invalid(offset, &format!("unknown name `{}`", name)))?;
+                self.expect("(")?;
+                for i in 0..function.arity() {
+                    if i > 0 {
+                        self.expect(",")?;
+                    }
+                    self.comparison()?;
+                }
+                self.expect(")")?;
+                self.ops.push(Op::Call(function));
+            }
+            Token::Symbol("(") => {
+                self.comparison()?;
+                self.expect(")")?;
+            }
+            Token::Symbol(symbol) => {
+                return Err(invalid(offset, &format!("unexpected `{}`", symbol)))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A compiled script.
+#[derive(Debug)]
+pub struct Script {
+    ops: Vec<Op>,
+    /// The largest number of values on the stack while evaluating it.
+    depth: usize,
+    /// A hash of the source, for the cache keys of the tiles it's applied to.
+    hash: u64,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            end: source.len(),
+            ops: Vec::new(),
+            nesting: 0,
+        };
+        parser.comparison()?;
+        if parser.position != parser.tokens.len() {
+            return Err(invalid(parser.offset(), "unexpected trailing input"));
+        }
+        let mut depth = 0usize;
+        let mut max_depth = 0;
+        for op in &parser.ops {
+            depth = match op {
+                Op::Const(_) | Op::Value | Op::Band => depth + 1,
+                Op::Neg => depth,
+                Op::Binary(_) => depth - 1,
+                Op::Call(function) => depth + 1 - function.arity(),
+            };
+            max_depth = max_depth.max(depth);
+        }
+        Ok(Self {
+            ops: parser.ops,
+            depth: max_depth,
+            hash: cluster::hash(source.as_bytes()),
+        })
+    }
+
+    /// Returns a suffix identifying the script in cache keys, so that the tiles rendered with
+    /// another version of it aren't served.
+    pub fn cache_key(&self) -> String {
+        format!("_script={:016x}", self.hash)
+    }
+
+    fn eval(&self, stack: &mut Vec<f64>, value: f64, band: f64) -> f64 {
+        stack.clear();
+        for &op in &self.ops {
+            match op {
+                Op::Const(c) => stack.push(c),
+                Op::Value => stack.push(value),
+                Op::Band => stack.push(band),
+                Op::Neg => {
+                    let top = stack.last_mut().expect("checked when parsing");
+                    *top = -*top;
+                }
+                Op::Binary(op) => {
+                    let b = stack.pop().expect("checked when parsing");
+                    let a = stack.last_mut().expect("checked when parsing");
+                    *a = op.apply(*a, b);
+                }
+                Op::Call(function) => {
+                    let start = stack.len() - function.arity();
+                    let result = function.call(&stack[start..]);
+                    stack.truncate(start);
+                    stack.push(result);
+                }
+            }
+        }
+        stack[0]
+    }
+
+    /// Transforms the pixel-interleaved `values` of the selected `bands`, numbered like
+    /// `band_numbers`, in place. Nodata values are left as they are, so they stay transparent.
+    pub fn apply(&self, values: &mut [f64], bands: &[SourceBand], band_numbers: &[isize]) {
+        let mut stack = Vec::with_capacity(self.depth);
+        for pixel in values.chunks_exact_mut(bands.len()) {
+            for ((value, band), &number) in pixel.iter_mut().zip(bands).zip(band_numbers) {
+                if !band.is_no_data(*value) {
+                    *value = self.eval(&mut stack, *value, number as f64);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Script;
+    use crate::error::Error;
+
+    fn eval(source: &str, value: f64, band: f64) -> f64 {
+        let script = Script::parse(source).unwrap();
+        script.eval(&mut Vec::new(), value, band)
+    }
+
+    fn error(source: &str) -> String {
+        match Script::parse(source) {
+            Err(Error::BadRequest(msg)) => msg,
+            result => panic!("{}: unexpected {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0.0, 1.0), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0, 1.0), 9.0);
+        assert_eq!(eval("10 - 4 - 3", 0.0, 1.0), 3.0);
+        assert_eq!(eval("8 / 2 / 2", 0.0, 1.0), 2.0);
+        assert_eq!(eval("7 % 4 * 2", 0.0, 1.0), 6.0);
+        assert_eq!(eval("-2^2", 0.0, 1.0), -4.0);
+        assert_eq!(eval("2^3^2", 0.0, 1.0), 512.0);
+        assert_eq!(eval("2^-1", 0.0, 1.0), 0.5);
+        assert_eq!(eval("1 + 2 < 4", 0.0, 1.0), 1.0);
+        assert_eq!(eval("v * 2 + band", 3.0, 2.0), 8.0);
+        assert_eq!(eval("1.5e-1 * 2E+1", 0.0, 1.0), 3.0);
+        assert_eq!(eval("if(band == 4, v * 1.2, v) * 0.5", 10.0, 4.0), 6.0);
+        assert_eq!(eval("if(band == 4, v * 1.2, v) * 0.5", 10.0, 1.0), 5.0);
+        assert_eq!(eval("clamp(v, 10, 0)", 5.0, 1.0), 0.0);
+        assert_eq!(eval("max(min(v, 3), -abs(-1))", 5.0, 1.0), 3.0);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("v / 0", 1.0, 1.0), f64::INFINITY);
+        assert_eq!(eval("-v / 0", 1.0, 1.0), f64::NEG_INFINITY);
+        assert!(eval("v / 0", 0.0, 1.0).is_nan());
+        assert!(eval("v % 0", 1.0, 1.0).is_nan());
+        assert!(eval("nan", 1.0, 1.0).is_nan());
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        assert_eq!(error("x + 1"), "invalid script at 0: unknown name `x`");
+        assert_eq!(
+            error("v + foo(v)"),
+            "invalid script at 4: unknown name `foo`"
+        );
+        assert_eq!(error("sqrt"), "invalid script at 4: expected `(`");
+    }
+
+    #[test]
+    fn stack_depth() {
+        let depth = |source| Script::parse(source).unwrap().depth;
+        assert_eq!(depth("((v))"), 1);
+        assert_eq!(depth("1 + 2 + 3 + 4"), 2);
+        assert_eq!(depth("1 + (2 + (3 + 4))"), 4);
+        assert_eq!(depth("1 + 2 * 3"), 3);
+        assert_eq!(depth("-clamp(v, 0, 1)"), 3);
+        assert_eq!(depth("if(v > 0, v, 0) + 1"), 3);
+        // the stack never grows past the depth computed when parsing
+        let script = Script::parse("1 + (2 + (3 + 4))").unwrap();
+        let mut stack = Vec::with_capacity(script.depth);
+        assert_eq!(script.eval(&mut stack, 0.0, 1.0), 10.0);
+        assert_eq!(stack.capacity(), script.depth);
+    }
+
+    #[test]
+    fn nesting() {
+        let parens = |n| format!("{}v{}", "(".repeat(n), ")".repeat(n));
+        assert!(Script::parse(&parens(63)).is_ok());
+        assert_eq!(
+            error(&parens(64)),
+            "invalid script at 64: too deeply nested"
+        );
+        assert!(error(&parens(100_000)).ends_with("too deeply nested"));
+        assert!(error(&"-".repeat(100_000)).ends_with("too deeply nested"));
+        assert!(error(&"2^".repeat(100_000)).ends_with("too deeply nested"));
+        assert!(error(&"abs(".repeat(100_000)).ends_with("too deeply nested"));
+    }
+
+    #[test]
+    fn malformed_input() {
+        let errors = [
+            ("", "invalid script at 0: unexpected end"),
+            ("1 +", "invalid script at 3: unexpected end"),
+            ("(1", "invalid script at 2: expected `)`"),
+            ("1 2", "invalid script at 2: unexpected trailing input"),
+            (
+                "1 < 2 < 3",
+                "invalid script at 6: unexpected trailing input",
+            ),
+            ("min(1)", "invalid script at 5: expected `,`"),
+            ("if(1, 2, 3,)", "invalid script at 10: expected `)`"),
+            ("1 $ 2", "invalid script at 2: unexpected character"),
+            ("1..2", "invalid script at 0: invalid number"),
+            ("* 2", "invalid script at 0: unexpected `*`"),
+            ("v = 1", "invalid script at 2: unexpected character"),
+        ];
+        for (source, msg) in errors {
+            assert_eq!(error(source), msg, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::batch;
+use crate::cache;
+use crate::config::Config;
+use crate::crs;
+use crate::dataset;
+use crate::dataset_pool::DatasetPool;
+use crate::error::Error;
+use crate::mbtiles;
+use crate::pmtiles;
+use crate::raster_info;
+use crate::registry::{Entry, Kind, Registry};
+use crate::remote;
+use crate::render;
+use crate::reproject;
+use crate::style::StyleQuery;
+use crate::tile_grid::Extent;
+use crate::tilejson;
+
+/// How often the progress is saved to the state file.
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_ZOOM: u8 = 30;
+/// Failed tiles listed in the summary.
+const MAX_REPORTED: usize = 10;
+
+#[derive(Args)]
+pub struct Options {
+    /// The dataset to seed
+    #[arg(long)]
+    dataset: String,
+    /// The lowest zoom level
+    #[arg(long, default_value_t = 0)]
+    minzoom: u8,
+    /// The highest zoom level
+    #[arg(long, default_value_t = 14)]
+    maxzoom: u8,
+    /// The extent to seed, as xmin,ymin,xmax,ymax in the CRS of the dataset [default: all of it]
+    #[arg(long, value_parser = crate::parse_bbox)]
+    bbox: Option<Extent>,
+    /// The threads rendering the tiles [default: one per CPU]
+    #[arg(long, value_parser = crate::parse_count)]
+    workers: Option<usize>,
+    /// An MBTiles or PMTiles archive to write the tiles into too
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// The file the progress is saved to, for resuming an interrupted seed
+    #[arg(long)]
+    state: Option<PathBuf>,
+    /// Tiles rendered per second, at most.
+    #[arg(long, value_parser = crate::parse_positive)]
+    rate: Option<f64>,
+    /// Tiles rendered at once, at most.
+    #[arg(long, value_parser = crate::parse_count)]
+    max_reads: Option<usize>,
+}
+
+/// The tiles of a dataset over a range of zoom levels, with the rows counted from the bottom of
+/// the tile grid.
+pub struct Pyramid {
+    ranges: Vec<(u8, RangeInclusive<u32>, RangeInclusive<u32>)>,
+}
+
+impl Pyramid {
+    /// Covers `bbox`, or the extent of the dataset if it has one.
+    pub fn new(
+        name: &str,
+        entry: &Entry,
+        bbox: Option<Extent>,
+        (minzoom, maxzoom): (u8, u8),
+        config: &Config,
+        pool: &DatasetPool,
+    ) -> Result<Self, Error> {
+        if minzoom > maxzoom || maxzoom > MAX_ZOOM {
+            return Err(Error::BadRequest(format!(
+                "invalid zoom range: {}-{}",
+                minzoom, maxzoom
+            )));
+        }
+        let extent = match (bbox, entry.kind) {
+            (Some(bbox), Kind::Raster | Kind::GeoPackage | Kind::Stac) => bbox,
+            (None, Kind::Raster | Kind::GeoPackage) => {
+                let dataset = pool.get(&entry.path)?;
+                let info = raster_info::get(&entry.path, &dataset)?;
+                match reproject::grid_warp(&info, &config.tile_grid, config.resampling)? {
+                    Some(warp) => {
+                        let transform = crs::transform(
+                            &dataset::spatial_ref(&dataset)?,
+                            &crs::parse_srs(&warp.wkt)?,
+                        )?;
+                        dataset::reproject_extent(&info.extent, &transform)?
+                    }
+                    None => info.extent.clone(),
+                }
+            }
+            (None, Kind::Stac) => {
+                return Err(Error::BadRequest(format!(
+                    "a bounding box is needed to seed {}",
+                    name
+                )))
+            }
+            _ => {
+                return Err(Error::BadRequest(format!(
+                    "{} is not rendered by the server",
+                    name
+                )))
+            }
+        };
+        let ranges = (minzoom..=maxzoom)
+            .filter_map(|z| {
+                let (xs, ys) = config.tile_grid.tile_range(&extent, z)?;
+                Some((z, xs, ys))
+            })
+            .collect();
+        Ok(Self { ranges })
+    }
+
+    pub fn total(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|(_, xs, ys)| {
+                (xs.end() - xs.start() + 1) as usize * (ys.end() - ys.start() + 1) as usize
+            })
+            .sum()
+    }
+
+    /// Lists the tiles lazily, since the pyramid can be too large to list up front.
+    pub fn into_tiles(self) -> impl Iterator<Item = (u8, u32, u32)> {
+        self.ranges
+            .into_iter()
+            .flat_map(|(z, xs, ys)| xs.flat_map(move |x| ys.clone().map(move |y| (z, x, y))))
+    }
+}
+
+/// Where the seeded tiles go, besides the tile cache.
+enum Output {
+    Cache,
+    MbTiles(Mutex<mbtiles::Writer>),
+    PmTiles(Mutex<pmtiles::Writer>),
+}
+
+impl Output {
+    fn create(
+        path: &Path,
+        name: &str,
+        entry: &Entry,
+        options: &Options,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("mbtiles") => {
+                let tilejson = tilejson::build_tilejson(name, entry.clone(), "", config)?;
+                let [xmin, ymin, xmax, ymax] = tilejson.bounds;
+                let mut metadata = BTreeMap::new();
+                metadata.insert("name", tilejson.name);
+                metadata.insert("format", "png".to_string());
+                metadata.insert("type", "overlay".to_string());
+                metadata.insert("minzoom", options.minzoom.to_string());
+                metadata.insert("maxzoom", options.maxzoom.to_string());
+                metadata.insert("bounds", format!("{},{},{},{}", xmin, ymin, xmax, ymax));
+                metadata.insert(
+                    "center",
+                    format!(
+                        "{},{},{}",
+                        (xmin + xmax) / 2.0,
+                        (ymin + ymax) / 2.0,
+                        options.minzoom
+                    ),
+                );
+                if let Some(description) = tilejson.description {
+                    metadata.insert("description", description);
+                }
+                if let Some(attribution) = tilejson.attribution {
+                    metadata.insert("attribution", attribution);
+                }
+                Ok(Output::MbTiles(Mutex::new(mbtiles::Writer::create(
+                    path, &metadata,
+                )?)))
+            }
+            Some("pmtiles") => {
+                let tilejson = tilejson::build_tilejson(name, entry.clone(), "", config)?;
+                let [xmin, ymin, xmax, ymax] = tilejson.bounds;
+                let metadata = json!({
+                    "name": tilejson.name,
+                    "description": tilejson.description,
+                    "attribution": tilejson.attribution,
+                    "type": "overlay",
+                });
+                let bounds = Extent {
+                    xmin,
+                    ymin,
+                    xmax,
+                    ymax,
+                };
+                Ok(Output::PmTiles(Mutex::new(pmtiles::Writer::create(
+                    path,
+                    (options.minzoom, options.maxzoom),
+                    bounds,
+                    metadata,
+                )?)))
+            }
+            _ => Err(Error::BadRequest(format!(
+                "unsupported output format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Writes a tile, with `row` counted from the bottom of the tile grid.
+    fn write(&self, (z, x, row): (u8, u32, u32), data: &[u8]) -> Result<(), Error> {
+        match self {
+            Output::Cache => Ok(()),
+            Output::MbTiles(writer) => writer.lock().unwrap().insert(z, x, row, data),
+            Output::PmTiles(writer) => {
+                writer
+                    .lock()
+                    .unwrap()
+                    .insert(z, x, (1 << z) - 1 - row, data)
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            Output::Cache => Ok(()),
+            Output::MbTiles(writer) => writer.into_inner().unwrap().finish(),
+            Output::PmTiles(writer) => writer.into_inner().unwrap().finish(),
+        }
+    }
+}
+
+/// The progress of a seed, saved so that it can be resumed.
+#[derive(Deserialize, Serialize)]
+struct State {
+    /// The options the seed was started with, which must match when resuming.
+    options: String,
+    /// The number of tiles, in seeding order, that were all completed.
+    completed: usize,
+}
+
+impl State {
+    fn options(options: &Options) -> String {
+        format!(
+            "{} {}-{} {:?} {:?}",
+            options.dataset, options.minzoom, options.maxzoom, options.bbox, options.output
+        )
+    }
+
+    /// Reads the state file, if there's one from an interrupted seed.
+    fn load(path: &Path, options: &Options) -> Result<usize, Error> {
+        let json = match std::fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let state = serde_json::from_slice::<State>(&json).map_err(|e| {
+            Error::BadRequest(format!("invalid state file {}: {}", path.display(), e))
+        })?;
+        if state.options != Self::options(options) {
+            return Err(Error::BadRequest(format!(
+                "{} was saved by a seed with other options: {}",
+                path.display(),
+                state.options
+            )));
+        }
+        Ok(state.completed)
+    }
+
+    /// Replaces the state file, through a temporary file so that it's never left truncated.
+    fn save(path: &Path, options: &Options, completed: usize) -> Result<(), Error> {
+        let state = State {
+            options: Self::options(options),
+            completed,
+        };
+        let json = serde_json::to_vec(&state).map_err(|e| Error::Io(io::Error::other(e)))?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        std::fs::write(&temp_path, json)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Tracks which tiles were completed, since the threads finish them out of order.
+struct Completed {
+    /// All the tiles before this one were completed.
+    watermark: usize,
+    /// The completed tiles after the watermark.
+    pending: BTreeSet<usize>,
+}
+
+impl Completed {
+    fn insert(&mut self, tile: usize) {
+        self.pending.insert(tile);
+        while self.pending.remove(&self.watermark) {
+            self.watermark += 1;
+        }
+    }
+}
+
+/// Limits the rate of the tiles rendered and how many are read at once, so that seeding doesn't
+/// starve the server or hit the rate limits of object stores. Cached tiles aren't limited.
+struct Throttle {
+    /// The time between two renders.
+    interval: Option<Duration>,
+    /// When the next render can start.
+    next: Mutex<Instant>,
+    max_reads: usize,
+    reads: Mutex<usize>,
+    read_done: Condvar,
+}
+
+/// A render allowed by a `Throttle`, finished when dropped.
+struct Read<'a>(&'a Throttle);
+
+impl Throttle {
+    fn new(rate: Option<f64>, max_reads: Option<usize>) -> Self {
+        Self {
+            interval: rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next: Mutex::new(Instant::now()),
+            max_reads: max_reads.unwrap_or(usize::MAX),
+            reads: Mutex::new(0),
+            read_done: Condvar::new(),
+        }
+    }
+
+    /// Waits until a render can start.
+    fn acquire(&self) -> Read<'_> {
+        if let Some(interval) = self.interval {
+            let slot = {
+                let mut next = self.next.lock().unwrap();
+                let slot = (*next).max(Instant::now());
+                *next = slot + interval;
+                slot
+            };
+            thread::sleep(slot.saturating_duration_since(Instant::now()));
+        }
+        let mut reads = self.reads.lock().unwrap();
+        while *reads >= self.max_reads {
+            reads = self.read_done.wait(reads).unwrap();
+        }
+        *reads += 1;
+        Read(self)
+    }
+}
+
+impl Drop for Read<'_> {
+    fn drop(&mut self) {
+        *self.0.reads.lock().unwrap() -= 1;
+        self.0.read_done.notify_one();
+    }
+}
+
+/// Redraws the progress line on stderr.
+fn progress(done: usize, skipped: usize, total: usize, start: Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    eprint!(
+        "\r{}/{} tiles ({:.1}%), {:.1} tiles/s",
+        skipped + done,
+        total,
+        (skipped + done) as f64 * 100.0 / total.max(1) as f64,
+        done as f64 / elapsed.max(1e-3)
+    );
+    let _ = io::stderr().flush();
+}
+
+/// Renders the tiles of a dataset over a range of zoom levels into the tile cache, through the
+/// same pipeline as the tile endpoint, so they're served without rendering them first.
+///
+/// With `--output`, the tiles are also written into an archive that can be served or used
+/// offline on its own.
+pub fn run(options: Options) -> Result<(), Error> {
+    let workers = options
+        .workers
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()));
+    let mut config = Config::from_env();
+    config.tile_grid.resolve_crs()?;
+    // keep a handle for each thread
+    config.pool.size = config.pool.size.max(workers);
+    remote::configure(&config.remote)?;
+    render::set_png_srgb(config.png_srgb);
+    cache::set_dir(&config.cache_dir);
+    std::fs::create_dir_all(cache::dir())?;
+    let registry = Registry::new(PathBuf::from("."), config.remote.clone())?;
+    let entry = registry.get(&options.dataset)?;
+    let pool = DatasetPool::new(config.pool.clone());
+    let pyramid = Pyramid::new(
+        &options.dataset,
+        &entry,
+        options.bbox.clone(),
+        (options.minzoom, options.maxzoom),
+        &config,
+        &pool,
+    )?;
+    let total = pyramid.total();
+    let skipped = match &options.state {
+        Some(path) => State::load(path, &options)?,
+        None => 0,
+    };
+    let pmtiles = options
+        .output
+        .as_ref()
+        .and_then(|path| path.extension())
+        .is_some_and(|extension| extension == "pmtiles");
+    if skipped > 0 && pmtiles {
+        // the tiles written before the interruption were never indexed
+        return Err(Error::BadRequest(
+            "PMTiles archives can't be resumed".to_string(),
+        ));
+    }
+    let tiles = Mutex::new(pyramid.into_tiles().enumerate().skip(skipped));
+    let output = match &options.output {
+        Some(path) => Output::create(path, &options.dataset, &entry, &options, &config)?,
+        None => Output::Cache,
+    };
+    println!(
+        "seeding {} tiles of {} at zoom {}-{} with {} threads",
+        total, options.dataset, options.minzoom, options.maxzoom, workers
+    );
+    if skipped > 0 {
+        println!("resuming after {} completed tiles", skipped);
+    }
+
+    let style = StyleQuery::default().with_defaults(&entry.style);
+    let throttle = Throttle::new(options.rate, options.max_reads);
+    let done = AtomicUsize::new(0);
+    let rendered = AtomicUsize::new(0);
+    let empty = AtomicUsize::new(0);
+    let failures = Mutex::new(Vec::new());
+    let completed = Mutex::new(Completed {
+        watermark: skipped,
+        pending: BTreeSet::new(),
+    });
+    let save = || match &options.state {
+        Some(path) => {
+            let watermark = completed.lock().unwrap().watermark;
+            State::save(path, &options, watermark)
+        }
+        None => Ok(()),
+    };
+    let start = Instant::now();
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| loop {
+                    let tile = tiles.lock().unwrap().next();
+                    let (i, (z, x, row)) = match tile {
+                        Some(tile) => tile,
+                        None => break,
+                    };
+                    let y = batch::flip_y(z, row, &config);
+                    let cached =
+                        crate::tile_cache_path(&entry, &options.dataset, (z, x, y), &style)
+                            .is_ok_and(|path| Path::new(&path).exists());
+                    let _read = (!cached).then(|| throttle.acquire());
+                    let result = crate::cached_tile(
+                        &entry,
+                        &options.dataset,
+                        (z, x, y),
+                        &style,
+                        &config,
+                        &pool,
+                    )
+                    .and_then(|(png, rendered)| {
+                        output.write((z, x, row), &png)?;
+                        Ok(rendered)
+                    });
+                    match &result {
+                        Ok(true) => {
+                            rendered.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(false) => {}
+                        Err(Error::OutsideBounds) => {
+                            empty.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => failures.lock().unwrap().push(((z, x, y), e.to_string())),
+                    }
+                    // failed tiles are retried when resuming
+                    if matches!(result, Ok(_) | Err(Error::OutsideBounds)) {
+                        completed.lock().unwrap().insert(i);
+                    }
+                    done.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut saved = Instant::now();
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            progress(done.load(Ordering::Relaxed), skipped, total, start);
+            if saved.elapsed() >= SAVE_INTERVAL {
+                if let Err(e) = save() {
+                    tracing::warn!("cannot save the seeding state: {}", e);
+                }
+                saved = Instant::now();
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    });
+    let (done, rendered, empty) = (done.into_inner(), rendered.into_inner(), empty.into_inner());
+    progress(done, skipped, total, start);
+    eprintln!();
+
+    output.finish()?;
+    save()?;
+
+    let failures = failures.into_inner().unwrap();
+    println!(
+        "seeded {} tiles ({} rendered, {} already cached, {} empty, {} failed) in {:.2} s",
+        done,
+        rendered,
+        done - rendered - empty - failures.len(),
+        empty,
+        failures.len(),
+        start.elapsed().as_secs_f64()
+    );
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let mut errors = BTreeMap::<&str, usize>::new();
+    for (_, error) in &failures {
+        *errors.entry(error).or_default() += 1;
+    }
+    for (error, count) in errors {
+        println!("{:>8} {}", count, error);
+    }
+    for ((z, x, y), error) in failures.iter().take(MAX_REPORTED) {
+        println!("cannot render {}/{}/{}: {}", z, x, y, error);
+    }
+    Err(Error::Io(io::Error::other(format!(
+        "{} tiles could not be rendered",
+        failures.len()
+    ))))
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use gdal::Metadata;
+use serde::Deserialize;
+
+use crate::dataset;
+use crate::error::Error;
+use crate::style::StyleQuery;
+
+/// Reflectance range stretched over the output colors by default, for the values scaled by
+/// 10000 in the products.
+const DEFAULT_RESCALE: &str = "0,3000";
+
+fn default_resolution() -> u32 {
+    10
+}
+
+fn default_bands() -> Vec<String> {
+    vec!["B4".to_string(), "B3".to_string(), "B2".to_string()]
+}
+
+/// Opens Sentinel-2 L1C and L2A products at one of their resolutions.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Sentinel2 {
+    /// The resolution of the bands to read, either 10, 20 or 60 m.
+    #[serde(default = "default_resolution")]
+    pub resolution: u32,
+    /// The bands shown by default, like `["B8", "B4", "B3"]` for false color.
+    #[serde(default = "default_bands")]
+    pub bands: Vec<String>,
+}
+
+/// Finds the metadata file of an unpacked `.SAFE` product, which GDAL opens.
+fn product_path(path: &Path) -> PathBuf {
+    if !path.is_dir() {
+        // the metadata file itself or a zipped product
+        return path.to_path_buf();
+    }
+    ["MTD_MSIL2A.xml", "MTD_MSIL1C.xml"]
+        .iter()
+        .map(|name| path.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Checks whether a band description like `B4, central wavelength 665 nm` names a band.
+fn is_band(description: &str, band: &str) -> bool {
+    description == band
+        || description
+            .strip_prefix(band)
+            .is_some_and(|rest| rest.starts_with(','))
+}
+
+impl Sentinel2 {
+    /// Selects the subdataset with the bands at the configured resolution, returning its path
+    /// along with the default style showing the configured bands.
+    pub fn open(&self, path: &Path) -> Result<(PathBuf, StyleQuery), Error> {
+        let product = dataset::open(&product_path(path))?;
+        let resolution = format!(":{}m:", self.resolution);
+        let subdataset = dataset::subdatasets(&product)
+            .into_iter()
+            .map(|(name, _)| name)
+            .find(|name| name.contains(&resolution))
+            .ok_or_else(|| {
+                Error::BadRequest(format!(
+                    "no {} m bands in {}",
+                    self.resolution,
+                    path.display()
+                ))
+            })?;
+        let subdataset = PathBuf::from(subdataset);
+
+        let dataset = dataset::open(&subdataset)?;
+        let descriptions = (1..=dataset.raster_count())
+            .map(|i| dataset.rasterband(i)?.description())
+            .collect::<Result<Vec<_>, _>>()?;
+        let bands = self
+            .bands
+            .iter()
+            .map(|band| {
+                descriptions
+                    .iter()
+                    .position(|description| is_band(description, band))
+                    .map(|i| (i + 1).to_string())
+                    .ok_or_else(|| {
+                        Error::BadRequest(format!(
+                            "band {} is not available at {} m",
+                            band, self.resolution
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let style = StyleQuery {
+            bands: Some(bands.join(",")),
+            rescale: Some(DEFAULT_RESCALE.to_string()),
+            ..Default::default()
+        };
+        Ok((subdataset, style))
+    }
+}
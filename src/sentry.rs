@@ -0,0 +1,92 @@
+//! Reports the internal errors and panics to Sentry.
+//!
+//! The events of the requests carry their method, URL and headers, without the API keys and
+//! the admin token.
+
+use std::sync::{Arc, OnceLock};
+
+use ::sentry::protocol::Event;
+use ::sentry::types::Dsn;
+use ::sentry::{ClientInitGuard, ClientOptions, Level};
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::access;
+use crate::error::Error;
+
+/// Headers left out of the events.
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie", "x-api-key"];
+
+/// Attached to the responses of internal errors, so that they can be reported with their
+/// message.
+#[derive(Clone)]
+pub struct Reported(pub String);
+
+/// Keeps the client, which would stop reporting when dropped.
+static GUARD: OnceLock<ClientInitGuard> = OnceLock::new();
+
+fn redact(mut event: Event<'static>) -> Option<Event<'static>> {
+    if let Some(request) = &mut event.request {
+        if let Some(url) = &mut request.url {
+            let query = url.query().map(access::redact_query);
+            url.set_query(query.as_deref());
+        }
+        request.query_string = request.query_string.as_deref().map(access::redact_query);
+        request
+            .headers
+            .retain(|name, _| !SECRET_HEADERS.contains(&name.to_ascii_lowercase().as_str()));
+    }
+    Some(event)
+}
+
+/// Starts reporting to the project of a DSN, like `https://key@o123.ingest.sentry.io/456`.
+/// Only the first call has an effect.
+pub fn init(dsn: &str) -> Result<(), Error> {
+    let dsn = dsn
+        .parse::<Dsn>()
+        .map_err(|e| Error::BadRequest(format!("invalid Sentry DSN {}: {}", dsn, e)))?;
+    GUARD.get_or_init(|| {
+        ::sentry::init(ClientOptions {
+            dsn: Some(dsn),
+            release: ::sentry::release_name!(),
+            before_send: Some(Arc::new(redact)),
+            ..ClientOptions::default()
+        })
+    });
+    Ok(())
+}
+
+/// Reports the internal errors of the requests, with their dataset, tile and request id.
+///
+/// Overloaded responses aren't reported, since they are expected under load.
+pub async fn report_errors<B>(request: Request<B>, next: Next<B>) -> Response {
+    let (dataset, tile) = access::request_target(request.uri().path());
+    let dataset = dataset.map(str::to_string);
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string);
+    let response = next.run(request).await;
+    if response.status() == StatusCode::INTERNAL_SERVER_ERROR {
+        if let Some(Reported(error)) = response.extensions().get::<Reported>() {
+            ::sentry::with_scope(
+                |scope| {
+                    let tags = [
+                        ("dataset", dataset),
+                        ("tile", tile),
+                        ("request_id", request_id),
+                    ];
+                    for (name, value) in tags {
+                        if let Some(value) = value {
+                            scope.set_tag(name, value);
+                        }
+                    }
+                },
+                || ::sentry::capture_message(error, Level::Error),
+            );
+        }
+    }
+    response
+}
@@ -0,0 +1,96 @@
+//! Logging of the requests slower than `Config::slow_request_threshold`, with the timings of the
+//! rendering stages they went through.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::access;
+use crate::config::Config;
+
+/// What a request did, collected while it runs.
+#[derive(Default)]
+pub struct Trace {
+    stages: Vec<(&'static str, Duration)>,
+    /// The tiles rendered, like `file.tif/3/4/2`.
+    tiles: Vec<String>,
+}
+
+pub type SharedTrace = Arc<Mutex<Trace>>;
+
+tokio::task_local! {
+    static REQUEST: SharedTrace;
+}
+
+thread_local! {
+    /// The trace of the request a worker thread runs a job for.
+    static WORKER: RefCell<Option<SharedTrace>> = const { RefCell::new(None) };
+}
+
+/// Returns the trace of the current request, if it's being traced.
+pub fn current() -> Option<SharedTrace> {
+    REQUEST
+        .try_with(Clone::clone)
+        .ok()
+        .or_else(|| WORKER.with(|trace| trace.borrow().clone()))
+}
+
+/// Runs a job on a worker thread, recording its stages in the trace of the request it's for.
+pub fn scope<T>(trace: Option<SharedTrace>, f: impl FnOnce() -> T) -> T {
+    let previous = WORKER.with(|worker| worker.replace(trace));
+    let result = f();
+    WORKER.with(|worker| worker.replace(previous));
+    result
+}
+
+pub fn record_stage(name: &'static str, elapsed: Duration) {
+    if let Some(trace) = current() {
+        trace.lock().unwrap().stages.push((name, elapsed));
+    }
+}
+
+pub fn record_tile(file: &str, (z, x, y): (u8, u32, u32)) {
+    if let Some(trace) = current() {
+        let tile = format!("{}/{}/{}/{}", file, z, x, y);
+        trace.lock().unwrap().tiles.push(tile);
+    }
+}
+
+/// Logs a warning for the requests taking longer than the configured threshold.
+pub async fn log_slow_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let threshold = request
+        .extensions()
+        .get::<Config>()
+        .and_then(|config| config.slow_request_threshold);
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => return next.run(request).await,
+    };
+    let uri = access::redact_uri(request.uri());
+    let trace = SharedTrace::default();
+    let start = Instant::now();
+    let response = REQUEST.scope(trace.clone(), next.run(request)).await;
+    let elapsed = start.elapsed();
+    if elapsed >= threshold {
+        let trace = trace.lock().unwrap();
+        let stages = trace
+            .stages
+            .iter()
+            .map(|(name, elapsed)| format!("{}={}", name, elapsed.as_micros()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        tracing::warn!(
+            uri = %uri,
+            status = response.status().as_u16(),
+            elapsed_us = elapsed.as_micros() as u64,
+            tiles = %trace.tiles.join(" "),
+            stages_us = %stages,
+            "slow request"
+        );
+    }
+    response
+}
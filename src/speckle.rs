@@ -0,0 +1,154 @@
+//! Speckle filters for SAR backscatter, applied to the values read before styling them.
+//!
+//! The filters work on the pixels of the tile, so at low zoom levels they smooth the values GDAL
+//! picked from the overviews. Like the sharpening and blurring filters, they need the tiles to be
+//! rendered with a border, so that the windows at their edges see the pixels of the next tile.
+
+use crate::error::Error;
+use crate::renderer::SourceBand;
+
+const DEFAULT_WINDOW: usize = 5;
+const MAX_WINDOW: usize = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speckle {
+    /// The mean of the window.
+    Boxcar { window: usize },
+    /// The Lee filter, which smooths the homogeneous areas like the boxcar one, but keeps the
+    /// edges and point targets, with the number of looks of the image.
+    Lee { window: usize, looks: f64 },
+}
+
+impl Speckle {
+    /// Parses a filter like `lee`, `lee,7` or `lee,7,4.4`, with the window size and, for the Lee
+    /// filter, the number of looks.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let invalid = || Error::BadRequest(format!("invalid speckle filter: {}", value));
+        let mut parts = value.split(',');
+        let kind = parts.next().unwrap_or_default();
+        let window = match parts.next() {
+            Some(window) => window.trim().parse().map_err(|_| invalid())?,
+            None => DEFAULT_WINDOW,
+        };
+        if !(3..=MAX_WINDOW).contains(&window) || window % 2 == 0 {
+            return Err(Error::BadRequest(format!(
+                "the speckle filter window must be odd, between 3 and {}",
+                MAX_WINDOW
+            )));
+        }
+        let speckle = match kind {
+            "boxcar" => Speckle::Boxcar { window },
+            "lee" => {
+                let looks = match parts.next() {
+                    Some(looks) => looks
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|looks: &f64| *looks > 0.0)
+                        .ok_or_else(invalid)?,
+                    None => 1.0,
+                };
+                Speckle::Lee { window, looks }
+            }
+            _ => return Err(invalid()),
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(speckle)
+    }
+
+    /// Returns how far from a pixel the filter reads, which is the border the tiles need.
+    pub fn radius(&self) -> usize {
+        match *self {
+            Speckle::Boxcar { window } | Speckle::Lee { window, .. } => window / 2,
+        }
+    }
+
+    /// Filters pixel-interleaved values in place, leaving out the nodata ones.
+    pub fn apply(&self, values: &mut [f64], bands: &[SourceBand], (width, height): (usize, usize)) {
+        let radius = self.radius();
+        for (i, band) in bands.iter().enumerate() {
+            let value = |pixel: usize| values[pixel * bands.len() + i];
+            let sums = SummedArea::new((width, height), |pixel| {
+                let value = value(pixel);
+                if band.is_no_data(value) {
+                    [0.0; 3]
+                } else {
+                    [1.0, value, value * value]
+                }
+            });
+            let mut filtered = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let value = value(y * width + x);
+                    if band.is_no_data(value) {
+                        filtered.push(value);
+                        continue;
+                    }
+                    let [count, sum, squares] = sums.window(
+                        (x.saturating_sub(radius), y.saturating_sub(radius)),
+                        ((x + radius + 1).min(width), (y + radius + 1).min(height)),
+                    );
+                    let mean = sum / count;
+                    filtered.push(match *self {
+                        Speckle::Boxcar { .. } => mean,
+                        Speckle::Lee { looks, .. } => {
+                            let variance = (squares / count - mean * mean).max(0.0);
+                            // the squared coefficients of variation of the window and the speckle
+                            let window_cv = variance / (mean * mean);
+                            let speckle_cv = 1.0 / looks;
+                            let weight = if window_cv > speckle_cv {
+                                1.0 - speckle_cv / window_cv
+                            } else {
+                                0.0
+                            };
+                            mean + weight * (value - mean)
+                        }
+                    });
+                }
+            }
+            for (pixel, value) in filtered.into_iter().enumerate() {
+                values[pixel * bands.len() + i] = value;
+            }
+        }
+    }
+}
+
+/// Sums of the pixel counts, values and squared values over the rectangles starting at the top
+/// left corner, for the sums over any window in constant time.
+struct SummedArea {
+    width: usize,
+    sums: Vec<[f64; 3]>,
+}
+
+impl SummedArea {
+    fn new((width, height): (usize, usize), pixel: impl Fn(usize) -> [f64; 3]) -> Self {
+        let stride = width + 1;
+        let mut sums = vec![[0.0; 3]; stride * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                let terms = pixel(y * width + x);
+                for k in 0..3 {
+                    sums[(y + 1) * stride + x + 1][k] =
+                        terms[k] + sums[y * stride + x + 1][k] + sums[(y + 1) * stride + x][k]
+                            - sums[y * stride + x][k];
+                }
+            }
+        }
+        Self { width, sums }
+    }
+
+    /// Returns the sums over the pixels from `start` up to, but not including, `end`.
+    fn window(&self, start: (usize, usize), end: (usize, usize)) -> [f64; 3] {
+        let stride = self.width + 1;
+        let mut out = [0.0; 3];
+        for (k, out) in out.iter_mut().enumerate() {
+            *out = self.sums[end.1 * stride + end.0][k]
+                - self.sums[start.1 * stride + end.0][k]
+                - self.sums[end.1 * stride + start.0][k]
+                + self.sums[start.1 * stride + start.0][k];
+        }
+        out
+    }
+}
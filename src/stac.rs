@@ -0,0 +1,287 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use gdal::raster::Buffer;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::canvas::Canvas;
+use crate::config::RemoteConfig;
+use crate::crs;
+use crate::dataset::{self, Resampling};
+use crate::error::Error;
+use crate::raster_info::RasterInfo;
+use crate::remote::{self, Profile};
+use crate::render;
+use crate::renderer::RgbRenderer;
+use crate::style::{Style, StyleQuery};
+use crate::tile_grid::Extent;
+
+/// Items fetched from the STAC API, across all result pages.
+const DEFAULT_LIMIT: usize = 500;
+/// Items rendered for a single tile, after which the remaining gaps are left empty.
+const MAX_TILE_ITEMS: usize = 16;
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+fn default_crs() -> String {
+    "EPSG:3857".to_string()
+}
+
+/// A STAC API search whose matching items are mosaicked into a dataset.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StacSearch {
+    /// The search endpoint, like `https://earth-search.aws.element84.com/v1/search`.
+    pub url: String,
+    #[serde(default)]
+    pub collections: Vec<String>,
+    /// The area to search in, as WGS84 `[west, south, east, north]`.
+    pub bbox: Option<[f64; 4]>,
+    /// A date or an interval like `2023-06-01T00:00:00Z/2023-06-30T23:59:59Z`.
+    pub datetime: Option<String>,
+    /// The maximum `eo:cloud_cover` of the items, in percent.
+    pub max_cloud_cover: Option<f64>,
+    /// The key of the item asset to read, like `visual`.
+    pub asset: String,
+    /// The CRS of the tile grid, which the assets are warped to.
+    #[serde(default = "default_crs")]
+    pub crs: String,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+/// The bounding box of an item and the asset to read from it.
+struct Footprint {
+    bbox: Extent,
+    cloud_cover: f64,
+    path: PathBuf,
+}
+
+impl StacSearch {
+    /// Builds the `GET` search URL.
+    fn search_url(&self) -> String {
+        let mut params = vec![format!("limit={}", self.limit.min(DEFAULT_LIMIT))];
+        if !self.collections.is_empty() {
+            params.push(format!(
+                "collections={}",
+                remote::encode_component(&self.collections.join(","))
+            ));
+        }
+        if let Some([west, south, east, north]) = self.bbox {
+            params.push(format!("bbox={},{},{},{}", west, south, east, north));
+        }
+        if let Some(datetime) = &self.datetime {
+            params.push(format!("datetime={}", remote::encode_component(datetime)));
+        }
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.url, separator, params.join("&"))
+    }
+
+    pub fn bounds(&self) -> Option<Extent> {
+        self.bbox.map(|[xmin, ymin, xmax, ymax]| Extent {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        })
+    }
+}
+
+fn intersects(a: &Extent, b: &Extent) -> bool {
+    a.xmin < b.xmax && b.xmin < a.xmax && a.ymin < b.ymax && b.ymin < a.ymax
+}
+
+/// A mosaic of the items matching a STAC search, with the item footprints fetched on first use
+/// and kept in memory.
+pub struct Mosaic {
+    search: StacSearch,
+    remote: RemoteConfig,
+    profile: Option<Profile>,
+    footprints: Mutex<Option<Arc<Vec<Footprint>>>>,
+}
+
+impl Mosaic {
+    pub fn new(
+        search: StacSearch,
+        remote: RemoteConfig,
+        profile: Option<Profile>,
+    ) -> Result<Self, Error> {
+        remote::check_allowed(&search.url, &remote)?;
+        crs::parse_srs(&search.crs)?;
+        Ok(Self {
+            search,
+            remote,
+            profile,
+            footprints: Mutex::new(None),
+        })
+    }
+
+    pub fn search(&self) -> &StacSearch {
+        &self.search
+    }
+
+    fn parse_item(&self, item: &Value) -> Option<Footprint> {
+        let cloud_cover = item["properties"]["eo:cloud_cover"].as_f64();
+        if let Some(max_cloud_cover) = self.search.max_cloud_cover {
+            if !cloud_cover.is_some_and(|cloud_cover| cloud_cover <= max_cloud_cover) {
+                return None;
+            }
+        }
+        let bbox = item["bbox"]
+            .as_array()?
+            .iter()
+            .map(Value::as_f64)
+            .collect::<Option<Vec<_>>>()?;
+        let bbox = match bbox[..] {
+            [xmin, ymin, xmax, ymax] | [xmin, ymin, _, xmax, ymax, _] => Extent {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            },
+            _ => return None,
+        };
+        let href = item["assets"][&self.search.asset]["href"].as_str()?;
+        let path = match remote::gdal_path(Path::new(href), &self.remote, self.profile.as_ref()) {
+            Ok(path) => path,
+            Err(e) => {
+                tracing::warn!("skipping STAC item asset {}: {}", href, e);
+                return None;
+            }
+        };
+        Some(Footprint {
+            bbox,
+            cloud_cover: cloud_cover.unwrap_or(0.0),
+            path,
+        })
+    }
+
+    /// Runs the search, following the result pages until the limit is reached.
+    fn fetch_footprints(&self) -> Result<Vec<Footprint>, Error> {
+        let mut footprints = Vec::new();
+        let mut fetched = 0;
+        let mut url = Some(self.search.search_url());
+        while let Some(page_url) = url.take() {
+            let page = remote::read_url(&page_url, &self.remote)?;
+            let page: Value = serde_json::from_slice(&page)
+                .map_err(|e| Error::BadRequest(format!("invalid STAC response: {}", e)))?;
+            let items = page["features"].as_array().cloned().unwrap_or_default();
+            fetched += items.len();
+            footprints.extend(items.iter().filter_map(|item| self.parse_item(item)));
+            if items.is_empty() || fetched >= self.search.limit {
+                break;
+            }
+            url = page["links"].as_array().and_then(|links| {
+                links
+                    .iter()
+                    .find(|link| {
+                        link["rel"] == "next" && link["method"].as_str().unwrap_or("GET") == "GET"
+                    })
+                    .and_then(|link| link["href"].as_str())
+                    .map(str::to_string)
+            });
+        }
+        // the least cloudy items end up on top
+        footprints.sort_by(|a, b| a.cloud_cover.total_cmp(&b.cloud_cover));
+        tracing::info!(
+            "found {} STAC items for {}",
+            footprints.len(),
+            self.search.url
+        );
+        Ok(footprints)
+    }
+
+    fn footprints(&self) -> Result<Arc<Vec<Footprint>>, Error> {
+        let mut footprints = self.footprints.lock().unwrap();
+        if let Some(footprints) = &*footprints {
+            return Ok(footprints.clone());
+        }
+        let fetched = Arc::new(self.fetch_footprints()?);
+        *footprints = Some(fetched.clone());
+        Ok(fetched)
+    }
+
+    /// Checks that the search can be run.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.footprints().map(drop)
+    }
+
+    /// Renders the items intersecting a tile, filling the transparent pixels of each one from
+    /// the ones below it.
+    pub fn render(
+        &self,
+        tile_extent: &Extent,
+        width: usize,
+        height: usize,
+        style: &StyleQuery,
+    ) -> Result<Canvas, Error> {
+        let grid_srs = crs::parse_srs(&self.search.crs)?;
+        let transform = crs::transform(&grid_srs, &crs::wgs84()?)?;
+        let extent_wgs84 = dataset::reproject_extent(tile_extent, &transform)?;
+        let footprints = self.footprints()?;
+
+        let pixels = width * height;
+        let mut bands = vec![vec![0u8; pixels]; 4];
+        let mut remaining = pixels;
+        let mut rendered = false;
+        let items = footprints
+            .iter()
+            .filter(|footprint| intersects(&footprint.bbox, &extent_wgs84))
+            .take(MAX_TILE_ITEMS);
+        for footprint in items {
+            let source = match dataset::open(&footprint.path) {
+                Ok(source) => source,
+                Err(e) => {
+                    tracing::warn!("cannot open {}: {}", footprint.path.display(), e);
+                    continue;
+                }
+            };
+            let warped = dataset::warp(source, &grid_srs, Resampling::Nearest)?;
+            let style = Style::parse(style, warped.raster_count())?;
+            let info = RasterInfo::read(&warped)?;
+            let out = match render::render(
+                &warped,
+                &info,
+                tile_extent,
+                width,
+                height,
+                &style,
+                &RgbRenderer,
+            ) {
+                Ok(out) => out,
+                Err(Error::OutsideBounds) => continue,
+                Err(e) => return Err(e),
+            };
+            rendered = true;
+            let item_bands = (1..=4)
+                .map(|band| Ok(out.rasterband(band)?.read_band_as::<u8>()?.data))
+                .collect::<Result<Vec<_>, Error>>()?;
+            for i in 0..pixels {
+                if bands[3][i] == 0 && item_bands[3][i] != 0 {
+                    for (band, item_band) in bands.iter_mut().zip(&item_bands) {
+                        band[i] = item_band[i];
+                    }
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+        if !rendered {
+            return Err(Error::OutsideBounds);
+        }
+
+        let out = Canvas::new(width, height)?;
+        for (i, band) in bands.into_iter().enumerate() {
+            out.rasterband(i as isize + 1)?.write(
+                (0, 0),
+                (width, height),
+                &Buffer::new((width, height), band),
+            )?;
+        }
+        Ok(out)
+    }
+}
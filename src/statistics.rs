@@ -0,0 +1,217 @@
+//! Per-band statistics and histograms of rasters, kept by GDAL in their `.aux.xml` files once
+//! computed, like the ones written by `gdalinfo -stats -hist`.
+
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::Json;
+use gdal::{Dataset, Metadata};
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::registry::{Kind, Registry};
+use crate::remote;
+use crate::workers;
+
+#[derive(Serialize)]
+pub struct Histogram {
+    /// The bounds of the buckets, spread evenly between them.
+    min: f64,
+    max: f64,
+    counts: Vec<u64>,
+}
+
+#[derive(Serialize)]
+pub struct BandStatistics {
+    band: isize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    stddev: f64,
+    /// Whether they were computed from an overview or a subset of the pixels.
+    approximate: bool,
+    histogram: Option<Histogram>,
+}
+
+#[derive(Serialize)]
+pub struct Statistics {
+    /// The bands whose pixels are all nodata are left out.
+    bands: Vec<BandStatistics>,
+}
+
+impl Statistics {
+    pub fn is_empty(&self) -> bool {
+        self.bands.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Only the statistics already computed.
+    Stored,
+    Approximate,
+    Exact,
+}
+
+/// A part of the progress reported to a callback, for one of the steps of a band.
+struct Progress<'a> {
+    report: &'a dyn Fn(f64) -> bool,
+    start: f64,
+    span: f64,
+}
+
+unsafe extern "C" fn report_progress(
+    complete: f64,
+    _message: *const c_char,
+    data: *mut c_void,
+) -> c_int {
+    let progress = &*(data as *const Progress);
+    c_int::from((progress.report)(progress.start + complete * progress.span))
+}
+
+fn band_statistics(
+    dataset: &Dataset,
+    band: isize,
+    mode: Mode,
+    progress: &Progress,
+) -> Option<BandStatistics> {
+    let c_band = unsafe { gdal_sys::GDALGetRasterBand(dataset.c_dataset(), band as _) };
+    let (mut min, mut max, mut mean, mut stddev) = (0.0, 0.0, 0.0, 0.0);
+    let half = Progress {
+        span: progress.span / 2.0,
+        ..*progress
+    };
+    let rv = unsafe {
+        match mode {
+            Mode::Stored => gdal_sys::GDALGetRasterStatistics(
+                c_band,
+                1,
+                0,
+                &mut min,
+                &mut max,
+                &mut mean,
+                &mut stddev,
+            ),
+            _ => gdal_sys::GDALComputeRasterStatistics(
+                c_band,
+                c_int::from(mode == Mode::Approximate),
+                &mut min,
+                &mut max,
+                &mut mean,
+                &mut stddev,
+                Some(report_progress),
+                &half as *const Progress as *mut c_void,
+            ),
+        }
+    };
+    // e.g. when every pixel is nodata, or they weren't computed yet
+    if rv != gdal_sys::CPLErr::CE_None {
+        return None;
+    }
+    let approximate = dataset
+        .rasterband(band)
+        .ok()
+        .and_then(|band| band.metadata_item("STATISTICS_APPROXIMATE", ""))
+        .is_some_and(|approximate| approximate == "YES");
+
+    let (mut histogram_min, mut histogram_max) = (0.0, 0.0);
+    let mut buckets: c_int = 0;
+    let mut counts: *mut gdal_sys::GUIntBig = ptr::null_mut();
+    let half = Progress {
+        start: progress.start + half.span,
+        ..half
+    };
+    let rv = unsafe {
+        gdal_sys::GDALGetDefaultHistogramEx(
+            c_band,
+            &mut histogram_min,
+            &mut histogram_max,
+            &mut buckets,
+            &mut counts,
+            c_int::from(mode != Mode::Stored),
+            Some(report_progress),
+            &half as *const Progress as *mut c_void,
+        )
+    };
+    let histogram = if rv == gdal_sys::CPLErr::CE_None && !counts.is_null() {
+        let counts = unsafe { std::slice::from_raw_parts(counts, buckets.max(0) as usize) };
+        Some(Histogram {
+            min: histogram_min,
+            max: histogram_max,
+            counts: counts.to_vec(),
+        })
+    } else {
+        None
+    };
+    unsafe { gdal_sys::VSIFree(counts as *mut c_void) };
+    Some(BandStatistics {
+        band,
+        min,
+        max,
+        mean,
+        stddev,
+        approximate,
+        histogram,
+    })
+}
+
+/// Computes the statistics and histograms of all the bands, reporting the progress between 0 and
+/// 1 to a callback, which stops it by returning `false`.
+pub fn compute(dataset: &Dataset, approximate: bool, report: &dyn Fn(f64) -> bool) -> Statistics {
+    let mode = if approximate {
+        Mode::Approximate
+    } else {
+        Mode::Exact
+    };
+    let count = dataset.raster_count();
+    let bands = (1..=count)
+        .filter_map(|band| {
+            let progress = Progress {
+                report,
+                start: (band - 1) as f64 / count as f64,
+                span: 1.0 / count as f64,
+            };
+            band_statistics(dataset, band, mode, &progress)
+        })
+        .collect();
+    Statistics { bands }
+}
+
+/// Returns the stored statistics, computing approximate ones for the bands without them.
+fn read(dataset: &Dataset) -> Statistics {
+    let ignore = |_| true;
+    let progress = Progress {
+        report: &ignore,
+        start: 0.0,
+        span: 1.0,
+    };
+    let bands = (1..=dataset.raster_count())
+        .filter_map(|band| {
+            band_statistics(dataset, band, Mode::Stored, &progress)
+                .or_else(|| band_statistics(dataset, band, Mode::Approximate, &progress))
+        })
+        .collect();
+    Statistics { bands }
+}
+
+pub async fn statistics(
+    extract::Path(file): extract::Path<String>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Json<Statistics>, Error> {
+    let entry = registry.get(&file)?;
+    if entry.kind != Kind::Raster {
+        return Err(Error::BadRequest(
+            "statistics are only available for rasters".to_string(),
+        ));
+    }
+    let statistics = workers::run(move || {
+        // not a pooled handle, so that GDAL saves the statistics it computes when closing it
+        let dataset = remote::with_path_options(&entry.path, || Dataset::open(&entry.path))?;
+        Ok(read(&dataset))
+    })
+    .await?;
+    Ok(Json(statistics))
+}
@@ -0,0 +1,292 @@
+#[cfg(feature = "scripts")]
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::point;
+#[cfg(feature = "scripts")]
+use crate::script::Script;
+use crate::speckle::Speckle;
+
+/// The range shown with `scale=db` when the style doesn't give one, which suits the calibrated
+/// backscatter of land.
+const DEFAULT_DECIBEL_RANGE: (f64, f64) = (-25.0, 0.0);
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct StyleQuery {
+    pub bands: Option<String>,
+    pub rescale: Option<String>,
+    /// `db` to style the values in decibels.
+    pub scale: Option<String>,
+    pub colormap: Option<String>,
+    pub sharpen: Option<String>,
+    pub blur: Option<String>,
+    /// A speckle filter like `lee,7`, for SAR backscatter.
+    pub speckle: Option<String>,
+    /// `true` to render the pan-sharpened bands of the datasets with a panchromatic band.
+    pub pansharpen: Option<String>,
+}
+
+impl StyleQuery {
+    /// Checks whether no styling parameters were passed.
+    pub fn is_default(&self) -> bool {
+        self.bands.is_none()
+            && self.rescale.is_none()
+            && self.scale.is_none()
+            && self.colormap.is_none()
+            && self.sharpen.is_none()
+            && self.blur.is_none()
+            && self.speckle.is_none()
+            && self.pansharpen.is_none()
+    }
+
+    /// Fills in the parameters that weren't passed from the defaults of a dataset.
+    pub fn with_defaults(&self, defaults: &StyleQuery) -> StyleQuery {
+        StyleQuery {
+            bands: self.bands.clone().or_else(|| defaults.bands.clone()),
+            rescale: self.rescale.clone().or_else(|| defaults.rescale.clone()),
+            scale: self.scale.clone().or_else(|| defaults.scale.clone()),
+            colormap: self.colormap.clone().or_else(|| defaults.colormap.clone()),
+            sharpen: self.sharpen.clone().or_else(|| defaults.sharpen.clone()),
+            blur: self.blur.clone().or_else(|| defaults.blur.clone()),
+            speckle: self.speckle.clone().or_else(|| defaults.speckle.clone()),
+            pansharpen: self
+                .pansharpen
+                .clone()
+                .or_else(|| defaults.pansharpen.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Colormap {
+    Gray,
+    Viridis,
+    Magma,
+    Terrain,
+}
+
+impl Colormap {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gray" => Some(Colormap::Gray),
+            "viridis" => Some(Colormap::Viridis),
+            "magma" => Some(Colormap::Magma),
+            "terrain" => Some(Colormap::Terrain),
+            _ => None,
+        }
+    }
+
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Gray => &[[0, 0, 0], [255, 255, 255]],
+            Colormap::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+            Colormap::Magma => &[
+                [0, 0, 4],
+                [81, 18, 124],
+                [183, 55, 121],
+                [252, 137, 97],
+                [252, 253, 191],
+            ],
+            Colormap::Terrain => &[
+                [51, 51, 153],
+                [0, 153, 255],
+                [0, 204, 102],
+                [255, 255, 153],
+                [128, 92, 84],
+                [255, 255, 255],
+            ],
+        }
+    }
+
+    /// Maps a value in the `0..=255` range to a color by interpolating between the stops.
+    pub fn color(self, value: u8) -> [u8; 3] {
+        let stops = self.stops();
+        let position = value as f64 / 255.0 * (stops.len() - 1) as f64;
+        let i = (position.floor() as usize).min(stops.len() - 2);
+        let t = position - i as f64;
+        let mut color = [0; 3];
+        for (c, (&a, &b)) in color
+            .iter_mut()
+            .zip(stops[i].iter().zip(stops[i + 1].iter()))
+        {
+            *c = (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        }
+        color
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Style {
+    pub bands: Vec<isize>,
+    pub rescale: Option<(f64, f64)>,
+    /// Whether the values are converted to decibels before rescaling them, like the backscatter
+    /// of SAR products.
+    pub decibels: bool,
+    pub colormap: Option<Colormap>,
+    /// Applied to the values read, before the script and the styling.
+    pub speckle: Option<Speckle>,
+    /// The script of the dataset, applied to the values before styling them.
+    #[cfg(feature = "scripts")]
+    pub script: Option<Arc<Script>>,
+}
+
+impl Style {
+    pub fn default_for(band_count: isize) -> Self {
+        let bands = if band_count >= 3 {
+            vec![1, 2, 3]
+        } else {
+            vec![1]
+        };
+        Self {
+            bands,
+            rescale: None,
+            decibels: false,
+            colormap: None,
+            speckle: None,
+            #[cfg(feature = "scripts")]
+            script: None,
+        }
+    }
+
+    pub fn parse(query: &StyleQuery, band_count: isize) -> Result<Self, Error> {
+        let mut style = Self::default_for(band_count);
+        if let Some(bands) = &query.bands {
+            style.bands = point::parse_bands(bands, band_count)?;
+            if style.bands.len() != 1 && style.bands.len() != 3 {
+                return Err(Error::BadRequest(
+                    "either one or three bands must be selected".to_string(),
+                ));
+            }
+        }
+        if let Some(rescale) = &query.rescale {
+            style.rescale = match rescale.split_once(',') {
+                Some((min, max)) => match (min.trim().parse(), max.trim().parse()) {
+                    (Ok(min), Ok(max)) if min < max => Some((min, max)),
+                    _ => None,
+                },
+                None => None,
+            };
+            if style.rescale.is_none() {
+                return Err(Error::BadRequest(format!("invalid rescale: {}", rescale)));
+            }
+        }
+        if let Some(scale) = &query.scale {
+            style.decibels = match scale.as_str() {
+                "db" => true,
+                "linear" => false,
+                _ => return Err(Error::BadRequest(format!("unknown scale: {}", scale))),
+            };
+            if style.decibels && style.rescale.is_none() {
+                style.rescale = Some(DEFAULT_DECIBEL_RANGE);
+            }
+        }
+        if let Some(colormap) = &query.colormap {
+            style.colormap = Some(
+                Colormap::from_name(colormap)
+                    .ok_or_else(|| Error::BadRequest(format!("unknown colormap: {}", colormap)))?,
+            );
+            if style.bands.len() != 1 {
+                return Err(Error::BadRequest(
+                    "colormaps can only be applied to a single band".to_string(),
+                ));
+            }
+        }
+        if let Some(speckle) = &query.speckle {
+            style.speckle = Some(Speckle::parse(speckle)?);
+        }
+        Ok(style)
+    }
+
+    /// Returns a suffix identifying the style in cache keys, empty for the default one.
+    ///
+    /// The style is checked first, except for the bands being in the dataset, so that an invalid
+    /// one never names the tile of a valid one.
+    pub fn cache_key(query: &StyleQuery) -> Result<String, Error> {
+        // a single band is the default, for the colormap check
+        let band_count = if query.bands.is_some() { isize::MAX } else { 1 };
+        Self::parse(query, band_count)?;
+        Filter::parse(query)?;
+        let mut key = String::new();
+        for (name, value) in [
+            ("bands", &query.bands),
+            ("rescale", &query.rescale),
+            ("scale", &query.scale),
+            ("colormap", &query.colormap),
+            ("sharpen", &query.sharpen),
+            ("blur", &query.blur),
+            ("speckle", &query.speckle),
+            ("pansharpen", &query.pansharpen),
+        ]
+        .iter()
+        {
+            if let Some(value) = value {
+                if !value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ",.-+".contains(c))
+                {
+                    return Err(Error::BadRequest(format!("invalid style: {}", value)));
+                }
+                key.push_str(&format!("_{}={}", name, value));
+            }
+        }
+        Ok(key)
+    }
+
+    pub fn colormap_lut(&self) -> Option<Vec<[u8; 3]>> {
+        self.colormap
+            .map(|colormap| (0..=255).map(|v| colormap.color(v)).collect())
+    }
+
+    /// Scales a raw band value to the `0..=255` output range.
+    pub fn scale(&self, value: f64) -> u8 {
+        // non-positive values go to the floor
+        let value = if self.decibels {
+            10.0 * value.log10()
+        } else {
+            value
+        };
+        let value = match self.rescale {
+            Some((min, max)) => (value - min) / (max - min) * 255.0,
+            None => value,
+        };
+        value.round().clamp(0.0, 255.0) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Style, StyleQuery};
+
+    fn cache_key(query: &str) -> Option<String> {
+        let query = serde_urlencoded::from_str::<StyleQuery>(query).unwrap();
+        Style::cache_key(&query).ok()
+    }
+
+    #[test]
+    fn cache_keys() {
+        assert_eq!(cache_key("").as_deref(), Some(""));
+        assert_eq!(
+            cache_key("bands=3&colormap=viridis").as_deref(),
+            Some("_bands=3_colormap=viridis")
+        );
+        assert_eq!(
+            cache_key("colormap=viridis").as_deref(),
+            Some("_colormap=viridis")
+        );
+        assert_eq!(cache_key("sharpen=1").as_deref(), Some("_sharpen=1"));
+        assert_eq!(cache_key("scale=db").as_deref(), Some("_scale=db"));
+        // used to name the same tiles as the ones above
+        assert_eq!(cache_key("speckle=h1"), None);
+        assert_eq!(cache_key("speckle=cdb"), None);
+        assert_eq!(cache_key("colormap=../x"), None);
+    }
+}
@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::http::header;
+use axum::response::IntoResponse;
+use gdal::Dataset;
+use serde::Deserialize;
+
+use crate::cache::{self, Claim};
+use crate::config::Config;
+use crate::dataset;
+use crate::error::Error;
+use crate::raster_info::RasterInfo;
+use crate::registry::Registry;
+use crate::render;
+use crate::renderer::RgbRenderer;
+use crate::style::Style;
+use crate::workers;
+use crate::Png;
+
+const DEFAULT_SIZE: usize = 512;
+const MAX_SIZE: usize = 1024;
+
+#[derive(Deserialize)]
+pub struct ThumbnailQuery {
+    size: Option<usize>,
+}
+
+/// Opens the coarsest overview level whose largest dimension is still at least `size` pixels.
+fn open_overview(path: &Path, size: usize) -> Result<Dataset, Error> {
+    let dataset = dataset::open(path)?;
+    let band = dataset.rasterband(1)?;
+    let mut level = None;
+    for i in 0..band.overview_count()? {
+        let (width, height) = band.overview(i as isize)?.size();
+        if width.max(height) >= size {
+            level = Some(i);
+        }
+    }
+    match level {
+        Some(level) => {
+            let option = format!("OVERVIEW_LEVEL={}", level);
+            dataset::open_with_options(path, &[&option])
+        }
+        None => Ok(dataset),
+    }
+}
+
+/// Renders a thumbnail and caches it, unless another instance sharing the cache does it first.
+fn render_thumbnail(
+    path: &Path,
+    size: usize,
+    file_name: &str,
+    config: &Config,
+) -> Result<Vec<u8>, Error> {
+    let _lock = match config.cache_lock_wait {
+        Some(wait) => match cache::claim(Path::new(file_name), config.cache_verify_crc, wait)? {
+            Claim::Cached(png) => return Ok(png),
+            Claim::Render(lock) => lock,
+        },
+        None => None,
+    };
+    let dataset = open_overview(path, size)?;
+    let info = RasterInfo::read(&dataset)?;
+    let extent = info.extent.clone();
+    let aspect = (extent.xmax - extent.xmin) / (extent.ymax - extent.ymin);
+    let (width, height) = if aspect >= 1.0 {
+        (size, ((size as f64 / aspect).round() as usize).max(1))
+    } else {
+        (((size as f64 * aspect).round() as usize).max(1), size)
+    };
+    let style = Style::default_for(dataset.raster_count());
+    let out = render::render(
+        &dataset,
+        &info,
+        &extent,
+        width,
+        height,
+        &style,
+        &RgbRenderer,
+    )?;
+    let png = render::encode_png(&out)?;
+    cache::write(Path::new(file_name), &png)?;
+    Ok(png)
+}
+
+pub async fn thumbnail(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<ThumbnailQuery>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<impl IntoResponse, Error> {
+    let path = registry.resolve(&file)?;
+    let size = query.size.unwrap_or(DEFAULT_SIZE);
+    if !(1..=MAX_SIZE).contains(&size) {
+        return Err(Error::BadRequest(format!(
+            "size must be between 1 and {}",
+            MAX_SIZE
+        )));
+    }
+
+    let file_name = format!(
+        "{}/thumbnails/{}{}.png",
+        cache::dir().display(),
+        cache::entry_prefix(&registry.cache_name(&file)),
+        size
+    );
+    let verify_crc = config.cache_verify_crc;
+    let png = match cache::read_async(Path::new(&file_name), verify_crc).await? {
+        Some(png) => png,
+        None => {
+            let config = config.0.clone();
+            workers::run(move || render_thumbnail(&path, size, &file_name, &config)).await?
+        }
+    };
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=86400")],
+        Png(png.into()),
+    ))
+}
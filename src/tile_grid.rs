@@ -1,5 +1,21 @@
+use std::ops::RangeInclusive;
+
 use serde::Serialize;
 
+use crate::crs;
+use crate::error::Error;
+
+/// The deepest zoom level of the tiles, whose indices still fit in `u32`.
+pub const MAX_ZOOM: u8 = 30;
+
+/// Checks that a tile is in the grid, before flipping its row or computing its extent.
+pub fn check_tile(z: u8, x: u32, y: u32) -> Result<(), Error> {
+    if z > MAX_ZOOM || x >= 1 << z || y >= 1 << z {
+        return Err(Error::OutsideBounds);
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct Extent {
     pub xmin: f64,
@@ -11,11 +27,44 @@ pub struct Extent {
 #[derive(Clone)]
 pub struct TileGrid {
     extent: Extent,
+    /// The CRS of the grid, which the rasters in other ones are reprojected to. The rasters are
+    /// assumed to be in the CRS of the grid if it's not known.
+    crs: Option<String>,
+    /// The WKT of `crs`, set by `resolve_crs`.
+    wkt: Option<String>,
 }
 
 impl TileGrid {
     pub fn new(extent: Extent) -> Self {
-        Self { extent }
+        Self {
+            extent,
+            crs: None,
+            wkt: None,
+        }
+    }
+
+    pub fn with_crs(mut self, crs: String) -> Self {
+        self.crs = Some(crs);
+        self.wkt = None;
+        self
+    }
+
+    pub fn crs(&self) -> Option<&str> {
+        self.crs.as_deref()
+    }
+
+    /// Parses the CRS of the grid, once before rendering tiles, instead of for each of them.
+    pub fn resolve_crs(&mut self) -> Result<(), Error> {
+        self.wkt = match &self.crs {
+            Some(crs) => Some(crs::parse_srs(crs)?.to_wkt()?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Returns the WKT of the CRS of the grid, if it's known and was resolved.
+    pub fn wkt(&self) -> Option<&str> {
+        self.wkt.as_deref()
     }
 
     pub fn tile_extent(&self, x: u32, y: u32, z: u8) -> Extent {
@@ -30,6 +79,34 @@ impl TileGrid {
         }
     }
 
+    pub fn extent(&self) -> &Extent {
+        &self.extent
+    }
+
+    /// Returns the columns and rows of the tiles intersecting an extent, if any.
+    pub fn tile_range(
+        &self,
+        extent: &Extent,
+        z: u8,
+    ) -> Option<(RangeInclusive<u32>, RangeInclusive<u32>)> {
+        let count = (1u64 << z) as f64;
+        let tile_w = (self.extent.xmax - self.extent.xmin) / count;
+        let tile_h = (self.extent.ymax - self.extent.ymin) / count;
+        let index = |v: f64| v.floor().clamp(0.0, count - 1.0) as u32;
+        let (xmin, xmax) = (
+            (extent.xmin - self.extent.xmin) / tile_w,
+            (extent.xmax - self.extent.xmin) / tile_w,
+        );
+        let (ymin, ymax) = (
+            (extent.ymin - self.extent.ymin) / tile_h,
+            (extent.ymax - self.extent.ymin) / tile_h,
+        );
+        if xmax <= 0.0 || ymax <= 0.0 || xmin >= count || ymin >= count {
+            return None;
+        }
+        Some((index(xmin)..=index(xmax), index(ymin)..=index(ymax)))
+    }
+
     pub fn web_mercator() -> Self {
         let origin_shift = 20_037_508.342_789_248;
         Self::new(Extent {
@@ -38,5 +115,6 @@ impl TileGrid {
             xmax: origin_shift,
             ymax: origin_shift,
         })
+        .with_crs("EPSG:3857".to_string())
     }
 }
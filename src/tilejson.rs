@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension, Host};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+
+use crate::archive;
+use crate::compressed;
+use crate::config::Config;
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::mbtiles;
+use crate::preview;
+use crate::raster_info::{self, RasterInfo};
+use crate::registry::{Entry, Kind, Registry};
+use crate::tile_grid::Extent;
+use crate::virtual_host::VirtualHost;
+use crate::workers;
+
+const MAX_ZOOM: u8 = 30;
+
+#[derive(Serialize)]
+pub struct TileJson {
+    tilejson: &'static str,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    scheme: &'static str,
+    tiles: Vec<String>,
+    minzoom: u8,
+    maxzoom: u8,
+    /// In WGS 84.
+    pub bounds: [f64; 4],
+    center: [f64; 3],
+}
+
+/// Returns the URL clients used to reach the server, honoring `X-Forwarded-Proto`.
+pub fn base_url(host: &str, headers: &HeaderMap) -> String {
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("http");
+    format!("{}://{}", scheme, host)
+}
+
+/// Returns the URL the data routes of a registry are reached at, which have no prefix on the
+/// hosts of its tenant.
+fn tenant_url(host: &str, headers: &HeaderMap, virtual_host: bool, registry: &Registry) -> String {
+    let base_url = base_url(host, headers);
+    if virtual_host {
+        base_url
+    } else {
+        base_url + &registry.url_prefix()
+    }
+}
+
+/// Returns the zoom levels between which the tiles go from covering the whole dataset to
+/// matching its resolution, assuming it's in the tile grid CRS like the renderer does.
+pub fn zoom_range(info: &RasterInfo, config: &Config) -> (u8, u8) {
+    let grid = config.tile_grid.extent();
+    let grid_width = grid.xmax - grid.xmin;
+    let extent_width = (info.extent.xmax - info.extent.xmin).max(f64::MIN_POSITIVE);
+    let pixel_size = info.geo_transform[1].abs().max(f64::MIN_POSITIVE);
+    let zoom = |v: f64| v.clamp(0.0, MAX_ZOOM as f64) as u8;
+    let minzoom = zoom((grid_width / extent_width).log2().floor());
+    let maxzoom = zoom(
+        (grid_width / (config.tile_width as f64 * pixel_size))
+            .log2()
+            .ceil(),
+    );
+    (minzoom, maxzoom.max(minzoom))
+}
+
+/// Returns the WGS84 bounds of a raster dataset and the zoom levels it's suited for.
+fn raster_bounds(path: &Path, config: &Config) -> Result<(Extent, (u8, u8)), Error> {
+    let dataset = dataset::open(path)?;
+    let info = raster_info::get(path, &dataset)?;
+    let transform = crs::transform(&dataset::spatial_ref(&dataset)?, &crs::wgs84()?)?;
+    let bounds = dataset::reproject_extent(&info.extent, &transform)?;
+    Ok((bounds, zoom_range(&info, config)))
+}
+
+/// The bounds of the Web Mercator grid, for datasets that don't know theirs.
+fn world_bounds() -> Extent {
+    Extent {
+        xmin: -180.0,
+        ymin: -85.051_128_779_806_59,
+        xmax: 180.0,
+        ymax: 85.051_128_779_806_59,
+    }
+}
+
+pub fn build_tilejson(
+    name: &str,
+    entry: Entry,
+    base_url: &str,
+    config: &Config,
+) -> Result<TileJson, Error> {
+    let mut info = entry.info.clone();
+    let (bounds, (minzoom, maxzoom)) = match entry.kind {
+        Kind::Raster | Kind::GeoPackage => raster_bounds(&entry.path, config)?,
+        Kind::MbTiles => {
+            let connection = archive::open_sqlite(&entry.path)?;
+            let mut metadata = mbtiles::metadata(&connection)?;
+            info.title = info.title.or_else(|| metadata.remove("name"));
+            info.description = info.description.or_else(|| metadata.remove("description"));
+            info.attribution = info.attribution.or_else(|| metadata.remove("attribution"));
+            let bounds = match metadata.get("bounds") {
+                Some(bounds) => preview::parse_bbox(bounds)?,
+                None => world_bounds(),
+            };
+            let zoom_range = mbtiles::zoom_range(&connection)?.unwrap_or((0, 0));
+            (bounds, zoom_range)
+        }
+        Kind::PmTiles => {
+            let archive = entry.pmtiles()?;
+            let metadata = archive.metadata()?;
+            let text = |key: &str| metadata[key].as_str().map(str::to_string);
+            info.title = info.title.or_else(|| text("name"));
+            info.description = info.description.or_else(|| text("description"));
+            info.attribution = info.attribution.or_else(|| text("attribution"));
+            let header = archive.header();
+            (header.bounds.clone(), (header.min_zoom, header.max_zoom))
+        }
+        Kind::Stac => {
+            let bounds = entry
+                .mosaic
+                .as_ref()
+                .and_then(|mosaic| mosaic.search().bounds())
+                .unwrap_or_else(world_bounds);
+            (bounds, (0, 22))
+        }
+        Kind::Wms => (world_bounds(), (0, 22)),
+    };
+    Ok(TileJson {
+        tilejson: "2.2.0",
+        name: info.title.unwrap_or_else(|| name.to_string()),
+        description: info.description,
+        attribution: info.attribution,
+        license: info.license,
+        // rows are counted from the bottom of the grid unless `reverse_y` is set
+        scheme: if config.reverse_y { "xyz" } else { "tms" },
+        tiles: vec![format!("{}/tile/{}/{{z}}/{{x}}/{{y}}", base_url, name)],
+        minzoom,
+        maxzoom,
+        bounds: [bounds.xmin, bounds.ymin, bounds.xmax, bounds.ymax],
+        center: [
+            (bounds.xmin + bounds.xmax) / 2.0,
+            (bounds.ymin + bounds.ymax) / 2.0,
+            minzoom as f64,
+        ],
+    })
+}
+
+pub async fn tilejson(
+    extract::Path(file): extract::Path<String>,
+    Host(host): Host,
+    headers: HeaderMap,
+    virtual_host: Option<Extension<VirtualHost>>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Json<TileJson>, Error> {
+    let entry = registry.get(&file)?;
+    let base_url = tenant_url(&host, &headers, virtual_host.is_some(), &registry);
+    let tilejson = workers::run(move || build_tilejson(&file, entry, &base_url, &config)).await?;
+    Ok(Json(tilejson))
+}
+
+pub async fn catalog(
+    Host(host): Host,
+    headers: HeaderMap,
+    virtual_host: Option<Extension<VirtualHost>>,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Response, Error> {
+    let base_url = tenant_url(&host, &headers, virtual_host.is_some(), &registry);
+    let document = workers::run(move || {
+        let mut catalog = Vec::new();
+        for (name, entry) in registry.entries() {
+            match build_tilejson(&name, entry, &base_url, &config) {
+                Ok(tilejson) => catalog.push(tilejson),
+                Err(e) => tracing::warn!("skipping {} from catalog: {}", name, e),
+            }
+        }
+        compressed::encode(format!("catalog {}", base_url), &catalog)
+    })
+    .await?;
+    Ok(document.respond(&headers))
+}
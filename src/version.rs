@@ -0,0 +1,30 @@
+use axum::Json;
+use serde::Serialize;
+
+/// Cargo features this binary was built with.
+fn features() -> Vec<&'static str> {
+    IntoIterator::into_iter([
+        ("scripts", cfg!(feature = "scripts")),
+        ("sentry", cfg!(feature = "sentry")),
+    ])
+    .filter(|&(_, enabled)| enabled)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+#[derive(Serialize)]
+pub struct Version {
+    version: &'static str,
+    commit: &'static str,
+    gdal: String,
+    features: Vec<&'static str>,
+}
+
+pub async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("GIT_COMMIT"),
+        gdal: gdal::version_info("RELEASE_NAME"),
+        features: features(),
+    })
+}
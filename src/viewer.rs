@@ -0,0 +1,7 @@
+use axum::response::Html;
+
+const VIEWER: &str = include_str!("../static/viewer.html");
+
+pub async fn viewer() -> Html<&'static str> {
+    Html(VIEWER)
+}
@@ -0,0 +1,61 @@
+//! Routing of the requests to the tenants by their `Host` header, as an alternative to the
+//! `/t/<tenant>` prefixes, so that each catalogue can have its own domain.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::OriginalUri;
+use axum::http::uri::{PathAndQuery, Uri};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Marks the requests routed to a tenant by their host, whose URLs don't need the prefix.
+#[derive(Clone, Copy)]
+pub struct VirtualHost;
+
+/// Maps host names, without ports and in lowercase, to the tenants served on them.
+pub struct VirtualHosts(pub BTreeMap<String, String>);
+
+/// Returns the name in a `Host` header, without the port.
+fn host_name(host: &str) -> String {
+    let name = match host.strip_prefix('[') {
+        // like `[::1]:3000`
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.to_ascii_lowercase()
+}
+
+/// Sends the requests for the hosts of a tenant to its routes, by prefixing their path with
+/// `/t/<tenant>`. This runs before the router, so that it routes them by the new path.
+pub async fn route(mut request: Request<Body>, next: Next<Body>) -> Response {
+    let tenant = request
+        .extensions()
+        .get::<Arc<VirtualHosts>>()
+        .and_then(|hosts| {
+            let host = request.headers().get(header::HOST)?.to_str().ok()?;
+            hosts.0.get(&host_name(host)).cloned()
+        });
+    if let Some(tenant) = tenant {
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        let mut parts = request.uri().clone().into_parts();
+        parts.path_and_query = match format!("/t/{}{}", tenant, path).parse::<PathAndQuery>() {
+            Ok(path) => Some(path),
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        let uri = match Uri::from_parts(parts) {
+            Ok(uri) => uri,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        // so that the forwarded tile requests keep the prefix, since the host isn't forwarded
+        request.extensions_mut().insert(OriginalUri(uri.clone()));
+        request.extensions_mut().insert(VirtualHost);
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
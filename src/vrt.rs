@@ -0,0 +1,209 @@
+use std::ffi::CString;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::UNIX_EPOCH;
+
+use gdal::Dataset;
+
+use crate::cache;
+use crate::config::Pansharpen;
+use crate::dataset::escape_xml;
+use crate::error::Error;
+use crate::registry::{self, Kind};
+use crate::remote;
+
+/// Returns where the generated VRT files are kept, along with the lists of files they were
+/// built from.
+fn vrt_dir() -> PathBuf {
+    cache::dir().join("vrt")
+}
+
+/// Describes a source file by its path, size and modification time, to notice when it changes.
+fn describe(path: &Path) -> String {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        // remote files are assumed not to change
+        Err(_) => return path.display().to_string(),
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs())
+        .unwrap_or_default();
+    format!("{}\t{}\t{}", path.display(), metadata.len(), modified)
+}
+
+/// Lists the rasters matching a glob pattern, or all of them in a directory.
+fn list_sources(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let pattern = if Path::new(pattern).is_dir() {
+        format!("{}/*", pattern.trim_end_matches('/'))
+    } else {
+        pattern.to_string()
+    };
+    let paths = glob::glob(&pattern)
+        .map_err(|e| Error::BadRequest(format!("invalid pattern {}: {}", pattern, e)))?;
+    let mut sources = paths
+        .filter_map(Result::ok)
+        .filter(|path| {
+            path.is_file()
+                && registry::is_supported(path)
+                && Kind::from_path(path) == Kind::Raster
+                && path.extension().is_none_or(|extension| extension != "vrt")
+        })
+        .collect::<Vec<_>>();
+    sources.sort();
+    Ok(sources)
+}
+
+/// Builds a VRT mosaic of the rasters matching a glob pattern or in a directory, unless the
+/// one built before is still up to date. Returns its path, whether it was rebuilt, and the
+/// rasters in it.
+pub fn mosaic(name: &str, pattern: &str) -> Result<(PathBuf, bool, Vec<PathBuf>), Error> {
+    let sources = list_sources(pattern)?;
+    if sources.is_empty() {
+        return Err(Error::BadRequest(format!("no rasters match {}", pattern)));
+    }
+    let (path, rebuilt) = build(name, &sources)?;
+    Ok((path, rebuilt, sources))
+}
+
+/// Builds a VRT mosaic of some rasters, with the later ones on top, unless the one built before
+/// is still up to date. Returns its path and whether it was rebuilt.
+pub fn build(name: &str, sources: &[PathBuf]) -> Result<(PathBuf, bool), Error> {
+    let vrt_path = vrt_dir().join(format!("{}.vrt", name));
+    let list_path = vrt_dir().join(format!("{}.files", name));
+    let list = sources
+        .iter()
+        .map(|source| describe(source))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if vrt_path.exists() && std::fs::read_to_string(&list_path).is_ok_and(|old| old == list) {
+        return Ok((vrt_path, false));
+    }
+
+    std::fs::create_dir_all(vrt_dir())?;
+    let names = sources
+        .iter()
+        .map(|source| CString::new(source.to_string_lossy().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut name_ptrs = names.iter().map(|name| name.as_ptr()).collect::<Vec<_>>();
+    name_ptrs.push(ptr::null());
+    let c_vrt_path = CString::new(vrt_path.to_string_lossy().as_bytes())?;
+    let c_dataset = remote::with_path_options(&sources[0], || unsafe {
+        gdal_sys::GDALBuildVRT(
+            c_vrt_path.as_ptr(),
+            names.len() as _,
+            ptr::null_mut(),
+            name_ptrs.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+        )
+    });
+    if c_dataset.is_null() {
+        return Err(Error::last_cpl_error(gdal_sys::CPLErr::CE_Failure));
+    }
+    // closing the dataset writes it to disk
+    drop(unsafe { Dataset::from_c_dataset(c_dataset) });
+    std::fs::write(&list_path, list)?;
+    tracing::info!("built {} from {} rasters", vrt_path.display(), names.len());
+    Ok((vrt_path, true))
+}
+
+/// Writes a pan-sharpened VRT of the bands of a multispectral raster, using the weighted Brovey
+/// method of GDAL, unless the one written before is still up to date. Returns its path and
+/// whether it was rewritten.
+pub fn pansharpen(
+    name: &str,
+    multispectral: &Path,
+    panchromatic: &Path,
+    options: &Pansharpen,
+) -> Result<(PathBuf, bool), Error> {
+    let bands = match &options.bands {
+        Some(bands) => bands.clone(),
+        None => {
+            let dataset =
+                remote::with_path_options(multispectral, || Dataset::open(multispectral))?;
+            (1..=dataset.raster_count()).collect()
+        }
+    };
+    if bands.is_empty() {
+        return Err(Error::BadRequest(
+            "no multispectral bands to sharpen".to_string(),
+        ));
+    }
+    let weights = match &options.weights {
+        Some(weights) if weights.len() != bands.len() => {
+            return Err(Error::BadRequest(format!(
+                "expected {} pan-sharpening weights, got {}",
+                bands.len(),
+                weights.len()
+            )))
+        }
+        Some(weights) => weights.clone(),
+        None => vec![1.0 / bands.len() as f64; bands.len()],
+    };
+
+    let vrt_path = vrt_dir().join(format!("{}.pansharpened.vrt", name));
+    let list_path = vrt_dir().join(format!("{}.pansharpened.files", name));
+    let list = format!(
+        "{}\n{}\n{:?}",
+        describe(multispectral),
+        describe(panchromatic),
+        options
+    );
+    if vrt_path.exists() && std::fs::read_to_string(&list_path).is_ok_and(|old| old == list) {
+        return Ok((vrt_path, false));
+    }
+
+    let weights = weights
+        .iter()
+        .map(|weight| weight.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut vrt = format!(
+        r#"<VRTDataset subClass="VRTPansharpenedDataset"><PansharpeningOptions><Algorithm>WeightedBrovey</Algorithm><AlgorithmOptions><Weights>{}</Weights></AlgorithmOptions>"#,
+        weights
+    );
+    let _ = write!(
+        vrt,
+        r#"<PanchroBand><SourceFilename relativeToVRT="0">{}</SourceFilename><SourceBand>{}</SourceBand></PanchroBand>"#,
+        escape_xml(&panchromatic.to_string_lossy()),
+        options.band
+    );
+    let multispectral_path = escape_xml(&multispectral.to_string_lossy());
+    for (i, band) in bands.iter().enumerate() {
+        let _ = write!(
+            vrt,
+            r#"<SpectralBand dstBand="{}"><SourceFilename relativeToVRT="0">{}</SourceFilename><SourceBand>{}</SourceBand></SpectralBand>"#,
+            i + 1,
+            multispectral_path,
+            band
+        );
+    }
+    vrt.push_str("</PansharpeningOptions></VRTDataset>");
+
+    std::fs::create_dir_all(vrt_dir())?;
+    std::fs::write(&vrt_path, vrt)?;
+    // GDAL checks that the rasters overlap and that the bands exist when opening it
+    remote::with_path_options(multispectral, || Dataset::open(&vrt_path))?;
+    std::fs::write(&list_path, list)?;
+    tracing::info!("wrote {}", vrt_path.display());
+    Ok((vrt_path, true))
+}
+
+/// Removes the VRTs generated for a dataset, along with their lists of files. Returns the
+/// number of files removed.
+pub fn remove(name: &str) -> io::Result<usize> {
+    let mut removed = 0;
+    for suffix in ["vrt", "files", "pansharpened.vrt", "pansharpened.files"] {
+        match std::fs::remove_file(vrt_dir().join(format!("{}.{}", name, suffix))) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
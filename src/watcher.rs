@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::task;
+
+use crate::admin;
+use crate::dataset_pool::DatasetPool;
+use crate::prune;
+use crate::raster_info;
+use crate::registry::Registry;
+use crate::workers;
+
+/// Periodically scans the data directory, registering new datasets and dropping the ones whose
+/// files were removed. The datasets whose files were modified lose their open handles, cached
+/// properties and cached tiles, so that the new version is served.
+///
+/// This polls instead of relying on file system notifications, which aren't delivered for
+/// network shares and some container volumes.
+pub async fn watch(registry: Arc<Registry>, pool: Arc<DatasetPool>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // files still being copied are picked up on the next scan
+        let settled = SystemTime::now() - interval;
+        let result = {
+            let registry = registry.clone();
+            workers::run(move || Ok(registry.sync(settled)?)).await
+        };
+        let changes = match result {
+            Ok(changes) => changes,
+            Err(e) => {
+                tracing::warn!("cannot scan data directory: {}", e);
+                continue;
+            }
+        };
+        for name in changes.added {
+            tracing::info!("registered dataset {}", name);
+        }
+        for (name, path) in changes.modified {
+            tracing::info!("dataset {} changed, dropping its cached tiles", name);
+            pool.evict(&path);
+            raster_info::forget(&path);
+            if let Ok(entry) = registry.get(&name) {
+                entry.forget_archive();
+            }
+            let cache_name = registry.cache_name(&name);
+            let result = task::spawn_blocking(move || admin::purge_cache(Some(&cache_name))).await;
+            if let Err(e) = result.unwrap_or_else(|e| Err(e.into())) {
+                tracing::warn!("cannot purge the cache of {}: {}", name, e);
+            }
+        }
+        let mut removed = Vec::new();
+        for (name, path) in changes.removed {
+            tracing::info!("removed dataset {}", name);
+            // so that the handles don't keep the deleted file on the disk
+            pool.evict(&path);
+            raster_info::forget(&path);
+            removed.push(name);
+        }
+        prune::schedule(registry.clone(), removed);
+    }
+}
@@ -0,0 +1,384 @@
+//! A basic OGC WCS 2.0 `GetCoverage`, in the KVP encoding, for downloading the values of the
+//! rasters as GeoTIFFs instead of rendered images.
+//!
+//! The coverages are the raster datasets, by name. Requests can trim them with `subset`, in
+//! `subsettingCrs` if given, reproject them to `outputCrs`, and resample them with `scaleFactor`
+//! or `scaleSize`, from the scaling extension. The other operations aren't implemented.
+
+use std::ffi::CString;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::body::{self, Full};
+use axum::extract::{Extension, RawQuery};
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use gdal::raster::{Buffer, RasterCreationOption};
+use gdal::{vsi, Dataset, Driver};
+
+use crate::config::{Config, OutputLimits};
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::preview;
+use crate::raster_info::RasterInfo;
+use crate::registry::{Kind, Registry};
+use crate::render;
+use crate::tile_grid::Extent;
+use crate::workers;
+
+/// The only output format, which is also the default one.
+const FORMAT: &str = "image/tiff";
+
+/// The axis labels taken for the horizontal and the vertical axes.
+const X_AXES: &[&str] = &["x", "e", "easting", "long", "lon", "longitude", "i"];
+const Y_AXES: &[&str] = &["y", "n", "northing", "lat", "latitude", "j"];
+
+#[derive(Clone, Copy)]
+enum Axis {
+    X,
+    Y,
+}
+
+enum Scale {
+    /// The resolution of the dataset.
+    Native,
+    /// A multiple of the size at the resolution of the dataset.
+    Factor(f64),
+    /// A size in pixels, keeping the aspect ratio for the missing dimension.
+    Size(Option<usize>, Option<usize>),
+}
+
+struct CoverageRequest {
+    coverage: String,
+    /// The ranges to trim the axes to.
+    subset_x: Option<(f64, f64)>,
+    subset_y: Option<(f64, f64)>,
+    subsetting_crs: Option<String>,
+    output_crs: Option<String>,
+    scale: Scale,
+}
+
+fn axis(label: &str) -> Result<Axis, Error> {
+    let label = label.trim().to_ascii_lowercase();
+    if X_AXES.contains(&label.as_str()) {
+        Ok(Axis::X)
+    } else if Y_AXES.contains(&label.as_str()) {
+        Ok(Axis::Y)
+    } else {
+        Err(Error::BadRequest(format!("unknown axis: {}", label)))
+    }
+}
+
+/// Splits an axis expression like `Lat(10,20)` into its label and its arguments.
+fn axis_expression(value: &str) -> Result<(Axis, Vec<&str>), Error> {
+    let invalid = || Error::BadRequest(format!("invalid axis expression: {}", value));
+    let (label, arguments) = value.trim().split_once('(').ok_or_else(invalid)?;
+    let arguments = arguments.strip_suffix(')').ok_or_else(invalid)?;
+    Ok((axis(label)?, arguments.split(',').map(str::trim).collect()))
+}
+
+/// Parses a `subset` like `x(100000,200000)`. Slicing to a single value isn't supported.
+fn parse_subset(value: &str) -> Result<(Axis, (f64, f64)), Error> {
+    let (axis, arguments) = axis_expression(value)?;
+    let bound = |bound: &str| {
+        bound
+            .trim_matches('"')
+            .parse::<f64>()
+            .ok()
+            .filter(|bound| bound.is_finite())
+            .ok_or_else(|| Error::BadRequest(format!("invalid subset: {}", value)))
+    };
+    match arguments[..] {
+        [low, high] => {
+            let (low, high) = (bound(low)?, bound(high)?);
+            if low >= high {
+                return Err(Error::BadRequest(format!(
+                    "the subset must be a non-empty range: {}",
+                    value
+                )));
+            }
+            Ok((axis, (low, high)))
+        }
+        [_] => Err(Error::BadRequest(format!(
+            "slicing isn't supported, only trimming: {}",
+            value
+        ))),
+        _ => Err(Error::BadRequest(format!("invalid subset: {}", value))),
+    }
+}
+
+/// Parses a `scaleSize` like `x(512),y(256)`.
+fn parse_scale_size(value: &str) -> Result<Scale, Error> {
+    let invalid = || Error::BadRequest(format!("invalid scaleSize: {}", value));
+    let (mut width, mut height) = (None, None);
+    for expression in value.split_inclusive(')') {
+        let (axis, arguments) = axis_expression(expression.trim_start_matches(','))?;
+        let size = match arguments[..] {
+            [size] => size.parse::<usize>().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        match axis {
+            Axis::X => width = Some(size),
+            Axis::Y => height = Some(size),
+        }
+    }
+    if width.is_none() && height.is_none() {
+        return Err(invalid());
+    }
+    Ok(Scale::Size(width, height))
+}
+
+impl CoverageRequest {
+    /// Reads the KVP parameters, whose names aren't case-sensitive, ignoring the unknown ones.
+    fn parse(query: &str) -> Result<Self, Error> {
+        let parameters: Vec<(String, String)> = serde_urlencoded::from_str(query)
+            .map_err(|e| Error::BadRequest(format!("invalid query: {}", e)))?;
+        let mut request = CoverageRequest {
+            coverage: String::new(),
+            subset_x: None,
+            subset_y: None,
+            subsetting_crs: None,
+            output_crs: None,
+            scale: Scale::Native,
+        };
+        let (mut service, mut operation, mut coverage) = (None, None, None);
+        for (name, value) in parameters {
+            match name.to_ascii_lowercase().as_str() {
+                "service" => service = Some(value),
+                "request" => operation = Some(value),
+                "version" if !value.starts_with("2.0") => {
+                    return Err(Error::BadRequest(format!(
+                        "unsupported WCS version: {}",
+                        value
+                    )))
+                }
+                "coverageid" => coverage = Some(value),
+                "subset" => match parse_subset(&value)? {
+                    (Axis::X, range) => request.subset_x = Some(range),
+                    (Axis::Y, range) => request.subset_y = Some(range),
+                },
+                "subsettingcrs" => request.subsetting_crs = Some(value),
+                "outputcrs" => request.output_crs = Some(value),
+                "format" if value != FORMAT => {
+                    return Err(Error::BadRequest(format!(
+                        "unsupported format {}, only {} is",
+                        value, FORMAT
+                    )))
+                }
+                "scalefactor" => {
+                    let factor = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|factor| *factor > 0.0 && factor.is_finite())
+                        .ok_or_else(|| {
+                            Error::BadRequest(format!("invalid scaleFactor: {}", value))
+                        })?;
+                    request.scale = Scale::Factor(factor);
+                }
+                "scalesize" => request.scale = parse_scale_size(&value)?,
+                _ => {}
+            }
+        }
+        if !service.is_some_and(|service| service.eq_ignore_ascii_case("WCS")) {
+            return Err(Error::BadRequest("service must be WCS".to_string()));
+        }
+        match operation {
+            Some(operation) if operation.eq_ignore_ascii_case("GetCoverage") => {}
+            Some(operation) => {
+                return Err(Error::BadRequest(format!(
+                    "unsupported request {}, only GetCoverage is",
+                    operation
+                )))
+            }
+            None => return Err(Error::BadRequest("missing request".to_string())),
+        }
+        request.coverage =
+            coverage.ok_or_else(|| Error::BadRequest("missing coverageId".to_string()))?;
+        Ok(request)
+    }
+
+    /// Trims an extent to the subsets.
+    fn trim(&self, mut extent: Extent) -> Extent {
+        if let Some((low, high)) = self.subset_x {
+            extent.xmin = extent.xmin.max(low);
+            extent.xmax = extent.xmax.min(high);
+        }
+        if let Some((low, high)) = self.subset_y {
+            extent.ymin = extent.ymin.max(low);
+            extent.ymax = extent.ymax.min(high);
+        }
+        extent
+    }
+}
+
+/// Finds the extent to read, in the CRS of the dataset, which is the output one.
+fn coverage_extent(
+    dataset: &Dataset,
+    info: &RasterInfo,
+    request: &CoverageRequest,
+) -> Result<Extent, Error> {
+    let dataset_srs = dataset::spatial_ref(dataset)?;
+    let subsetting_srs = match &request.subsetting_crs {
+        Some(crs) => Some(crs::parse_srs(crs)?),
+        None => None,
+    };
+    let extent = match subsetting_srs.filter(|srs| *srs != dataset_srs) {
+        Some(srs) => {
+            let to_subsetting = crs::transform(&dataset_srs, &srs)?;
+            let extent = dataset::reproject_extent(&info.extent, &to_subsetting)?;
+            let from_subsetting = crs::transform(&srs, &dataset_srs)?;
+            let extent = dataset::reproject_extent(&request.trim(extent), &from_subsetting)?;
+            // the reprojected extent can be larger than the dataset
+            Extent {
+                xmin: extent.xmin.max(info.extent.xmin),
+                ymin: extent.ymin.max(info.extent.ymin),
+                xmax: extent.xmax.min(info.extent.xmax),
+                ymax: extent.ymax.min(info.extent.ymax),
+            }
+        }
+        None => request.trim(info.extent.clone()),
+    };
+    if !(extent.xmin < extent.xmax && extent.ymin < extent.ymax) {
+        return Err(Error::BadRequest(
+            "the subset doesn't intersect the coverage".to_string(),
+        ));
+    }
+    Ok(extent)
+}
+
+/// Writes the values of the bands into a GeoTIFF in memory, with the type of the dataset.
+fn encode_geotiff(
+    dataset: &Dataset,
+    values: &[f64],
+    window: &render::Window,
+    (width, height): (usize, usize),
+    geo_transform: &[f64; 6],
+) -> Result<Vec<u8>, Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let count = dataset.raster_count();
+    let band_type = dataset.rasterband(1)?.band_type();
+    let driver = Driver::get("MEM")?;
+    let c_name = CString::new("")?;
+    let c_dataset = unsafe {
+        gdal_sys::GDALCreate(
+            driver.c_driver(),
+            c_name.as_ptr(),
+            width as _,
+            height as _,
+            count as _,
+            band_type,
+            ptr::null_mut(),
+        )
+    };
+    if c_dataset.is_null() {
+        return Err(Error::last_cpl_error(gdal_sys::CPLErr::CE_Failure));
+    }
+    let mut out = unsafe { Dataset::from_c_dataset(c_dataset) };
+    out.set_geo_transform(geo_transform)?;
+    out.set_spatial_ref(&dataset::spatial_ref(dataset)?)?;
+    let (x, y) = window.output_position;
+    let (window_width, window_height) = window.output_size;
+    for band in 1..=count {
+        let no_data = dataset.rasterband(band)?.no_data_value();
+        let mut target = out.rasterband(band)?;
+        if let Some(no_data) = no_data {
+            target.set_no_data_value(no_data)?;
+        }
+        // the pixels outside the dataset are nodata, or zero without it
+        let mut data = vec![no_data.unwrap_or(0.0); width * height];
+        for row in 0..window_height {
+            for col in 0..window_width {
+                let pixel = (y as usize + row) * width + x as usize + col;
+                data[pixel] =
+                    values[(row * window_width + col) * count as usize + band as usize - 1];
+            }
+        }
+        target.write((0, 0), (width, height), &Buffer::new((width, height), data))?;
+    }
+
+    let file_name = format!(
+        "/vsimem/coverage_{}.tif",
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let options = [
+        RasterCreationOption {
+            key: "COMPRESS",
+            value: "DEFLATE",
+        },
+        RasterCreationOption {
+            key: "TILED",
+            value: "YES",
+        },
+    ];
+    drop(out.create_copy(&Driver::get("GTiff")?, &file_name, &options)?);
+    Ok(vsi::get_vsi_mem_file_bytes_owned(&file_name)?)
+}
+
+fn get_coverage(
+    path: &Path,
+    request: &CoverageRequest,
+    limits: &OutputLimits,
+) -> Result<Vec<u8>, Error> {
+    let dataset = &*dataset::open_in_crs(path, request.output_crs.as_deref())?;
+    let info = RasterInfo::read(dataset)?;
+    let extent = coverage_extent(dataset, &info, request)?;
+    let native = (
+        (extent.xmax - extent.xmin) / info.geo_transform[1],
+        (extent.ymax - extent.ymin) / -info.geo_transform[5],
+    );
+    let pixels = |size: f64| Some((size.round() as usize).max(1));
+    let (width, height) = match request.scale {
+        Scale::Native => (pixels(native.0), pixels(native.1)),
+        Scale::Factor(factor) => (pixels(native.0 * factor), pixels(native.1 * factor)),
+        Scale::Size(width, height) => (width, height),
+    };
+    let size = preview::output_size(&extent, width, height, limits)?;
+    let bands = (1..=dataset.raster_count()).collect::<Vec<_>>();
+    preview::check_read_cost(dataset, &info, &extent, size, bands.len(), limits)?;
+
+    let window = render::window(&info, &extent, size.0, size.1)?;
+    let values = render::read_bands(dataset, &bands, &window)?;
+    let geo_transform = [
+        extent.xmin,
+        (extent.xmax - extent.xmin) / size.0 as f64,
+        0.0,
+        extent.ymax,
+        0.0,
+        (extent.ymin - extent.ymax) / size.1 as f64,
+    ];
+    let geotiff = encode_geotiff(dataset, &values, &window, size, &geo_transform);
+    render::VALUES.put(values);
+    geotiff
+}
+
+pub async fn wcs(
+    RawQuery(query): RawQuery,
+    config: Extension<Config>,
+    registry: Extension<Arc<Registry>>,
+) -> Result<Response, Error> {
+    let request = CoverageRequest::parse(query.as_deref().unwrap_or_default())?;
+    let entry = registry.get(&request.coverage)?;
+    if !matches!(entry.kind, Kind::Raster | Kind::GeoPackage) {
+        return Err(Error::BadRequest(format!(
+            "{} is not a raster dataset",
+            request.coverage
+        )));
+    }
+    let limits = config.output_limits.clone();
+    let coverage = request.coverage.clone();
+    let geotiff = workers::run(move || get_coverage(&entry.path, &request, &limits)).await?;
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, FORMAT)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.tif\"", coverage),
+        )
+        .body(body::boxed(Full::from(geotiff)))
+        .unwrap();
+    Ok(response)
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::archive::{self, RawTile};
+use crate::cache;
+use crate::config::{Config, RemoteConfig};
+use crate::error::Error;
+use crate::remote;
+use crate::tile_grid::{self, Extent};
+
+fn default_version() -> String {
+    "1.3.0".to_string()
+}
+
+fn default_crs() -> String {
+    "EPSG:3857".to_string()
+}
+
+fn default_format() -> String {
+    "image/png".to_string()
+}
+
+/// A remote WMS layer, requested tile by tile and cached like the rendered tiles.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WmsSource {
+    /// The service endpoint, without the `GetMap` parameters.
+    pub url: String,
+    /// Comma-separated layer names.
+    pub layers: String,
+    #[serde(default)]
+    pub styles: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// The CRS of the tile grid, which the map is requested in.
+    #[serde(default = "default_crs")]
+    pub crs: String,
+    #[serde(default = "default_format")]
+    pub format: String,
+}
+
+impl WmsSource {
+    /// Checks that the service can be queried.
+    pub fn validate(&self, remote: &RemoteConfig) -> Result<(), Error> {
+        remote::check_allowed(&self.url, remote)?;
+        remote::read_url(&self.request_url("GetCapabilities", &[]), remote)?;
+        Ok(())
+    }
+
+    fn request_url(&self, request: &str, params: &[(&str, String)]) -> String {
+        let mut query = format!(
+            "SERVICE=WMS&REQUEST={}&VERSION={}",
+            request,
+            remote::encode_component(&self.version)
+        );
+        for (key, value) in params {
+            query.push_str(&format!("&{}={}", key, remote::encode_component(value)));
+        }
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.url, separator, query)
+    }
+
+    fn get_map_url(&self, extent: &Extent, width: usize, height: usize) -> String {
+        let (crs_key, axes_swapped) = if self.version == "1.3.0" {
+            // WMS 1.3.0 uses the axis order of the CRS, which is latitude first for WGS84
+            ("CRS", self.crs.eq_ignore_ascii_case("EPSG:4326"))
+        } else {
+            ("SRS", false)
+        };
+        let bbox = if axes_swapped {
+            [extent.ymin, extent.xmin, extent.ymax, extent.xmax]
+        } else {
+            [extent.xmin, extent.ymin, extent.xmax, extent.ymax]
+        };
+        let bbox = bbox
+            .iter()
+            .map(f64::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        self.request_url(
+            "GetMap",
+            &[
+                ("LAYERS", self.layers.clone()),
+                ("STYLES", self.styles.clone()),
+                (crs_key, self.crs.clone()),
+                ("BBOX", bbox),
+                ("WIDTH", width.to_string()),
+                ("HEIGHT", height.to_string()),
+                ("FORMAT", self.format.clone()),
+                ("TRANSPARENT", "TRUE".to_string()),
+            ],
+        )
+    }
+
+    /// Returns a tile from the cache, requesting it from the service if needed.
+    ///
+    /// `y` is the row in the tile grid, flipped according to `reverse_y`.
+    pub fn tile(
+        &self,
+        file: &str,
+        (z, x, y): (u8, u32, u32),
+        config: &Config,
+    ) -> Result<RawTile, Error> {
+        tile_grid::check_tile(z, x, y)?;
+        let file_name = format!(
+            "{}/{}{}_{}_{}.wms",
+            cache::dir().display(),
+            cache::entry_prefix(file),
+            z,
+            x,
+            y
+        );
+        let data = if Path::new(&file_name).exists() {
+            std::fs::read(&file_name)?
+        } else {
+            let y = if config.reverse_y {
+                (1 << z) - 1 - y
+            } else {
+                y
+            };
+            let extent = config.tile_grid.tile_extent(x, y, z);
+            let url = self.get_map_url(&extent, config.tile_width, config.tile_height);
+            let data = remote::read_url(&url, &config.remote)?;
+            if archive::sniff_format(&data).is_empty() {
+                // errors are reported as XML service exceptions
+                let message = String::from_utf8_lossy(&data[..data.len().min(512)]).into_owned();
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    message,
+                )));
+            }
+            std::fs::write(&file_name, &data)?;
+            data
+        };
+        Ok(RawTile {
+            format: archive::sniff_format(&data).to_string(),
+            data: data.into(),
+            encoding: None,
+        })
+    }
+}
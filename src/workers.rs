@@ -0,0 +1,221 @@
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_int};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{OnceLock, RwLock};
+use std::time::Instant;
+
+use gdal::config;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::task;
+
+use crate::config::WorkerConfig;
+use crate::error::Error;
+use crate::slow;
+
+struct Workers {
+    config: RwLock<WorkerConfig>,
+    /// Limits the GDAL jobs running at once, with the others waiting for a permit.
+    permits: Semaphore,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+static WORKERS: OnceLock<Workers> = OnceLock::new();
+
+impl Workers {
+    fn new(config: WorkerConfig) -> Self {
+        Self {
+            permits: Semaphore::new(config.threads),
+            config: RwLock::new(config),
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Sets the number of GDAL jobs that can run at once and wait for a thread. Only the first call has
+/// an effect.
+pub fn configure(config: &WorkerConfig) {
+    let _ = WORKERS.set(Workers::new(config.clone()));
+}
+
+fn workers() -> &'static Workers {
+    WORKERS.get_or_init(|| Workers::new(WorkerConfig::default()))
+}
+
+thread_local! {
+    /// When the job running on a worker thread is abandoned.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Returns the deadline of the job running on the current thread, to pass it to the threads it
+/// starts.
+pub fn deadline() -> Option<Instant> {
+    DEADLINE.with(Cell::get)
+}
+
+pub fn deadline_passed() -> bool {
+    passed(deadline())
+}
+
+fn passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Runs `f` with a deadline on the current thread.
+///
+/// The HTTP requests of remote datasets get a timeout of the time left, so that GDAL gives up on
+/// unresponsive storage too, instead of keeping the thread busy after the job was abandoned.
+pub fn with_deadline<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+    let previous = DEADLINE.with(|current| current.replace(deadline));
+    if let Some(deadline) = deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        // whole seconds, since older GDAL versions don't parse fractions
+        let timeout = left.as_secs().max(1).to_string();
+        let _ = config::set_thread_local_config_option("GDAL_HTTP_TIMEOUT", &timeout);
+    }
+    let result = f();
+    if deadline.is_some() {
+        let _ = config::clear_thread_local_config_option("GDAL_HTTP_TIMEOUT");
+    }
+    DEADLINE.with(|current| current.set(previous));
+    result
+}
+
+/// A GDAL progress callback interrupting the read it's passed to once the deadline passes.
+pub unsafe extern "C" fn check_deadline(
+    _complete: f64,
+    _message: *const c_char,
+    _data: *mut c_void,
+) -> c_int {
+    c_int::from(!deadline_passed())
+}
+
+/// Counts a job as queued until it starts running or its request is dropped.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs blocking work, like reading or rendering a dataset, on the blocking thread pool.
+///
+/// Unlike `block_in_place`, this doesn't take over one of the runtime workers, so requests that
+/// only do async I/O aren't delayed by rendering. At most `WorkerConfig::threads` jobs run at
+/// once, and the others wait in turn, unless `WorkerConfig::queue_depth` of them are already
+/// waiting, in which case the job is rejected with `Error::Overloaded`.
+///
+/// With a `WorkerConfig::deadline`, the jobs running for longer fail with
+/// `Error::DeadlineExceeded`. The request doesn't wait for them, but their thread stays taken until
+/// GDAL gives up on the read, so it's only then that another job can start.
+pub async fn run<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let workers = workers();
+    let (queue_depth, retry_after, limit) = {
+        let config = workers.config.read().unwrap();
+        (config.queue_depth, config.retry_after, config.deadline)
+    };
+    if workers.queued.fetch_add(1, Ordering::Relaxed) >= queue_depth {
+        workers.queued.fetch_sub(1, Ordering::Relaxed);
+        workers.rejected.fetch_add(1, Ordering::Relaxed);
+        return Err(Error::Overloaded(retry_after));
+    }
+    let queued = Queued(&workers.queued);
+    let permit = workers
+        .permits
+        .acquire()
+        .await
+        .expect("semaphore is never closed");
+    drop(queued);
+    // keep the job in the span of its request
+    let span = tracing::Span::current();
+    let trace = slow::current();
+    let deadline = limit.map(|limit| Instant::now() + limit);
+    let job = task::spawn_blocking(move || {
+        // held until the job ends, even if the request gave up on it
+        let _permit = permit;
+        let result = span.in_scope(|| slow::scope(trace, || with_deadline(deadline, f)));
+        match result {
+            Err(_) if passed(deadline) => Err(Error::DeadlineExceeded),
+            result => result,
+        }
+    });
+    let result = match limit {
+        Some(limit) => match tokio::time::timeout(limit, job).await {
+            Ok(result) => result?,
+            Err(_) => Err(Error::DeadlineExceeded),
+        },
+        None => job.await?,
+    };
+    if let Err(Error::DeadlineExceeded) = result {
+        workers.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Takes one of the worker threads for a running job that splits its work, like reading the bands
+/// of a dataset at once, or returns `None` if they are all busy.
+///
+/// The job's own permit isn't enough for more than one thread, so this keeps the split jobs within
+/// `WorkerConfig::threads` too.
+pub fn try_borrow() -> Option<SemaphorePermit<'static>> {
+    workers().permits.try_acquire().ok()
+}
+
+/// A snapshot of the state of the worker threads.
+pub struct WorkerStats {
+    pub threads: usize,
+    pub running: usize,
+    pub queued: usize,
+    pub queue_depth: usize,
+    pub rejected: u64,
+    /// Jobs abandoned after running past the deadline.
+    pub timed_out: u64,
+}
+
+pub fn stats() -> WorkerStats {
+    let workers = workers();
+    let config = workers.config.read().unwrap();
+    WorkerStats {
+        threads: config.threads,
+        // permits being taken away by `set_threads` count as running jobs for a while
+        running: config
+            .threads
+            .saturating_sub(workers.permits.available_permits()),
+        queued: workers.queued.load(Ordering::Relaxed),
+        queue_depth: config.queue_depth,
+        rejected: workers.rejected.load(Ordering::Relaxed),
+        timed_out: workers.timed_out.load(Ordering::Relaxed),
+    }
+}
+
+/// Changes the number of GDAL jobs that can run at once.
+///
+/// When lowering it, this waits until enough of the running jobs finish.
+pub async fn set_threads(threads: usize) {
+    let workers = workers();
+    let old = std::mem::replace(&mut workers.config.write().unwrap().threads, threads);
+    if threads > old {
+        workers.permits.add_permits(threads - old);
+    } else if threads < old {
+        workers
+            .permits
+            .acquire_many((old - threads) as u32)
+            .await
+            .expect("semaphore is never closed")
+            .forget();
+    }
+}
+
+/// Changes the number of GDAL jobs that can wait for a thread.
+pub fn set_queue_depth(queue_depth: usize) {
+    workers().config.write().unwrap().queue_depth = queue_depth;
+}
@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+
+/// Checks whether a path, possibly a remote one, points to a Zarr store.
+pub fn is_zarr(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zarr"))
+}
+
+/// Builds the GDAL path of a 2D slice of an array in a Zarr store, like
+/// `ZARR:"data.zarr":/temperature:0:3` for the first time step and fourth depth level.
+///
+/// The indices select the non-spatial dimensions, which come before the Y and X ones.
+pub fn array_path(path: &Path, variable: &str, indices: &[u64]) -> PathBuf {
+    let variable = if variable.starts_with('/') {
+        variable.to_string()
+    } else {
+        format!("/{}", variable)
+    };
+    let mut array_path = format!("ZARR:\"{}\":{}", path.display(), variable);
+    for index in indices {
+        array_path.push_str(&format!(":{}", index));
+    }
+    PathBuf::from(array_path)
+}
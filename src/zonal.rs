@@ -0,0 +1,157 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::{self, Extension};
+use axum::Json;
+use gdal::raster::rasterize;
+use gdal::vector::Geometry as OgrGeometry;
+use gdal::Driver;
+use serde::{Deserialize, Serialize};
+
+use crate::crs;
+use crate::dataset;
+use crate::error::Error;
+use crate::geojson::{self, Geometry};
+use crate::point;
+use crate::registry::Registry;
+use crate::workers;
+
+const MAX_PIXELS: usize = 4096 * 4096;
+
+#[derive(Deserialize)]
+pub struct ZonalQuery {
+    crs: Option<String>,
+    bands: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ZonalStatistics {
+    bands: Vec<BandStatistics>,
+}
+
+#[derive(Serialize)]
+pub struct BandStatistics {
+    band: isize,
+    count: usize,
+    min: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+    sum: f64,
+}
+
+fn compute_statistics(
+    path: &Path,
+    geometry: Geometry,
+    query: &ZonalQuery,
+) -> Result<ZonalStatistics, Error> {
+    let mut polygons = geometry.into_polygons()?;
+
+    let dataset = dataset::open(path)?;
+    let spatial_ref = dataset::spatial_ref(&dataset)?;
+    let source_srs = match &query.crs {
+        Some(crs) => crs::parse_srs(crs)?,
+        None => crs::wgs84()?,
+    };
+    let transform = crs::transform(&source_srs, &spatial_ref)?;
+    for ring in polygons.iter_mut().flatten() {
+        let mut xs = ring.iter().map(|p| p.0).collect::<Vec<_>>();
+        let mut ys = ring.iter().map(|p| p.1).collect::<Vec<_>>();
+        let mut zs = vec![0.0; ring.len()];
+        transform.transform_coords(&mut xs, &mut ys, &mut zs)?;
+        *ring = xs.into_iter().zip(ys).collect();
+    }
+
+    let geo_transform = dataset::geo_transform(&dataset)?;
+    let raster_size = dataset.raster_size();
+    let (mut col_min, mut row_min) = (f64::INFINITY, f64::INFINITY);
+    let (mut col_max, mut row_max) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &(x, y) in polygons.iter().flatten().flatten() {
+        let (col, row) = dataset::world_to_pixel(&geo_transform, x, y);
+        col_min = col_min.min(col);
+        row_min = row_min.min(row);
+        col_max = col_max.max(col);
+        row_max = row_max.max(row);
+    }
+    let col_min = col_min.floor().max(0.0) as usize;
+    let row_min = row_min.floor().max(0.0) as usize;
+    let col_max = (col_max.ceil() as usize).min(raster_size.0);
+    let row_max = (row_max.ceil() as usize).min(raster_size.1);
+    if col_min >= col_max || row_min >= row_max {
+        return Err(Error::OutsideBounds);
+    }
+    let window_size = (col_max - col_min, row_max - row_min);
+    if window_size.0 * window_size.1 > MAX_PIXELS {
+        return Err(Error::BadRequest(
+            "polygon covers too many pixels".to_string(),
+        ));
+    }
+    let window = (col_min as isize, row_min as isize);
+
+    let geometry = OgrGeometry::from_wkt(&geojson::multi_polygon_wkt(&polygons))?;
+    let mut mask = Driver::get("MEM")?.create_with_band_type::<u8>(
+        "",
+        window_size.0 as isize,
+        window_size.1 as isize,
+        1,
+    )?;
+    let [x0, a, b, y0, d, e] = geo_transform;
+    mask.set_geo_transform(&[
+        x0 + a * col_min as f64 + b * row_min as f64,
+        a,
+        b,
+        y0 + d * col_min as f64 + e * row_min as f64,
+        d,
+        e,
+    ])?;
+    rasterize(&mut mask, &[1], &[geometry], &[1.0], None)?;
+    let mask = mask
+        .rasterband(1)?
+        .read_as::<u8>((0, 0), window_size, window_size, None)?;
+
+    let bands = match &query.bands {
+        Some(bands) => point::parse_bands(bands, dataset.raster_count())?,
+        None => (1..=dataset.raster_count()).collect(),
+    };
+    let mut statistics = Vec::with_capacity(bands.len());
+    for band in bands {
+        let rasterband = dataset.rasterband(band)?;
+        let no_data = rasterband.no_data_value();
+        let scale = rasterband.scale().unwrap_or(1.0);
+        let offset = rasterband.offset().unwrap_or(0.0);
+        let buf = rasterband.read_as::<f64>(window, window_size, window_size, None)?;
+
+        let mut count = 0;
+        let (mut min, mut max, mut sum) = (f64::INFINITY, f64::NEG_INFINITY, 0.0);
+        for (&value, &m) in buf.data.iter().zip(mask.data.iter()) {
+            if m == 0 || Some(value) == no_data || value.is_nan() {
+                continue;
+            }
+            let value = value * scale + offset;
+            count += 1;
+            min = min.min(value);
+            max = max.max(value);
+            sum += value;
+        }
+        statistics.push(BandStatistics {
+            band,
+            count,
+            min: Some(min).filter(|_| count > 0),
+            max: Some(max).filter(|_| count > 0),
+            mean: Some(sum / count as f64).filter(|_| count > 0),
+            sum,
+        });
+    }
+
+    Ok(ZonalStatistics { bands: statistics })
+}
+
+pub async fn zonal(
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<ZonalQuery>,
+    registry: Extension<Arc<Registry>>,
+    Json(geometry): Json<Geometry>,
+) -> Result<Json<ZonalStatistics>, Error> {
+    let path = registry.resolve(&file)?;
+    let statistics = workers::run(move || compute_statistics(&path, geometry, &query)).await?;
+    Ok(Json(statistics))
+}